@@ -0,0 +1,19 @@
+-- The assets tables are usually provisioned by the Biletado compose setup.
+-- Create them here as well so the service can bootstrap an empty database.
+CREATE TABLE IF NOT EXISTS buildings (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    address TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS storeys (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    building_id UUID NOT NULL REFERENCES buildings (id)
+);
+
+CREATE TABLE IF NOT EXISTS rooms (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    storey_id UUID NOT NULL REFERENCES storeys (id)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE rooms DROP COLUMN deleted_at;
+ALTER TABLE storeys DROP COLUMN deleted_at;
+ALTER TABLE buildings DROP COLUMN deleted_at;
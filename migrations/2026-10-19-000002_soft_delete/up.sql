@@ -0,0 +1,4 @@
+-- Deleted assets are kept in the trash until the purge job removes them.
+ALTER TABLE buildings ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE storeys ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE rooms ADD COLUMN deleted_at TIMESTAMPTZ;
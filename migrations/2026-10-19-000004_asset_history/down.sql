@@ -0,0 +1,5 @@
+DROP TRIGGER rooms_history ON rooms;
+DROP TRIGGER storeys_history ON storeys;
+DROP TRIGGER buildings_history ON buildings;
+DROP FUNCTION record_asset_history();
+DROP TABLE asset_history;
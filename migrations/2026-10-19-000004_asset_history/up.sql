@@ -0,0 +1,49 @@
+-- Every version of a building, storey, or room, valid from `valid_from` until (excluding) `valid_to`.
+-- An open version (`valid_to` IS NULL) is the current state of an asset that is not in the trash.
+CREATE TABLE asset_history (
+    id BIGSERIAL PRIMARY KEY,
+    entity_type TEXT NOT NULL,
+    entity_id UUID NOT NULL,
+    valid_from TIMESTAMPTZ NOT NULL,
+    valid_to TIMESTAMPTZ,
+    data JSONB NOT NULL
+);
+
+CREATE INDEX asset_history_entity_idx ON asset_history (entity_type, entity_id, valid_from);
+CREATE INDEX asset_history_validity_idx ON asset_history (entity_type, valid_from, valid_to);
+
+-- the versions are kept by triggers, so changes from direct SQL end up in the history as well
+CREATE FUNCTION record_asset_history() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND OLD IS NOT DISTINCT FROM NEW THEN
+        RETURN NULL;
+    END IF;
+
+    IF TG_OP <> 'INSERT' THEN
+        UPDATE asset_history SET valid_to = now()
+            WHERE entity_type = TG_ARGV[0] AND entity_id = OLD.id AND valid_to IS NULL;
+    END IF;
+
+    IF TG_OP <> 'DELETE' AND NEW.deleted_at IS NULL THEN
+        INSERT INTO asset_history (entity_type, entity_id, valid_from, data)
+            VALUES (TG_ARGV[0], NEW.id, now(), to_jsonb(NEW));
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER buildings_history AFTER INSERT OR UPDATE OR DELETE ON buildings
+    FOR EACH ROW EXECUTE FUNCTION record_asset_history('building');
+CREATE TRIGGER storeys_history AFTER INSERT OR UPDATE OR DELETE ON storeys
+    FOR EACH ROW EXECUTE FUNCTION record_asset_history('storey');
+CREATE TRIGGER rooms_history AFTER INSERT OR UPDATE OR DELETE ON rooms
+    FOR EACH ROW EXECUTE FUNCTION record_asset_history('room');
+
+-- existing assets start their history now
+INSERT INTO asset_history (entity_type, entity_id, valid_from, data)
+    SELECT 'building', id, now(), to_jsonb(buildings) FROM buildings WHERE deleted_at IS NULL;
+INSERT INTO asset_history (entity_type, entity_id, valid_from, data)
+    SELECT 'storey', id, now(), to_jsonb(storeys) FROM storeys WHERE deleted_at IS NULL;
+INSERT INTO asset_history (entity_type, entity_id, valid_from, data)
+    SELECT 'room', id, now(), to_jsonb(rooms) FROM rooms WHERE deleted_at IS NULL;
@@ -0,0 +1,7 @@
+ALTER TABLE rooms
+    DROP COLUMN hearing_loop,
+    DROP COLUMN wheelchair_accessible,
+    DROP COLUMN area,
+    DROP COLUMN room_type,
+    DROP COLUMN standing_capacity,
+    DROP COLUMN seated_capacity;
@@ -0,0 +1,7 @@
+ALTER TABLE rooms
+    ADD COLUMN seated_capacity INTEGER CHECK (seated_capacity >= 0),
+    ADD COLUMN standing_capacity INTEGER CHECK (standing_capacity >= 0),
+    ADD COLUMN room_type TEXT CHECK (room_type IN ('office', 'lecture_hall', 'seminar', 'meeting', 'lab', 'workshop', 'storage', 'other')),
+    ADD COLUMN area DOUBLE PRECISION CHECK (area > 0),
+    ADD COLUMN wheelchair_accessible BOOLEAN,
+    ADD COLUMN hearing_loop BOOLEAN;
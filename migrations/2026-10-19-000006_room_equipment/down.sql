@@ -0,0 +1,2 @@
+DROP TABLE room_equipment;
+DROP TABLE equipment;
@@ -0,0 +1,25 @@
+-- catalogue of equipment types, `key` is the short identifier used in filters (e.g. `projector`)
+CREATE TABLE equipment (
+    id UUID PRIMARY KEY,
+    key TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL
+);
+
+-- equipment present in a room, either as a counted quantity or as individual items with a serial number
+CREATE TABLE room_equipment (
+    id UUID PRIMARY KEY,
+    room_id UUID NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    equipment_id UUID NOT NULL REFERENCES equipment (id),
+    quantity INTEGER NOT NULL DEFAULT 1 CHECK (quantity > 0),
+    serial_number TEXT
+);
+
+CREATE INDEX room_equipment_room_idx ON room_equipment (room_id);
+
+INSERT INTO equipment (id, key, name) VALUES
+    (md5('equipment:projector')::uuid, 'projector', 'Projector'),
+    (md5('equipment:whiteboard')::uuid, 'whiteboard', 'Whiteboard'),
+    (md5('equipment:vc')::uuid, 'vc', 'Video conference kit'),
+    (md5('equipment:desk')::uuid, 'desk', 'Desk'),
+    (md5('equipment:pc')::uuid, 'pc', 'Computer'),
+    (md5('equipment:microphone')::uuid, 'microphone', 'Microphone');
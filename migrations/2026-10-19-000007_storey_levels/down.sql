@@ -0,0 +1,5 @@
+DROP INDEX storeys_building_level_idx;
+ALTER TABLE storeys
+    DROP COLUMN display_order,
+    DROP COLUMN elevation,
+    DROP COLUMN level;
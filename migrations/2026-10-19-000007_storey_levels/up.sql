@@ -0,0 +1,10 @@
+-- `level` is the floor number (0 = ground floor, negative for basements),
+-- `elevation` the height of the floor in metres, `display_order` breaks ties for storeys without a level
+ALTER TABLE storeys
+    ADD COLUMN level INTEGER,
+    ADD COLUMN elevation DOUBLE PRECISION,
+    ADD COLUMN display_order INTEGER;
+
+-- only one storey per level and building, storeys in the trash don't count
+CREATE UNIQUE INDEX storeys_building_level_idx ON storeys (building_id, level)
+    WHERE deleted_at IS NULL AND level IS NOT NULL;
@@ -0,0 +1,9 @@
+ALTER TABLE buildings
+    DROP CONSTRAINT buildings_position_check,
+    DROP COLUMN longitude,
+    DROP COLUMN latitude,
+    DROP COLUMN country,
+    DROP COLUMN city,
+    DROP COLUMN postal_code,
+    DROP COLUMN house_number,
+    DROP COLUMN street;
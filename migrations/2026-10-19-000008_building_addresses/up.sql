@@ -0,0 +1,24 @@
+-- structured address next to the legacy free-text `address`, plus the WGS84 position of the building
+ALTER TABLE buildings
+    ADD COLUMN street TEXT,
+    ADD COLUMN house_number TEXT,
+    ADD COLUMN postal_code TEXT,
+    ADD COLUMN city TEXT,
+    ADD COLUMN country TEXT,
+    ADD COLUMN latitude DOUBLE PRECISION CHECK (latitude BETWEEN -90 AND 90),
+    ADD COLUMN longitude DOUBLE PRECISION CHECK (longitude BETWEEN -180 AND 180),
+    ADD CONSTRAINT buildings_position_check CHECK ((latitude IS NULL) = (longitude IS NULL));
+
+-- distance queries use PostGIS if it is installed, otherwise earthdistance.
+-- Installing extensions needs privileges the service user might not have,
+-- the service falls back to computing distances itself in that case.
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'postgis') THEN
+        CREATE EXTENSION IF NOT EXISTS cube;
+        CREATE EXTENSION IF NOT EXISTS earthdistance;
+    END IF;
+EXCEPTION WHEN OTHERS THEN
+    RAISE NOTICE 'could not install earthdistance: %', SQLERRM;
+END;
+$$;
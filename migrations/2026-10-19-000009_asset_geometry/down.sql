@@ -0,0 +1,8 @@
+ALTER TABLE rooms
+    DROP CONSTRAINT rooms_position_check,
+    DROP COLUMN footprint,
+    DROP COLUMN longitude,
+    DROP COLUMN latitude;
+
+ALTER TABLE buildings
+    DROP COLUMN footprint;
@@ -0,0 +1,10 @@
+-- optional geometry for GIS tools: footprints are GeoJSON Polygon or MultiPolygon geometries in WGS84,
+-- rooms get a position like buildings
+ALTER TABLE buildings
+    ADD COLUMN footprint JSONB;
+
+ALTER TABLE rooms
+    ADD COLUMN latitude DOUBLE PRECISION CHECK (latitude BETWEEN -90 AND 90),
+    ADD COLUMN longitude DOUBLE PRECISION CHECK (longitude BETWEEN -180 AND 180),
+    ADD COLUMN footprint JSONB,
+    ADD CONSTRAINT rooms_position_check CHECK ((latitude IS NULL) = (longitude IS NULL));
@@ -0,0 +1,4 @@
+ALTER TABLE rooms
+    DROP COLUMN plan_polygon;
+
+DROP TABLE floor_plans;
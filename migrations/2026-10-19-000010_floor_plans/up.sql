@@ -0,0 +1,16 @@
+-- one floor plan per storey, the image is either stored in `data` or in a file at `path`
+-- below the configured floor plan directory
+CREATE TABLE floor_plans (
+    storey_id UUID PRIMARY KEY REFERENCES storeys (id) ON DELETE CASCADE,
+    content_type TEXT NOT NULL CHECK (content_type IN ('image/svg+xml', 'image/png')),
+    width DOUBLE PRECISION NOT NULL CHECK (width > 0),
+    height DOUBLE PRECISION NOT NULL CHECK (height > 0),
+    data BYTEA,
+    path TEXT,
+    uploaded_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK ((data IS NULL) <> (path IS NULL))
+);
+
+-- outline of a room on the floor plan of its storey, a JSON array of [x, y] points
+ALTER TABLE rooms
+    ADD COLUMN plan_polygon JSONB;
@@ -0,0 +1,9 @@
+-- events of asset changes, written in the same transaction as the change and removed once the relay has published them
+-- `seq` keeps the order in which they were written
+CREATE TABLE outbox (
+    seq BIGSERIAL PRIMARY KEY,
+    id UUID NOT NULL UNIQUE,
+    event_type TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,5 @@
+DROP TRIGGER rooms_changes ON rooms;
+DROP TRIGGER storeys_changes ON storeys;
+DROP TRIGGER buildings_changes ON buildings;
+DROP FUNCTION record_asset_change();
+DROP TABLE asset_changes;
@@ -0,0 +1,76 @@
+-- Changes of buildings, storeys and rooms for the `GET /assets/events` stream, in the order they were made.
+-- `building_ids` are the buildings the asset belonged to before and after the change, for filtering by building.
+-- Clients resume the stream after the last `id` they have seen.
+CREATE TABLE asset_changes (
+    id BIGSERIAL PRIMARY KEY,
+    entity_type TEXT NOT NULL,
+    entity_id UUID NOT NULL,
+    action TEXT NOT NULL CHECK (action IN ('created', 'updated', 'deleted')),
+    building_ids UUID[] NOT NULL,
+    data JSONB NOT NULL,
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX asset_changes_occurred_at_idx ON asset_changes (occurred_at);
+
+-- like the history, changes are recorded by triggers, so other instances and direct SQL are seen as well;
+-- every change is announced on the `asset_changes` channel with its ID once the transaction commits
+CREATE FUNCTION record_asset_change() RETURNS trigger AS $$
+DECLARE
+    old_row JSONB;
+    new_row JSONB;
+    change TEXT;
+    buildings UUID[];
+    change_id BIGINT;
+BEGIN
+    IF TG_OP <> 'INSERT' THEN
+        old_row := to_jsonb(OLD);
+    END IF;
+    IF TG_OP <> 'DELETE' THEN
+        new_row := to_jsonb(NEW);
+    END IF;
+
+    -- moving to and restoring from the trash count as deleting and creating, purging the trash is no change
+    IF old_row IS NULL THEN
+        change := CASE WHEN new_row->>'deleted_at' IS NULL THEN 'created' END;
+    ELSIF new_row IS NULL THEN
+        change := CASE WHEN old_row->>'deleted_at' IS NULL THEN 'deleted' END;
+    ELSIF old_row->>'deleted_at' IS NULL AND new_row->>'deleted_at' IS NOT NULL THEN
+        change := 'deleted';
+    ELSIF old_row->>'deleted_at' IS NOT NULL AND new_row->>'deleted_at' IS NULL THEN
+        change := 'created';
+    ELSIF new_row->>'deleted_at' IS NULL AND old_row <> new_row THEN
+        change := 'updated';
+    END IF;
+    IF change IS NULL THEN
+        RETURN NULL;
+    END IF;
+
+    IF TG_ARGV[0] = 'building' THEN
+        buildings := ARRAY[(COALESCE(new_row, old_row)->>'id')::uuid];
+    ELSIF TG_ARGV[0] = 'storey' THEN
+        buildings := ARRAY(
+            SELECT DISTINCT (r->>'building_id')::uuid FROM unnest(ARRAY[old_row, new_row]) r WHERE r IS NOT NULL
+        );
+    ELSE
+        buildings := ARRAY(
+            SELECT DISTINCT building_id FROM storeys
+            WHERE id IN ((old_row->>'storey_id')::uuid, (new_row->>'storey_id')::uuid)
+        );
+    END IF;
+
+    INSERT INTO asset_changes (entity_type, entity_id, action, building_ids, data)
+        VALUES (TG_ARGV[0], (COALESCE(new_row, old_row)->>'id')::uuid, change, buildings, COALESCE(new_row, old_row))
+        RETURNING id INTO change_id;
+    PERFORM pg_notify('asset_changes', change_id::text);
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER buildings_changes AFTER INSERT OR UPDATE OR DELETE ON buildings
+    FOR EACH ROW EXECUTE FUNCTION record_asset_change('building');
+CREATE TRIGGER storeys_changes AFTER INSERT OR UPDATE OR DELETE ON storeys
+    FOR EACH ROW EXECUTE FUNCTION record_asset_change('storey');
+CREATE TRIGGER rooms_changes AFTER INSERT OR UPDATE OR DELETE ON rooms
+    FOR EACH ROW EXECUTE FUNCTION record_asset_change('room');
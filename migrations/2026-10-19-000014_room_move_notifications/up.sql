@@ -0,0 +1,15 @@
+-- rooms that ended up in another building, written together with the move and kept
+-- until the reservations service has been told; failed notifications are retried until they arrive
+CREATE TABLE room_move_notifications (
+    id UUID PRIMARY KEY,
+    room_id UUID NOT NULL,
+    storey_id UUID NOT NULL,
+    from_building_id UUID NOT NULL,
+    to_building_id UUID NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX room_move_notifications_due_idx ON room_move_notifications (next_attempt_at);
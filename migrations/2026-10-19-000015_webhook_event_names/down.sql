@@ -0,0 +1,7 @@
+UPDATE webhooks SET events = ARRAY(
+    SELECT regexp_replace(pattern, '\.(create|update|delete|restore|move)d$', '.\1') FROM unnest(events) AS pattern
+);
+
+UPDATE webhook_deliveries SET
+    event_type = regexp_replace(event_type, '\.(create|update|delete|restore|move)d$', '.\1'),
+    payload = jsonb_set(payload, '{type}', to_jsonb(regexp_replace(event_type, '\.(create|update|delete|restore|move)d$', '.\1')));
@@ -0,0 +1,9 @@
+-- webhook events are named like the events of the outbox and the event stream now,
+-- `room.update` became `room.updated`; subscriptions and pending deliveries follow
+UPDATE webhooks SET events = ARRAY(
+    SELECT regexp_replace(pattern, '\.(create|update|delete|restore|move)$', '.\1d') FROM unnest(events) AS pattern
+);
+
+UPDATE webhook_deliveries SET
+    event_type = regexp_replace(event_type, '\.(create|update|delete|restore|move)$', '.\1d'),
+    payload = jsonb_set(payload, '{type}', to_jsonb(regexp_replace(event_type, '\.(create|update|delete|restore|move)$', '.\1d')));
@@ -0,0 +1,3 @@
+DROP INDEX asset_changes_tx_idx;
+
+ALTER TABLE asset_changes DROP COLUMN tx;
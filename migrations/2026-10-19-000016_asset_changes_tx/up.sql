@@ -0,0 +1,7 @@
+-- The ID of a change is taken when it is written, not when its transaction commits, so a change can turn up
+-- after others with higher IDs. `tx` is the transaction that made the change: once it is older than every running
+-- transaction, no change can turn up before it anymore, and readers go through the changes by `tx` and then `id`.
+-- Changes recorded before are taken as made by a single transaction.
+ALTER TABLE asset_changes ADD COLUMN tx BIGINT NOT NULL DEFAULT pg_current_xact_id()::text::bigint;
+
+CREATE INDEX asset_changes_tx_idx ON asset_changes (tx, id);
@@ -0,0 +1,5 @@
+DROP TRIGGER rooms_collection_version ON rooms;
+DROP TRIGGER storeys_collection_version ON storeys;
+DROP TRIGGER buildings_collection_version ON buildings;
+DROP FUNCTION bump_collection_version();
+DROP TABLE collection_version;
@@ -0,0 +1,31 @@
+-- Version of the asset collections for conditional GETs, counted up by every transaction that writes to
+-- buildings, storeys or rooms, purging the trash included. The triggers are deferred to the commit, so the version
+-- goes up in the order the transactions commit, and the row is only locked while they do.
+-- It starts above every change ID, which versioned the collections before, so no old ETag matches by chance.
+CREATE TABLE collection_version (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    version BIGINT NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL
+);
+
+INSERT INTO collection_version (version, changed_at)
+    SELECT COALESCE(max(id), 0) + 1, now() FROM asset_changes;
+
+CREATE FUNCTION bump_collection_version() RETURNS trigger AS $$
+BEGIN
+    -- once per transaction, however many rows it wrote
+    IF current_setting('assets.collection_version_bumped', true) = 'true' THEN
+        RETURN NULL;
+    END IF;
+    PERFORM set_config('assets.collection_version_bumped', 'true', true);
+    UPDATE collection_version SET version = version + 1, changed_at = clock_timestamp();
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE CONSTRAINT TRIGGER buildings_collection_version AFTER INSERT OR UPDATE OR DELETE ON buildings
+    DEFERRABLE INITIALLY DEFERRED FOR EACH ROW EXECUTE FUNCTION bump_collection_version();
+CREATE CONSTRAINT TRIGGER storeys_collection_version AFTER INSERT OR UPDATE OR DELETE ON storeys
+    DEFERRABLE INITIALLY DEFERRED FOR EACH ROW EXECUTE FUNCTION bump_collection_version();
+CREATE CONSTRAINT TRIGGER rooms_collection_version AFTER INSERT OR UPDATE OR DELETE ON rooms
+    DEFERRABLE INITIALLY DEFERRED FOR EACH ROW EXECUTE FUNCTION bump_collection_version();
@@ -0,0 +1,2 @@
+DROP INDEX room_move_notifications_room_idx;
+ALTER TABLE room_move_notifications DROP COLUMN status;
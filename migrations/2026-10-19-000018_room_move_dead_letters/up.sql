@@ -0,0 +1,5 @@
+-- room moves the reservations service rejects, or that ran out of attempts, are kept as dead letters;
+-- moves of a room are sent in the order they happened, the index finds the older ones still pending
+ALTER TABLE room_move_notifications ADD COLUMN status TEXT NOT NULL DEFAULT 'pending';
+
+CREATE INDEX room_move_notifications_room_idx ON room_move_notifications (room_id, created_at) WHERE status = 'pending';
@@ -1,5 +1,22 @@
 pub mod buildings_api;
 pub mod storeys_api;
 pub mod rooms_api;
+pub mod equipment_api;
+pub mod trash_api;
+pub mod audit_api;
+pub mod geojson_api;
+pub mod floorplans_api;
+pub mod import_api;
+pub mod export_api;
+pub mod batch_api;
+pub mod moves_api;
+pub mod clone_api;
+pub mod webhooks_api;
+pub mod events_api;
+pub mod cache_api;
+pub mod openapi_api;
+pub mod cascade;
+pub mod checks;
+pub mod conditional;
 pub mod util;
 pub mod auth;
\ No newline at end of file
@@ -0,0 +1,38 @@
+use actix_web::{get, HttpMessage, HttpRequest, HttpResponse, Responder, web};
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+use log::info;
+
+use crate::api::auth::{validator, Actor};
+use crate::api::util::get_trace_id;
+use crate::db::crud::audit_crud::*;
+use crate::db::models::{AuditEntry, AuditFilter};
+
+/// Audit context of a request, changes made with it are recorded on behalf of the authenticated actor,
+/// with the trace ID from its Jaeger header.
+pub fn request_audit(req: &HttpRequest) -> Audit {
+    actor_audit(req.extensions().get::<Actor>().cloned(), get_trace_id(req))
+}
+
+/// Audit context for changes outside of a request, e.g. from the command line.
+pub fn actor_audit(actor: Option<Actor>, trace_id: Option<String>) -> Audit {
+    match actor {
+        Some(actor) => Audit::new(actor.id, actor.name, trace_id),
+        None => Audit::new(None, None, trace_id)
+    }
+}
+
+#[utoipa::path(
+    tag = "audit",
+    params(AuditFilter),
+    responses(
+        (status = 200, description = "Audit log entries matching the filter, newest first", body = [AuditEntry])
+    ),
+    security(("bearer" = []))
+)]
+#[get("/audit", wrap="HttpAuthentication::bearer(validator)")]
+async fn get_audit_log(filter: web::Query<AuditFilter>) -> impl Responder {
+    let entries = find_audit_entries(&filter);
+    info!("found {} audit entries", entries.len());
+    HttpResponse::Ok().json(entries)
+}
@@ -1,4 +1,4 @@
-use actix_web::{Error, dev::ServiceRequest};
+use actix_web::{Error, HttpMessage, dev::ServiceRequest};
 use actix_web_httpauth::extractors::{AuthenticationError, bearer::{BearerAuth, Config}};
 
 use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
@@ -18,20 +18,21 @@ struct KeycloakPublicKey {
 
 /// Fetch the RSA public key from a Keycloak server.
 /// The GET request should be submitted to the traefik reverse proxy and include the Jaeger tracing header.
-fn fetch_keycloak_pubkey(jaeger_key: String, jaeger_id: String) -> Option<DecodingKey> {
+async fn fetch_keycloak_pubkey(jaeger_key: String, jaeger_id: String) -> Option<DecodingKey> {
     
     let keycloak_host = env::var("KEYCLOAK_HOST").unwrap_or("traefik".to_string());
     let keycloak_realm = env::var("KEYCLOAK_REALM").unwrap_or("biletado".to_string());
     let keycloak_url = format!("http://{}/auth/realms/{}", keycloak_host, keycloak_realm);
     
-    let client = reqwest::blocking::Client::new();
+    // the async client, the blocking one panics when it is dropped inside the actix runtime
+    let client = reqwest::Client::new();
     let resp = client.get(keycloak_url)
                      .header(jaeger_key, jaeger_id)
-                     .send().ok()?;
+                     .send().await.ok()?;
 
     if resp.status().is_success() {
         
-        let pubkey : KeycloakPublicKey = resp.json().ok()?;
+        let pubkey : KeycloakPublicKey = resp.json().await.ok()?;
         
         // do not touch, enough hours were wasted here
         let pem_key = format!("-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----", pubkey.public_key.trim());
@@ -49,14 +50,25 @@ fn fetch_keycloak_pubkey(jaeger_key: String, jaeger_id: String) -> Option<Decodi
 
 #[derive(Deserialize)]
 /// The claims deserialized from the JWT MUST contain the `exp` attribute.
+/// `sub` and `preferred_username` identify the user for the audit log.
 struct Claims {
-    exp: usize
+    #[allow(dead_code)] // never read, but decoding has to fail without it
+    exp: usize,
+    sub: Option<String>,
+    preferred_username: Option<String>
+}
+
+#[derive(Clone)]
+/// The authenticated user of a request.
+/// The validator attaches it to the request extensions, where handlers can pick it up.
+pub struct Actor {
+    pub id: Option<String>,
+    pub name: Option<String>
 }
 
 /// Validate a token using the public key from the keycloak server.
-/// Return an optional containing true if the token was decoded successfully,
-/// false if decoded with problems, None if decoding fails.
-fn validate_auth(token: String, decoding_key: DecodingKey) -> Option<bool> {
+/// Return the claims of the token if it was decoded successfully, None otherwise.
+fn validate_auth(token: String, decoding_key: DecodingKey) -> Option<Claims> {
 
     debug!("attempting to validate token {}", token);
     
@@ -74,7 +86,7 @@ fn validate_auth(token: String, decoding_key: DecodingKey) -> Option<bool> {
 
     if token_msg.is_err() { debug!("error while decoding json web token {}", token); }
 
-    Some(token_msg.is_ok())
+    token_msg.ok().map(|data| data.claims)
 
 }
 
@@ -87,8 +99,8 @@ pub async fn validator(req: ServiceRequest, credentials: BearerAuth) -> Result<S
     let (jaeger_key, jaeger_id) = get_jaeger_params(&req);
 
     // fetch the public key every time because Rust lifetimes are a headache and a half
-    let pubkey = fetch_keycloak_pubkey(jaeger_key, jaeger_id);
-    let config = req.app_data::<Config>().map(|data| data.clone()).unwrap_or_else(Default::default);
+    let pubkey = fetch_keycloak_pubkey(jaeger_key, jaeger_id).await;
+    let config = req.app_data::<Config>().cloned().unwrap_or_default();
 
     if pubkey.is_none() {
         debug!("keycloak public key not found");
@@ -100,7 +112,10 @@ pub async fn validator(req: ServiceRequest, credentials: BearerAuth) -> Result<S
     debug!("extracted token successfully, attempting to validate");
 
     match validate_auth(token, pubkey.unwrap()) {
-        Some(res) => if res { Ok(req) } else { Err(AuthenticationError::from(config).into()) },
+        Some(claims) => {
+            req.extensions_mut().insert(Actor { id: claims.sub, name: claims.preferred_username });
+            Ok(req)
+        },
         None => Err(AuthenticationError::from(config).into())
     }
 }
\ No newline at end of file
@@ -0,0 +1,317 @@
+use actix_web::{post, HttpRequest, HttpResponse, Responder};
+use actix_web::http::StatusCode;
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+use diesel::PgConnection;
+use log::{info, error};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::env;
+use uuid::Uuid;
+
+use crate::api::audit_api::request_audit;
+use crate::api::auth::validator;
+use crate::api::checks::{check_building, check_room, check_storey, Rejection};
+use crate::api::util::get_trace_id;
+use crate::cache::invalidate_all;
+use crate::db::crud::audit_crud::Audit;
+use crate::batch::{AssetType, BatchAction, BatchOperation, BatchReport, BatchRequest, OperationResult, References};
+use crate::db::crud::buildings_crud::{create_or_update_building_with, delete_building_by_id_with, find_building_by_id_with};
+use crate::db::crud::rooms_crud::{create_or_update_room_with, delete_room_by_id_with, find_room_by_id_with, has_rooms_with};
+use crate::db::crud::storeys_crud::{create_or_update_storey_with, delete_storey_by_id_with, find_storey_by_id_with, has_storeys_with};
+use crate::db::models::{OptionalIDBuilding, OptionalIDRoom, OptionalIDStorey};
+use crate::dbconn::transaction;
+use crate::openapi::ErrorMessage;
+use crate::reservations;
+
+/// Largest number of operations in a batch by default, can be changed with `BATCH_MAX_OPERATIONS`.
+const DEFAULT_MAX_OPERATIONS: usize = 1000;
+
+/// Asset written by an operation, reported once the batch is committed.
+/// The audit log gets the change inside the batch transaction.
+struct Change {
+    id: Uuid,
+    after: Option<Value>
+}
+
+impl Change {
+    fn new<T: Serialize>(id: Uuid, after: Option<&T>) -> Change {
+        Change { id, after: after.and_then(|asset| serde_json::to_value(asset).ok()) }
+    }
+}
+
+/// Why a batch was rolled back.
+enum BatchError {
+    Operation(usize, Rejection),
+    Database(diesel::result::Error)
+}
+
+impl From<diesel::result::Error> for BatchError {
+    fn from(e: diesel::result::Error) -> Self {
+        BatchError::Database(e)
+    }
+}
+
+#[utoipa::path(
+    tag = "batch",
+    request_body = BatchRequest,
+    responses(
+        (status = 200, description = "All operations ran and were committed", body = BatchReport),
+        (status = 400, description = "Invalid request body or too many operations", body = ErrorMessage),
+        (status = 422, description = "An operation failed, the batch was rolled back", body = BatchReport),
+        (status = 500, description = "An operation failed on the server side, the batch was rolled back", body = BatchReport),
+        (status = 503, description = "The reservations of a deleted room could not be checked, the batch was rolled back", body = BatchReport)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/batch", wrap="HttpAuthentication::bearer(validator)")]
+async fn batch_assets(req_body: String, req: HttpRequest) -> impl Responder {
+
+    let batch : BatchRequest = match serde_json::from_str(&req_body) {
+        Ok(batch) => batch,
+        Err(e) => {
+            error!("invalid batch request body: {}", e);
+            return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+        }
+    };
+
+    let max_operations = env::var("BATCH_MAX_OPERATIONS").ok().and_then(|max| max.parse().ok()).unwrap_or(DEFAULT_MAX_OPERATIONS);
+    if batch.operations.len() > max_operations {
+        error!("batch with {} operations, at most {} are allowed", batch.operations.len(), max_operations);
+        return HttpResponse::BadRequest().json(json!({ "message": format!("at most {} operations per batch", max_operations) }));
+    }
+
+    let reserved = reserved_rooms(&batch.operations, &req).await;
+
+    let audit = request_audit(&req);
+    let mut results = Vec::new();
+    let outcome = transaction::<_, BatchError, _>(|conn| {
+        let mut references = References::default();
+        for (index, operation) in batch.operations.iter().enumerate() {
+            let change = run_operation(conn, &audit, operation, &mut references, reserved.as_deref())
+                .map_err(|e| BatchError::Operation(index, e))?;
+            let status = match operation.op {
+                BatchAction::Create => StatusCode::CREATED,
+                BatchAction::Update => StatusCode::OK,
+                BatchAction::Delete => StatusCode::NO_CONTENT
+            };
+            results.push(OperationResult {
+                index,
+                op: operation.op,
+                asset_type: operation.asset_type,
+                id: Some(change.id),
+                status: Some(status.as_u16()),
+                message: None,
+                asset: change.after
+            });
+        }
+        Ok(())
+    });
+
+    match outcome {
+        Ok(()) => {
+            info!("batch of {} operations committed", batch.operations.len());
+            invalidate_all(results.iter().filter_map(|result| Some((result.asset_type.name(), result.id?))));
+            HttpResponse::Ok().json(BatchReport { committed: true, results })
+        },
+        Err(BatchError::Operation(index, e)) => {
+            error!("operation {} of batch failed, rolled back: {}", index, e.message);
+            let failed = &batch.operations[index];
+            results.push(OperationResult {
+                index,
+                op: failed.op,
+                asset_type: failed.asset_type,
+                id: None,
+                status: Some(e.status.as_u16()),
+                message: Some(e.message),
+                asset: None
+            });
+            for (index, operation) in batch.operations.iter().enumerate().skip(index + 1) {
+                results.push(OperationResult {
+                    index,
+                    op: operation.op,
+                    asset_type: operation.asset_type,
+                    id: None,
+                    status: None,
+                    message: Some("not run".to_string()),
+                    asset: None
+                });
+            }
+            // results of operations that were rolled back are still reported, `committed` tells they did not stick
+            let report = BatchReport { committed: false, results };
+            // server side failures keep their status, e.g. 503 if the reservations could not be checked
+            if e.status.is_server_error() {
+                HttpResponse::build(e.status).json(report)
+            } else {
+                HttpResponse::UnprocessableEntity().json(report)
+            }
+        },
+        Err(BatchError::Database(e)) => {
+            error!("batch transaction failed: {}", e);
+            HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }))
+        }
+    }
+
+}
+
+/// Rooms deleted by the batch that have reservations, or None if the reservations could not be fetched.
+/// This is checked before the transaction starts, so it is not kept open while waiting for the reservations backend.
+/// Rooms that are referenced by name are created in the same batch and cannot have reservations yet.
+async fn reserved_rooms(operations: &[BatchOperation], req: &HttpRequest) -> Option<Vec<Uuid>> {
+    let room_ids : Vec<Uuid> = operations.iter()
+        .filter(|operation| operation.op == BatchAction::Delete && operation.asset_type == AssetType::Room)
+        .filter_map(|operation| operation.id.as_deref().and_then(|id| Uuid::parse_str(id).ok()))
+        .collect();
+    if room_ids.is_empty() {
+        return Some(Vec::new());
+    }
+
+    match reservations::client().rooms_with_reservations(&room_ids, get_trace_id(req).as_deref()).await {
+        Ok(reserved) => Some(reserved),
+        Err(e) => {
+            error!("could not get reservation data for batch: {}", e);
+            None
+        }
+    }
+}
+
+/// Run a single operation of a batch on the connection of the batch transaction.
+fn run_operation(conn: &PgConnection, audit: &Audit, operation: &BatchOperation, references: &mut References, reserved: Option<&[Uuid]>) -> Result<Change, Rejection> {
+
+    let id = match operation.id {
+        Some(ref id) => Some(references.resolve(id).map_err(|e| Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, e))?),
+        None => None
+    };
+
+    let change = if operation.op == BatchAction::Delete {
+        let id = id.ok_or_else(|| Rejection::new(StatusCode::BAD_REQUEST, "delete without UUID"))?;
+        match operation.asset_type {
+            AssetType::Building => delete_building(conn, audit, id)?,
+            AssetType::Storey => delete_storey(conn, audit, id)?,
+            AssetType::Room => delete_room(conn, audit, id, reserved)?
+        }
+    } else {
+        let mut data = operation.data.clone();
+        references.resolve_fields(&mut data).map_err(|e| Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+        match operation.asset_type {
+            AssetType::Building => save_building(conn, audit, operation.op, id, data)?,
+            AssetType::Storey => save_storey(conn, audit, operation.op, id, data)?,
+            AssetType::Room => save_room(conn, audit, operation.op, id, data)?
+        }
+    };
+
+    if let Some(ref name) = operation.reference {
+        if operation.op != BatchAction::Create {
+            return Err(Rejection::new(StatusCode::BAD_REQUEST, "only created assets can have a reference"));
+        }
+        if !references.insert(name, change.id) {
+            return Err(Rejection::new(StatusCode::BAD_REQUEST, format!("reference {} is used twice", name)));
+        }
+    }
+    Ok(change)
+}
+
+fn parse_data<T: serde::de::DeserializeOwned>(data: Value) -> Result<T, Rejection> {
+    serde_json::from_value(data).map_err(|_| Rejection::new(StatusCode::BAD_REQUEST, "invalid input"))
+}
+
+/// Work out the asset a create or update is about, the UUID of the operation and the one in the data have to agree.
+fn target_id(id: Option<Uuid>, body_id: Option<Uuid>) -> Result<Option<Uuid>, Rejection> {
+    match (id, body_id) {
+        (Some(id), Some(body_id)) if id != body_id => Err(Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, "mismatched ID in operation and object")),
+        (id, body_id) => Ok(id.or(body_id))
+    }
+}
+
+/// Unlike the single endpoints, a create must not overwrite an existing asset and an update must not create one.
+/// Everything else is checked like in the single endpoints.
+fn check_existing<T>(action: BatchAction, id: Option<Uuid>, before: &Option<T>) -> Result<(), Rejection> {
+    match (action, id, before) {
+        (BatchAction::Create, Some(_), Some(_)) => Err(Rejection::new(StatusCode::CONFLICT, "asset with UUID already exists")),
+        (BatchAction::Update, None, _) => Err(Rejection::new(StatusCode::BAD_REQUEST, "update without UUID")),
+        (BatchAction::Update, Some(_), None) => Err(Rejection::new(StatusCode::NOT_FOUND, "asset with UUID not found")),
+        _ => Ok(())
+    }
+}
+
+fn save_building(conn: &PgConnection, audit: &Audit, action: BatchAction, id: Option<Uuid>, data: Value) -> Result<Change, Rejection> {
+    let mut building : OptionalIDBuilding = parse_data(data)?;
+    building.id = target_id(id, building.id)?;
+
+    let checked = check_building(conn, building)?;
+    check_existing(action, checked.request.id, &checked.before)?;
+
+    let building = checked.request;
+    let after = create_or_update_building_with(conn, audit, building.id, building.name, checked.address, building.attributes)
+        .ok_or_else(|| Rejection::new(StatusCode::INTERNAL_SERVER_ERROR, "something went wrong :O"))?;
+    Ok(Change::new(after.id, Some(&after)))
+}
+
+fn save_storey(conn: &PgConnection, audit: &Audit, action: BatchAction, id: Option<Uuid>, data: Value) -> Result<Change, Rejection> {
+    let mut storey : OptionalIDStorey = parse_data(data)?;
+    storey.id = target_id(id, storey.id)?;
+
+    let checked = check_storey(conn, storey)?;
+    check_existing(action, checked.request.id, &checked.before)?;
+
+    let storey = checked.request;
+    let after = create_or_update_storey_with(conn, audit, storey.id, storey.name, storey.building_id, storey.attributes)
+        .ok_or_else(|| Rejection::new(StatusCode::INTERNAL_SERVER_ERROR, "something went wrong :O"))?;
+    Ok(Change::new(after.id, Some(&after)))
+}
+
+fn save_room(conn: &PgConnection, audit: &Audit, action: BatchAction, id: Option<Uuid>, data: Value) -> Result<Change, Rejection> {
+    let mut room : OptionalIDRoom = parse_data(data)?;
+    room.id = target_id(id, room.id)?;
+
+    let checked = check_room(conn, room)?;
+    check_existing(action, checked.request.id, &checked.before)?;
+
+    let room = checked.request;
+    let after = create_or_update_room_with(conn, audit, room.id, room.name, room.storey_id, room.attributes)
+        .ok_or_else(|| Rejection::new(StatusCode::INTERNAL_SERVER_ERROR, "something went wrong :O"))?;
+    Ok(Change::new(after.id, Some(&after)))
+}
+
+fn delete_building(conn: &PgConnection, audit: &Audit, id: Uuid) -> Result<Change, Rejection> {
+    if find_building_by_id_with(conn, id, false).is_none() {
+        return Err(Rejection::new(StatusCode::NOT_FOUND, "building with UUID not found"));
+    }
+    if has_storeys_with(conn, id) {
+        return Err(Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, "building has existing storeys"));
+    }
+    if !delete_building_by_id_with(conn, audit, id) {
+        return Err(Rejection::new(StatusCode::NOT_FOUND, "building with UUID not found"));
+    }
+    Ok(Change::new::<Value>(id, None))
+}
+
+fn delete_storey(conn: &PgConnection, audit: &Audit, id: Uuid) -> Result<Change, Rejection> {
+    if find_storey_by_id_with(conn, id, false).is_none() {
+        return Err(Rejection::new(StatusCode::NOT_FOUND, "storey with UUID not found"));
+    }
+    if has_rooms_with(conn, id) {
+        return Err(Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, "storey has existing rooms"));
+    }
+    if !delete_storey_by_id_with(conn, audit, id) {
+        return Err(Rejection::new(StatusCode::NOT_FOUND, "storey with UUID not found"));
+    }
+    Ok(Change::new::<Value>(id, None))
+}
+
+fn delete_room(conn: &PgConnection, audit: &Audit, id: Uuid, reserved: Option<&[Uuid]>) -> Result<Change, Rejection> {
+    if find_room_by_id_with(conn, id, false).is_none() {
+        return Err(Rejection::new(StatusCode::NOT_FOUND, "room with UUID not found"));
+    }
+    match reserved {
+        Some(reserved) if reserved.contains(&id) => {
+            return Err(Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, format!("room {} has existing reservations", id)));
+        },
+        Some(_) => (),
+        None => return Err(Rejection::new(StatusCode::SERVICE_UNAVAILABLE, "could not check reservations of room"))
+    }
+    if !delete_room_by_id_with(conn, audit, id) {
+        return Err(Rejection::new(StatusCode::NOT_FOUND, "room with UUID not found"));
+    }
+    Ok(Change::new::<Value>(id, None))
+}
@@ -1,14 +1,34 @@
-use actix_web::{get, post, put, delete, HttpResponse, Responder, web};
+use actix_web::{get, post, put, delete, HttpRequest, HttpResponse, Responder, web};
 use actix_web_httpauth::middleware::HttpAuthentication;
 
+use chrono::{DateTime, Utc};
 use log::{info, error};
+use serde::Deserialize;
 use serde_json::json;
+use utoipa::IntoParams;
+use std::sync::Arc;
 
+use crate::api::audit_api::request_audit;
 use crate::api::auth::validator;
-use crate::api::util::validate_uuid;
+use crate::api::cascade::cascade_delete;
+use crate::api::checks::check_building;
+use crate::api::conditional::conditional_get;
+use crate::api::geojson_api::{asset_feature, feature_collection, wants_geojson};
+use crate::api::util::{history_unreadable, validate_uuid, QueryDeleteMode, QueryReadOptions, QueryReservationPolicy};
+use crate::cache;
+use crate::db::crud::history_crud::{asset_as_of, assets_as_of, asset_history_of};
 use crate::db::crud::buildings_crud::*;
+use crate::db::crud::cascade_crud::building_subtree;
 use crate::db::crud::storeys_crud::has_storeys;
-use crate::db::models::OptionalIDBuilding;
+use crate::db::models::{AssetVersion, Building, DeletionReport, OptionalIDBuilding};
+use crate::dbconn::connection;
+use crate::geo;
+use crate::openapi::ErrorMessage;
+
+/// Radius in metres for distance queries that do not specify one.
+const DEFAULT_RADIUS: f64 = 5000.0;
+/// Largest radius in metres of a distance query, larger ones are cut down to it.
+const MAX_RADIUS: f64 = 100_000.0;
 
 // Yeah yeah, I know, a lot of this code is duplicated throughout the API implementation.
 // However, I couldn't really figure out how deduplicate this without using traits or macro magic,
@@ -21,15 +41,82 @@ use crate::db::models::OptionalIDBuilding;
 // A lot of the code is structurally similar/identical with different struct types as input/output,
 // so just parametrize your macros accordingly. Traits could work too, but have fun writing THAT generic code.
 
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct QueryBuildings {
+    include_deleted: Option<bool>,
+    as_of: Option<DateTime<Utc>>,
+    near: Option<String>,
+    radius: Option<f64>
+}
+
+#[utoipa::path(
+    tag = "buildings",
+    params(QueryBuildings),
+    responses(
+        (status = 200, description = "Buildings, with `near` nearest first and with their `distance` in metres; GeoJSON with `Accept: application/geo+json`", body = [Building]),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 400, description = "Invalid position or radius", body = ErrorMessage)
+    )
+)]
 #[get("/buildings")]
-async fn get_all_buildings() -> impl Responder {
-    let buildings = get_buildings();
+async fn get_all_buildings(param: web::Query<QueryBuildings>, req: HttpRequest) -> impl Responder {
+    conditional_get(&req, |version| list_buildings(&param, &req, version))
+}
+
+fn list_buildings(param: &QueryBuildings, req: &HttpRequest, version: Option<i64>) -> HttpResponse {
+
+    if let Some(ref near) = param.near {
+        let position = geo::parse_position(near);
+        let radius = param.radius.unwrap_or(DEFAULT_RADIUS);
+        if position.is_none() || radius.is_nan() || radius < 0.0 {
+            error!("invalid distance query near {} with radius {}", near, radius);
+            return HttpResponse::BadRequest().json(json!({ "message": "invalid position or radius" }));
+        }
+        let radius = radius.min(MAX_RADIUS);
+        let include_deleted = param.include_deleted.unwrap_or(false);
+
+        let nearby = match param.as_of {
+            Some(as_of) => match assets_as_of::<Building>("building", as_of) {
+                Ok(buildings) => nearest_first(buildings, position.unwrap(), radius),
+                Err(e) => return history_unreadable(e)
+            },
+            None => buildings_near(position.unwrap(), radius, include_deleted)
+        };
+        info!("found {} buildings within {} m of {}", nearby.len(), radius, near);
+        if wants_geojson(req) {
+            return feature_collection(nearby.iter().map(|building| asset_feature("building", building)).collect());
+        }
+        return HttpResponse::Ok().json(nearby);
+    }
+
+    let include_deleted = param.include_deleted.unwrap_or(false);
+    let buildings = match param.as_of {
+        Some(as_of) => match assets_as_of::<Building>("building", as_of) {
+            Ok(buildings) => Arc::new(buildings),
+            Err(e) => return history_unreadable(e)
+        },
+        None => cache::read_at(cache::building_lists, include_deleted, version, || get_buildings(include_deleted))
+    };
     info!("found {} buildings", buildings.len());
-    HttpResponse::Ok().json(buildings)
+    if wants_geojson(req) {
+        return feature_collection(buildings.iter().map(|building| asset_feature("building", building)).collect());
+    }
+    HttpResponse::Ok().json(&*buildings)
 }
 
+#[utoipa::path(
+    tag = "buildings",
+    request_body = OptionalIDBuilding,
+    responses(
+        (status = 201, description = "Building created, or updated if one with the given UUID exists", body = Building),
+        (status = 400, description = "Invalid request body", body = ErrorMessage),
+        (status = 409, description = "Building with the UUID is in the trash", body = ErrorMessage),
+        (status = 422, description = "Invalid attributes", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
 #[post("/buildings", wrap="HttpAuthentication::bearer(validator)")]
-async fn add_building(req_body: String) -> impl Responder {
+async fn add_building(req_body: String, req: HttpRequest) -> impl Responder {
     let body_content : Result<OptionalIDBuilding, serde_json::Error> = serde_json::from_str(&req_body);
     if body_content.is_err() {
         error!("invalid building request body: {}", req_body);
@@ -37,10 +124,14 @@ async fn add_building(req_body: String) -> impl Responder {
     }
     
     let building = body_content.unwrap();
-    let building_name = building.name.to_string();
-    let building_address = building.address.to_string();
 
-    if let Some(new_building) = create_or_update_building(building.id, building_name, building_address) {
+    let checked = match check_building(&connection().unwrap(), building) {
+        Ok(checked) => checked,
+        Err(rejection) => return rejection.response()
+    };
+    let building = checked.request;
+
+    if let Some(new_building) = create_or_update_building(&request_audit(&req), building.id, building.name, checked.address, building.attributes) {
         info!("building {} newly created or updated", new_building.id);
         HttpResponse::Created().json(new_building)
     } else {
@@ -49,13 +140,29 @@ async fn add_building(req_body: String) -> impl Responder {
     }
 }
 
+#[utoipa::path(
+    tag = "buildings",
+    params(("id" = Uuid, Path, description = "UUID of the building"), QueryReadOptions),
+    responses(
+        (status = 200, description = "The building", body = Building),
+        (status = 404, description = "No building with the UUID", body = ErrorMessage)
+    )
+)]
 #[get("/buildings/{id}")]
-async fn get_building_by_id(id: web::Path<String>) -> impl Responder {
+async fn get_building_by_id(id: web::Path<String>, param: web::Query<QueryReadOptions>) -> impl Responder {
     let building_uuid = validate_uuid(id.to_string());
 
     if let Some(building_id) = building_uuid {
 
-        match find_building_by_id(building_id) {
+        let building = match param.as_of {
+            Some(as_of) => match asset_as_of::<Building>("building", building_id, as_of) {
+                Ok(building) => building,
+                Err(e) => return history_unreadable(e)
+            },
+            None => find_building_by_id(building_id, param.include_deleted.unwrap_or(false))
+        };
+
+        match building {
             Some(building) => {
                 info!("found building with UUID: {}", id);
                 HttpResponse::Ok().json(building)
@@ -72,8 +179,20 @@ async fn get_building_by_id(id: web::Path<String>) -> impl Responder {
     }
 }
 
+#[utoipa::path(
+    tag = "buildings",
+    params(("id" = Uuid, Path, description = "UUID of the building")),
+    request_body = OptionalIDBuilding,
+    responses(
+        (status = 204, description = "Building updated, or created if it did not exist"),
+        (status = 400, description = "Invalid UUID or request body", body = ErrorMessage),
+        (status = 409, description = "Building with the UUID is in the trash", body = ErrorMessage),
+        (status = 422, description = "UUID in the body does not match, or invalid attributes", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
 #[put("/buildings/{id}", wrap="HttpAuthentication::bearer(validator)")]
-async fn update_building(id: web::Path<String>, req_body: String) -> impl Responder {
+async fn update_building(id: web::Path<String>, req_body: String, req: HttpRequest) -> impl Responder {
     
     let param_id = validate_uuid(id.to_string());
     if param_id.is_none() {
@@ -88,8 +207,6 @@ async fn update_building(id: web::Path<String>, req_body: String) -> impl Respon
     }
 
     let building = body_content.unwrap();
-    let building_name = building.name.to_string();
-    let building_address = building.address.to_string();
 
     if let Some(body_id) = building.id {
         let param_id = param_id.unwrap();
@@ -99,7 +216,13 @@ async fn update_building(id: web::Path<String>, req_body: String) -> impl Respon
         }
     }
 
-    if let Some(new_building) = create_or_update_building(building.id, building_name, building_address) {
+    let checked = match check_building(&connection().unwrap(), building) {
+        Ok(checked) => checked,
+        Err(rejection) => return rejection.response()
+    };
+    let building = checked.request;
+
+    if let Some(new_building) = create_or_update_building(&request_audit(&req), building.id, building.name, checked.address, building.attributes) {
         info!("building {} newly created or updated", new_building.id);
         HttpResponse::NoContent().finish()
     } else {
@@ -109,8 +232,22 @@ async fn update_building(id: web::Path<String>, req_body: String) -> impl Respon
 
 }
 
+#[utoipa::path(
+    tag = "buildings",
+    params(("id" = Uuid, Path, description = "UUID of the building"), QueryDeleteMode, QueryReservationPolicy),
+    responses(
+        (status = 200, description = "Dry run, the assets that would be deleted", body = DeletionReport),
+        (status = 204, description = "Building moved to the trash"),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No building with the UUID"),
+        (status = 409, description = "Assets changed or rooms were reserved during a cascading delete", body = ErrorMessage),
+        (status = 422, description = "Has children without cascade, or rooms have reservations the policy does not allow to cancel", body = ErrorMessage),
+        (status = 503, description = "Reservations service unavailable", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
 #[delete("/buildings/{id}", wrap="HttpAuthentication::bearer(validator)")]
-async fn delete_building(id: web::Path<String>) -> impl Responder {
+async fn delete_building(id: web::Path<String>, mode: web::Query<QueryDeleteMode>, policy: web::Query<QueryReservationPolicy>, req: HttpRequest) -> impl Responder {
 
     let param_id = validate_uuid(id.to_string());
     if param_id.is_none() {
@@ -119,12 +256,27 @@ async fn delete_building(id: web::Path<String>) -> impl Responder {
     }
 
     let param_id = param_id.unwrap();
+    let dry_run = mode.dry_run.unwrap_or(false);
+    if (mode.cascade.unwrap_or(false) || dry_run) && find_building_by_id(param_id, false).is_none() {
+        error!("building with UUID {} not found", param_id);
+        return HttpResponse::NotFound().finish();
+    }
+
+    if mode.cascade.unwrap_or(false) {
+        return cascade_delete(building_subtree(param_id), dry_run, policy.on_reservations.unwrap_or_default(), &req).await;
+    }
+
     if has_storeys(param_id) {
         error!("cannot delete building {}, has existing storeys", param_id);
         return HttpResponse::UnprocessableEntity().json(json!({ "message": "building has existing storeys" }));
     }
+
+    if dry_run {
+        info!("dry run: would delete building {}", param_id);
+        return HttpResponse::Ok().json(DeletionReport { buildings: vec![param_id], storeys: Vec::new(), rooms: Vec::new() });
+    }
     
-    if delete_building_by_id(param_id) {
+    if delete_building_by_id(&request_audit(&req), param_id) {
         info!("deleted building {}", param_id);
         HttpResponse::NoContent().finish()
     } else {
@@ -133,3 +285,63 @@ async fn delete_building(id: web::Path<String>) -> impl Responder {
     }
     
 }
+
+#[utoipa::path(
+    tag = "buildings",
+    params(("id" = Uuid, Path, description = "UUID of the building")),
+    responses(
+        (status = 204, description = "Building restored from the trash"),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No building with the UUID in the trash", body = ErrorMessage),
+        (status = 422, description = "The building cannot come back, e.g. its parent is deleted", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/buildings/{id}/restore", wrap="HttpAuthentication::bearer(validator)")]
+async fn restore_building(id: web::Path<String>, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let param_id = param_id.unwrap();
+    if restore_building_by_id(&request_audit(&req), param_id) {
+        info!("restored building {}", param_id);
+        HttpResponse::NoContent().finish()
+    } else {
+        error!("building with UUID {} not found in trash", param_id);
+        HttpResponse::NotFound().json(json!({ "message": "building with UUID not found in trash" }))
+    }
+
+}
+
+#[utoipa::path(
+    tag = "buildings",
+    params(("id" = Uuid, Path, description = "UUID of the building")),
+    responses(
+        (status = 200, description = "All versions of the building, oldest first", body = [AssetVersion]),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No building with the UUID", body = ErrorMessage)
+    )
+)]
+#[get("/buildings/{id}/history")]
+async fn get_building_history(id: web::Path<String>) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let versions = asset_history_of("building", param_id.unwrap());
+    if versions.is_empty() {
+        error!("no history for building with UUID: {}", id);
+        return HttpResponse::NotFound().json(json!({ "message": "building with UUID not found" }));
+    }
+
+    info!("found {} versions of building {}", versions.len(), id);
+    HttpResponse::Ok().json(versions)
+
+}
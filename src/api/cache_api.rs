@@ -0,0 +1,28 @@
+use actix_web::{get, HttpResponse, Responder};
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::api::auth::validator;
+use crate::cache::{enabled, stats, CacheStats};
+
+#[derive(Serialize, ToSchema)]
+/// Whether the read cache is turned on, with the counters of each cache.
+pub struct CacheReport {
+    pub enabled: bool,
+    pub caches: Vec<CacheStats>
+}
+
+/// Hit and miss counters of the read cache, to tune its TTL and size.
+#[utoipa::path(
+    tag = "cache",
+    responses(
+        (status = 200, description = "Whether the read cache is enabled, and the counters of each cache", body = CacheReport)
+    ),
+    security(("bearer" = []))
+)]
+#[get("/cache", wrap="HttpAuthentication::bearer(validator)")]
+async fn get_cache_stats() -> impl Responder {
+    HttpResponse::Ok().json(CacheReport { enabled: enabled(), caches: stats() })
+}
@@ -0,0 +1,155 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::StatusCode;
+
+use chrono::Utc;
+use log::{info, error};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::api::audit_api::request_audit;
+use crate::db::crud::audit_crud::Audit;
+use crate::api::util::{get_trace_id, reservations_unavailable, ReservationPolicy};
+use crate::db::crud::cascade_crud::{delete_subtree, restore_subtree};
+use crate::db::models::{DeletionReport, Reservation};
+use crate::reservations;
+
+/// Find the reservations that stand in the way of deleting the rooms under the given policy.
+/// Err is the response to send if the reservations service cannot be asked.
+pub async fn blocking_reservations(rooms: &[Uuid], policy: ReservationPolicy, req: &HttpRequest) -> Result<Vec<Reservation>, HttpResponse> {
+    if rooms.is_empty() {
+        return Ok(Vec::new());
+    }
+    let now = Utc::now();
+    match reservations::client().reservations_of_rooms(rooms, get_trace_id(req).as_deref()).await {
+        Ok(mut found) => {
+            if policy != ReservationPolicy::Refuse {
+                found.retain(|res| reservations::is_current(res, now));
+            }
+            Ok(found)
+        },
+        Err(e) => Err(reservations_unavailable(&e))
+    }
+}
+
+/// Rooms that have at least one of the reservations.
+pub fn reserved_rooms(reservations: &[Reservation]) -> Vec<Uuid> {
+    let mut rooms : Vec<Uuid> = reservations.iter().map(|res| res.room_id).collect();
+    rooms.sort();
+    rooms.dedup();
+    rooms
+}
+
+/// Cancel the reservations of rooms that have just been deleted, the last step of `ReservationPolicy::Cancel`.
+/// This works like a saga: the service cannot take part in our transaction, so if a cancellation fails
+/// `restore` undoes the delete and the response lists the reservations that were cancelled already.
+pub async fn cancel_reservations<F: FnOnce() -> bool>(reservations: &[Reservation], restore: F, req: &HttpRequest) -> Result<(), HttpResponse> {
+    let trace_id = get_trace_id(req);
+    let mut cancelled = Vec::new();
+    for res in reservations {
+        if let Err(e) = reservations::client().cancel_reservation(res.id, trace_id.as_deref()).await {
+            error!("could not cancel reservation {} of room {}: {}", res.id, res.room_id, e);
+            if !restore() {
+                error!("could not restore assets after failed cancellation, they stay in the trash");
+                return Err(HttpResponse::InternalServerError().json(json!({
+                    "message": "could not cancel reservations, deleted assets are in the trash", "cancelled": cancelled
+                })));
+            }
+            info!("restored deleted assets after failed cancellation");
+            return Err(HttpResponse::ServiceUnavailable().json(json!({
+                "message": "could not cancel reservations, nothing was deleted", "cancelled": cancelled
+            })));
+        }
+        info!("cancelled reservation {} of room {}", res.id, res.room_id);
+        cancelled.push(res.id);
+    }
+    Ok(())
+}
+
+/// Undo a delete whose rooms turned out to be reserved, `response` is what to answer if that works.
+fn undo_delete<F: FnOnce() -> bool>(restore: F, response: HttpResponse) -> HttpResponse {
+    if !restore() {
+        error!("could not restore assets after reservations were found, they stay in the trash");
+        return HttpResponse::InternalServerError().json(json!({ "message": "rooms have reservations, deleted assets are in the trash" }));
+    }
+    info!("restored deleted assets after reservations were found");
+    response
+}
+
+/// Delete assets and deal with the reservations of their rooms, the part of a delete that must not stop halfway.
+/// It runs as a task of its own and is carried through even if the client goes away in between.
+/// Once the rooms are deleted their reservations are looked up again, a room may have been booked since the first check:
+/// under `ReservationPolicy::Cancel` all of them are cancelled, otherwise the delete is undone.
+/// `delete` returns the response to send if nothing was deleted, the assets to delete are passed back on success.
+pub async fn delete_with_reservations<T, D, R>(assets: T, rooms: Vec<Uuid>, policy: ReservationPolicy, delete: D, restore: R, req: &HttpRequest) -> Result<T, HttpResponse>
+where
+    T: 'static,
+    D: FnOnce(&Audit, &T) -> Result<(), HttpResponse> + 'static,
+    R: Fn(&Audit, &T) -> bool + 'static
+{
+    let audit = request_audit(req);
+    let req = req.clone();
+    let saga = actix_web::rt::spawn(async move {
+        delete(&audit, &assets)?;
+        let reservations = match blocking_reservations(&rooms, policy, &req).await {
+            Ok(reservations) => reservations,
+            Err(response) => return Err(undo_delete(|| restore(&audit, &assets), response))
+        };
+        if !reservations.is_empty() && policy != ReservationPolicy::Cancel {
+            let reserved = reserved_rooms(&reservations);
+            info!("{} rooms were reserved while deleting them", reserved.len());
+            let response = HttpResponse::build(StatusCode::CONFLICT).json(
+                json!({ "message": "rooms were reserved while deleting them, nothing was deleted", "rooms": reserved })
+            );
+            return Err(undo_delete(|| restore(&audit, &assets), response));
+        }
+        cancel_reservations(&reservations, || restore(&audit, &assets), &req).await?;
+        Ok(assets)
+    });
+    saga.await.unwrap_or_else(|e| {
+        error!("delete failed: {}", e);
+        Err(HttpResponse::InternalServerError().json(json!({ "message": "delete failed" })))
+    })
+}
+
+/// Delete a whole subtree of assets, or only report it if `dry_run` is set.
+/// Every room in the subtree is checked for reservations first, and depending on the policy
+/// a single reserved room stops the entire delete or its reservations are cancelled afterwards.
+/// Each deleted asset gets its own entry in the audit log.
+pub async fn cascade_delete(report: DeletionReport, dry_run: bool, policy: ReservationPolicy, req: &HttpRequest) -> HttpResponse {
+
+    let reservations = match blocking_reservations(&report.rooms, policy, req).await {
+        Ok(reservations) => reservations,
+        Err(response) => return response
+    };
+    if !reservations.is_empty() && policy != ReservationPolicy::Cancel {
+        let reserved = reserved_rooms(&reservations);
+        info!("{} rooms in subtree have existing reservations, cannot delete", reserved.len());
+        return HttpResponse::UnprocessableEntity().json(
+            json!({ "message": "rooms have existing reservations", "rooms": reserved })
+        );
+    }
+    info!("{} reservations of rooms in subtree to cancel, ok to delete", reservations.len());
+
+    if dry_run {
+        info!("dry run: would delete {} buildings, {} storeys and {} rooms", report.buildings.len(), report.storeys.len(), report.rooms.len());
+        return HttpResponse::Ok().json(report);
+    }
+
+    // the rooms go first, so nobody can book them while their reservations are cancelled
+    let rooms = report.rooms.clone();
+    let delete = |audit: &Audit, report: &DeletionReport| {
+        if delete_subtree(audit, report) {
+            return Ok(());
+        }
+        error!("subtree changed while deleting, rolled back");
+        Err(HttpResponse::Conflict().json(json!({ "message": "assets changed during delete, try again" })))
+    };
+    match delete_with_reservations(report, rooms, policy, delete, restore_subtree, req).await {
+        Ok(report) => {
+            info!("deleted {} buildings, {} storeys and {} rooms", report.buildings.len(), report.storeys.len(), report.rooms.len());
+            HttpResponse::NoContent().finish()
+        },
+        Err(response) => response
+    }
+
+}
@@ -0,0 +1,133 @@
+use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+
+use diesel::PgConnection;
+use log::error;
+use serde_json::json;
+
+use crate::api::util::{validate_building_attributes, validate_room_attributes};
+use crate::db::crud::buildings_crud::find_building_by_id_with;
+use crate::db::crud::rooms_crud::find_room_by_id_with;
+use crate::db::crud::storeys_crud::{find_storey_by_id_with, level_taken_with};
+use crate::db::models::{Building, OptionalIDBuilding, OptionalIDRoom, OptionalIDStorey, Room, Storey};
+
+// The checks of a create or update, shared by the single endpoints, batches and the GeoJSON import,
+// so a write is accepted or refused the same way whichever way it comes in.
+// They take a connection, so a batch can check against what it has written so far.
+
+/// Why a write was refused, with the status the single endpoints answer with.
+pub struct Rejection {
+    pub status: StatusCode,
+    pub message: String
+}
+
+impl Rejection {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Rejection {
+        Rejection { status, message: message.into() }
+    }
+
+    /// The response of the single endpoints.
+    pub fn response(&self) -> HttpResponse {
+        error!("write refused: {}", self.message);
+        HttpResponse::build(self.status).json(json!({ "message": self.message }))
+    }
+}
+
+/// A building write that passed the checks, with the address worked out
+/// and the attributes completed from the building it replaces.
+pub struct BuildingWrite {
+    pub request: OptionalIDBuilding,
+    pub address: String,
+    pub before: Option<Building>
+}
+
+/// A storey write that passed the checks.
+pub struct StoreyWrite {
+    pub request: OptionalIDStorey,
+    pub before: Option<Storey>
+}
+
+/// A room write that passed the checks, with the attributes completed from the room it replaces.
+pub struct RoomWrite {
+    pub request: OptionalIDRoom,
+    pub before: Option<Room>
+}
+
+/// Assets in the trash only come back through their restore endpoint, not by writing to them.
+fn check_not_trashed<T>(kind: &str, before: &Option<T>, trashed: impl Fn(&T) -> bool) -> Result<(), Rejection> {
+    match before {
+        Some(existing) if trashed(existing) => Err(Rejection::new(StatusCode::CONFLICT, format!("{} with UUID is in the trash, restore it first", kind))),
+        _ => Ok(())
+    }
+}
+
+/// Check the create or update of a building.
+pub fn check_building(conn: &PgConnection, mut building: OptionalIDBuilding) -> Result<BuildingWrite, Rejection> {
+    let before = building.id.and_then(|id| find_building_by_id_with(conn, id, true));
+    check_not_trashed("building", &before, |existing| existing.deleted_at.is_some())?;
+
+    let address = resolve_address(&building, before.as_ref())
+        .ok_or_else(|| Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, "missing address"))?;
+    if let Some(ref existing) = before {
+        building.attributes = building.attributes.or(existing);
+    }
+    if let Some(problem) = validate_building_attributes(&building.attributes) {
+        return Err(Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, problem));
+    }
+    Ok(BuildingWrite { request: building, address, before })
+}
+
+/// Check the create or update of a storey.
+pub fn check_storey(conn: &PgConnection, storey: OptionalIDStorey) -> Result<StoreyWrite, Rejection> {
+    if find_building_by_id_with(conn, storey.building_id, false).is_none() {
+        return Err(Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid building UUID"));
+    }
+
+    let before = storey.id.and_then(|id| find_storey_by_id_with(conn, id, true));
+    check_not_trashed("storey", &before, |existing| existing.deleted_at.is_some())?;
+
+    let storey_level = storey.attributes.level.unwrap_or_else(|| before.as_ref().and_then(|existing| existing.level));
+    if storey_level.is_some_and(|storey_level| level_taken_with(conn, storey.building_id, storey_level, storey.id)) {
+        return Err(Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, "level already exists in building"));
+    }
+    Ok(StoreyWrite { request: storey, before })
+}
+
+/// Check the create or update of a room.
+/// The attributes are checked as they will be stored, e.g. a request may complete the position of the room.
+pub fn check_room(conn: &PgConnection, mut room: OptionalIDRoom) -> Result<RoomWrite, Rejection> {
+    if find_storey_by_id_with(conn, room.storey_id, false).is_none() {
+        return Err(Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid storey UUID"));
+    }
+
+    let before = room.id.and_then(|id| find_room_by_id_with(conn, id, true));
+    check_not_trashed("room", &before, |existing| existing.deleted_at.is_some())?;
+
+    if let Some(ref existing) = before {
+        room.attributes = room.attributes.or(existing);
+    }
+    if let Some(problem) = validate_room_attributes(&room.attributes) {
+        return Err(Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, problem));
+    }
+    Ok(RoomWrite { request: room, before })
+}
+
+/// Work out the free-text address of a building from a request.
+/// An explicit `address` wins, then the structured address of the request
+/// (completed from the existing building), then the address the building already has.
+fn resolve_address(building: &OptionalIDBuilding, before: Option<&Building>) -> Option<String> {
+    if let Some(ref address) = building.address {
+        return Some(address.to_string());
+    }
+    let attributes = &building.attributes;
+    let has_structured_address = [&attributes.street, &attributes.house_number, &attributes.postal_code, &attributes.city, &attributes.country]
+        .iter().any(|field| field.is_some());
+    if has_structured_address {
+        let merged = match before {
+            Some(existing) => attributes.clone().or(existing),
+            None => attributes.clone()
+        };
+        return merged.format_address().or_else(|| before.map(|existing| existing.address.to_string()));
+    }
+    before.map(|existing| existing.address.to_string())
+}
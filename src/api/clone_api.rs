@@ -0,0 +1,135 @@
+use actix_web::{post, HttpRequest, HttpResponse, Responder, web};
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+use log::{info, error};
+use serde_json::json;
+
+use crate::api::audit_api::request_audit;
+use crate::api::auth::validator;
+use crate::api::floorplans_api::copy_plan_file;
+use crate::api::util::validate_uuid;
+use crate::db::crud::buildings_crud::find_building_by_id;
+use crate::db::crud::clone_crud::{clone_building_by_id, clone_storey_by_id};
+use crate::db::crud::storeys_crud::{find_storey_by_id, level_taken, storey_name_taken};
+use crate::db::models::{CloneBuilding, CloneMapping, CloneStorey};
+use crate::openapi::ErrorMessage;
+
+#[utoipa::path(
+    tag = "storeys",
+    params(("id" = Uuid, Path, description = "UUID of the storey")),
+    request_body = CloneStorey,
+    responses(
+        (status = 201, description = "Storey copied with its rooms, equipment and floor plan; UUIDs of the originals mapped to their copies", body = CloneMapping),
+        (status = 400, description = "Invalid UUID or request body", body = ErrorMessage),
+        (status = 404, description = "No storey with the UUID", body = ErrorMessage),
+        (status = 422, description = "Invalid building UUID, or name or level already exists in the building", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/storeys/{id}/clone", wrap="HttpAuthentication::bearer(validator)")]
+async fn clone_storey(id: web::Path<String>, req_body: String, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let target : CloneStorey = match serde_json::from_str(&req_body) {
+        Ok(target) => target,
+        Err(_) => {
+            error!("invalid storey clone request body: {}", req_body);
+            return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+        }
+    };
+
+    let param_id = param_id.unwrap();
+    let source = match find_storey_by_id(param_id, false) {
+        Some(storey) => storey,
+        None => {
+            error!("storey with UUID {} not found", param_id);
+            return HttpResponse::NotFound().json(json!({ "message": "storey with UUID not found" }));
+        }
+    };
+
+    if find_building_by_id(target.building_id, false).is_none() {
+        error!("building with UUID {} does not exist", target.building_id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid building UUID" }));
+    }
+
+    let storey_name = match (target.name, target.rename.as_ref()) {
+        (Some(name), _) => name,
+        (None, Some(pattern)) => pattern.apply(&source.name),
+        (None, None) => source.name.clone()
+    };
+    if storey_name_taken(target.building_id, &storey_name, None) {
+        error!("building {} already has a storey named {}", target.building_id, storey_name);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "storey name already exists in building" }));
+    }
+
+    let storey_level = target.level.or(source.level);
+    if storey_level.is_some_and(|storey_level| level_taken(target.building_id, storey_level, None)) {
+        error!("building {} already has a storey on level {:?}", target.building_id, storey_level);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "level already exists in building" }));
+    }
+
+    match clone_storey_by_id(&request_audit(&req), param_id, target.building_id, storey_name, storey_level, target.rename.as_ref(), copy_plan_file) {
+        Some(cloned) => {
+            info!("cloned storey {} with {} rooms into building {}", param_id, cloned.rooms.len(), target.building_id);
+            HttpResponse::Created().json(cloned.mapping())
+        },
+        None => {
+            error!("storey clone threw an error");
+            HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }))
+        }
+    }
+
+}
+
+#[utoipa::path(
+    tag = "buildings",
+    params(("id" = Uuid, Path, description = "UUID of the building")),
+    request_body = CloneBuilding,
+    responses(
+        (status = 201, description = "Building copied with its storeys, rooms, equipment and floor plans; UUIDs of the originals mapped to their copies", body = CloneMapping),
+        (status = 400, description = "Invalid UUID or request body", body = ErrorMessage),
+        (status = 404, description = "No building with the UUID", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/buildings/{id}/clone", wrap="HttpAuthentication::bearer(validator)")]
+async fn clone_building(id: web::Path<String>, req_body: String, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let target : CloneBuilding = match serde_json::from_str(&req_body) {
+        Ok(target) => target,
+        Err(_) => {
+            error!("invalid building clone request body: {}", req_body);
+            return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+        }
+    };
+
+    let param_id = param_id.unwrap();
+    if find_building_by_id(param_id, false).is_none() {
+        error!("building with UUID {} not found", param_id);
+        return HttpResponse::NotFound().json(json!({ "message": "building with UUID not found" }));
+    }
+
+    match clone_building_by_id(&request_audit(&req), param_id, target.name, target.address, target.rename.as_ref(), copy_plan_file) {
+        Some(cloned) => {
+            info!("cloned building {} with {} storeys and {} rooms", param_id, cloned.storeys.len(), cloned.rooms.len());
+            HttpResponse::Created().json(cloned.mapping())
+        },
+        None => {
+            error!("building clone threw an error");
+            HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }))
+        }
+    }
+
+}
+
@@ -0,0 +1,91 @@
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, EntityTag, HeaderValue, HttpDate, IfModifiedSince, IfNoneMatch};
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use std::env;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use crate::api::geojson_api::wants_geojson;
+use crate::db::crud::changes_crud::collection_version;
+
+/// `Cache-Control` of the asset collections, from `HTTP_CACHE_CONTROL`.
+/// The default lets caches keep a copy, but makes them ask with the ETag before using it.
+fn cache_control() -> &'static str {
+    static CACHE_CONTROL: OnceLock<String> = OnceLock::new();
+    CACHE_CONTROL.get_or_init(|| env::var("HTTP_CACHE_CONTROL").unwrap_or_else(|_| "no-cache".to_string()))
+}
+
+/// Answer a GET of an asset collection with validators, or with 304 Not Modified if the client has it already.
+/// Every committed write to buildings, storeys and rooms, purges of the trash included, counts up the collection
+/// version, which versions all collections; ETags are weak, as the JSON and GeoJSON variants share it.
+/// `respond` gets the version, cached lists must be at least that new to be sent with its ETag.
+pub fn conditional_get(req: &HttpRequest, respond: impl FnOnce(Option<i64>) -> HttpResponse) -> HttpResponse {
+    // read before the collection, a change in between only makes the next request load it again
+    let version = match collection_version() {
+        Some(version) => version,
+        None => return respond(None)
+    };
+    let etag = collection_etag(version.0, wants_geojson(req));
+    let validators = [
+        (header::ETAG, etag.to_string()),
+        (header::LAST_MODIFIED, HttpDate::from(SystemTime::from(version.1)).to_string()),
+        (header::CACHE_CONTROL, cache_control().to_string()),
+        (header::VARY, "Accept".to_string())
+    ];
+
+    let mut response = if is_not_modified(req.get_header::<IfNoneMatch>(), req.get_header::<IfModifiedSince>(), &etag, version.1) {
+        debug!("collection unchanged since {}", etag);
+        HttpResponse::NotModified().finish()
+    } else {
+        respond(Some(version.0))
+    };
+    if response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED {
+        for (name, value) in validators {
+            response.headers_mut().insert(name, HeaderValue::from_str(&value).unwrap());
+        }
+    }
+    response
+}
+
+fn collection_etag(version: i64, geojson: bool) -> EntityTag {
+    EntityTag::new_weak(if geojson { format!("{}-geojson", version) } else { version.to_string() })
+}
+
+/// `If-None-Match` decides if it is there, otherwise `If-Modified-Since`, which only has seconds.
+fn is_not_modified(if_none_match: Option<IfNoneMatch>, if_modified_since: Option<IfModifiedSince>, etag: &EntityTag, modified: DateTime<Utc>) -> bool {
+    match (if_none_match, if_modified_since) {
+        (Some(IfNoneMatch::Any), _) => true,
+        (Some(IfNoneMatch::Items(tags)), _) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        (None, Some(IfModifiedSince(since))) => modified.timestamp() <= DateTime::<Utc>::from(SystemTime::from(since)).timestamp(),
+        (None, None) => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_is_not_modified() {
+        let etag = collection_etag(42, false);
+        let modified = Utc::now();
+        let tags = |tags: &[EntityTag]| Some(IfNoneMatch::Items(tags.to_vec()));
+        let since = |time: DateTime<Utc>| Some(IfModifiedSince(HttpDate::from(SystemTime::from(time))));
+
+        assert!(is_not_modified(tags(&[EntityTag::new_weak("42".to_string())]), None, &etag, modified));
+        assert!(is_not_modified(tags(&[EntityTag::new_strong("41".to_string()), EntityTag::new_strong("42".to_string())]), None, &etag, modified));
+        assert!(!is_not_modified(tags(&[collection_etag(41, false)]), None, &etag, modified));
+        assert!(!is_not_modified(tags(&[collection_etag(42, true)]), None, &etag, modified));
+        assert!(is_not_modified(Some(IfNoneMatch::Any), None, &etag, modified));
+
+        assert!(is_not_modified(None, since(modified), &etag, modified));
+        assert!(!is_not_modified(None, since(modified - Duration::seconds(2)), &etag, modified));
+        // an ETag that does not match wins over a date that does
+        assert!(!is_not_modified(tags(&[collection_etag(41, false)]), since(modified), &etag, modified));
+        assert!(!is_not_modified(None, None, &etag, modified));
+    }
+}
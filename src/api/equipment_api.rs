@@ -0,0 +1,337 @@
+use actix_web::{get, post, put, delete, HttpRequest, HttpResponse, Responder, web};
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+use log::{info, error};
+use serde_json::json;
+
+use crate::api::audit_api::request_audit;
+use crate::api::auth::validator;
+use crate::api::util::{validate_equipment_key, validate_uuid};
+use crate::db::crud::equipment_crud::*;
+use crate::db::crud::rooms_crud::find_room_by_id;
+use crate::db::models::{Equipment, OptionalIDEquipment, OptionalIDRoomEquipment, RoomEquipment};
+use crate::openapi::ErrorMessage;
+
+#[utoipa::path(
+    tag = "equipment",
+    responses(
+        (status = 200, description = "The equipment catalogue", body = [Equipment])
+    )
+)]
+#[get("/equipment")]
+async fn get_equipment_catalogue() -> impl Responder {
+    let catalogue = get_equipment();
+    info!("found {} equipment types", catalogue.len());
+    HttpResponse::Ok().json(catalogue)
+}
+
+#[utoipa::path(
+    tag = "equipment",
+    request_body = OptionalIDEquipment,
+    responses(
+        (status = 201, description = "Equipment type created, or updated if one with the given UUID exists", body = Equipment),
+        (status = 400, description = "Invalid request body", body = ErrorMessage),
+        (status = 422, description = "Invalid or existing equipment key", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/equipment", wrap="HttpAuthentication::bearer(validator)")]
+async fn add_equipment(req_body: String, req: HttpRequest) -> impl Responder {
+
+    let body_content : Result<OptionalIDEquipment, serde_json::Error> = serde_json::from_str(&req_body);
+    if body_content.is_err() {
+        error!("invalid equipment request body: {}", req_body);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+    }
+
+    let new_type = body_content.unwrap();
+    if !validate_equipment_key(&new_type.key) {
+        error!("invalid equipment key: {}", new_type.key);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid equipment key" }));
+    }
+
+    if let Some(saved) = create_or_update_equipment(&request_audit(&req), new_type.id, new_type.key, new_type.name) {
+        info!("equipment {} newly created or updated", saved.id);
+        HttpResponse::Created().json(saved)
+    } else {
+        error!("equipment create/update threw an error");
+        HttpResponse::UnprocessableEntity().json(json!({ "message": "equipment key already exists" }))
+    }
+
+}
+
+#[utoipa::path(
+    tag = "equipment",
+    params(("id" = Uuid, Path, description = "UUID of the equipment type")),
+    request_body = OptionalIDEquipment,
+    responses(
+        (status = 204, description = "Equipment type updated, or created if it did not exist"),
+        (status = 400, description = "Invalid UUID or request body", body = ErrorMessage),
+        (status = 422, description = "UUID in the body does not match, or invalid or existing equipment key", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[put("/equipment/{id}", wrap="HttpAuthentication::bearer(validator)")]
+async fn update_equipment(id: web::Path<String>, req_body: String, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let body_content : Result<OptionalIDEquipment, serde_json::Error> = serde_json::from_str(&req_body);
+    if body_content.is_err() {
+        error!("invalid equipment request body: {}", req_body);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+    }
+
+    let new_type = body_content.unwrap();
+    let param_id = param_id.unwrap();
+    if new_type.id.is_some_and(|body_id| body_id != param_id) {
+        error!("request parameter UUID {} and body UUID {:?} do not match", param_id, new_type.id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "mismatched ID in URL and object" }));
+    }
+
+    if !validate_equipment_key(&new_type.key) {
+        error!("invalid equipment key: {}", new_type.key);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid equipment key" }));
+    }
+
+    if let Some(saved) = create_or_update_equipment(&request_audit(&req), Some(param_id), new_type.key, new_type.name) {
+        info!("equipment {} newly created or updated", saved.id);
+        HttpResponse::NoContent().finish()
+    } else {
+        error!("equipment create/update threw an error");
+        HttpResponse::UnprocessableEntity().json(json!({ "message": "equipment key already exists" }))
+    }
+
+}
+
+#[utoipa::path(
+    tag = "equipment",
+    params(("id" = Uuid, Path, description = "UUID of the equipment type")),
+    responses(
+        (status = 204, description = "Equipment type deleted"),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No equipment type with the UUID"),
+        (status = 422, description = "The equipment is still present in rooms", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[delete("/equipment/{id}", wrap="HttpAuthentication::bearer(validator)")]
+async fn delete_equipment(id: web::Path<String>, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let param_id = param_id.unwrap();
+    if equipment_in_use(param_id) {
+        error!("cannot delete equipment {}, still present in rooms", param_id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "equipment is present in rooms" }));
+    }
+
+    if delete_equipment_by_id(&request_audit(&req), param_id) {
+        info!("deleted equipment {}", param_id);
+        HttpResponse::NoContent().finish()
+    } else {
+        error!("equipment with UUID {} not found", param_id);
+        HttpResponse::NotFound().finish()
+    }
+
+}
+
+#[utoipa::path(
+    tag = "equipment",
+    params(("id" = Uuid, Path, description = "UUID of the room")),
+    responses(
+        (status = 200, description = "Equipment present in the room", body = [RoomEquipment]),
+        (status = 404, description = "No room with the UUID", body = ErrorMessage)
+    )
+)]
+#[get("/rooms/{id}/equipment")]
+async fn get_room_equipment(id: web::Path<String>) -> impl Responder {
+
+    let room_uuid = validate_uuid(id.to_string());
+    if room_uuid.is_none() {
+        error!("failed to parse room UUID: {}", id);
+        return HttpResponse::NotFound().json(json!({ "message": "invalid UUID" }));
+    }
+
+    let room_id = room_uuid.unwrap();
+    if find_room_by_id(room_id, false).is_none() {
+        error!("could not find room with UUID: {}", id);
+        return HttpResponse::NotFound().json(json!({ "message": "room with UUID not found" }));
+    }
+
+    let entries = equipment_of_room(room_id);
+    info!("found {} equipment entries in room {}", entries.len(), room_id);
+    HttpResponse::Ok().json(entries)
+
+}
+
+#[utoipa::path(
+    tag = "equipment",
+    params(("id" = Uuid, Path, description = "UUID of the room")),
+    request_body = OptionalIDRoomEquipment,
+    responses(
+        (status = 201, description = "Equipment entry created, or updated if one with the given UUID exists", body = RoomEquipment),
+        (status = 400, description = "Invalid UUID or request body", body = ErrorMessage),
+        (status = 404, description = "No room with the UUID", body = ErrorMessage),
+        (status = 422, description = "Invalid equipment UUID or quantity, or the entry belongs to another room", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/rooms/{id}/equipment", wrap="HttpAuthentication::bearer(validator)")]
+async fn add_room_equipment(id: web::Path<String>, req_body: String, req: HttpRequest) -> impl Responder {
+
+    let room_uuid = validate_uuid(id.to_string());
+    if room_uuid.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let body_content : Result<OptionalIDRoomEquipment, serde_json::Error> = serde_json::from_str(&req_body);
+    if body_content.is_err() {
+        error!("invalid room equipment request body: {}", req_body);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+    }
+
+    let entry = body_content.unwrap();
+    let room_id = room_uuid.unwrap();
+    if let Some(response) = check_room_equipment(room_id, &entry) {
+        return response;
+    }
+
+    let before = entry.id.and_then(find_room_equipment_by_id);
+    if before.as_ref().is_some_and(|existing| existing.room_id != room_id) {
+        error!("room equipment {:?} belongs to another room", entry.id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "equipment entry belongs to another room" }));
+    }
+
+    if let Some(saved) = create_or_update_room_equipment(&request_audit(&req), entry.id, room_id, entry.equipment_id, entry.quantity, entry.serial_number) {
+        info!("room equipment {} newly created or updated", saved.id);
+        HttpResponse::Created().json(saved)
+    } else {
+        error!("room equipment create/update threw an error");
+        HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }))
+    }
+
+}
+
+#[utoipa::path(
+    tag = "equipment",
+    params(("id" = Uuid, Path, description = "UUID of the room"), ("entry_id" = Uuid, Path, description = "UUID of the equipment entry")),
+    request_body = OptionalIDRoomEquipment,
+    responses(
+        (status = 204, description = "Equipment entry updated, or created if it did not exist"),
+        (status = 400, description = "Invalid UUIDs or request body", body = ErrorMessage),
+        (status = 404, description = "No room with the UUID", body = ErrorMessage),
+        (status = 422, description = "UUID in the body does not match, invalid equipment UUID or quantity, or the entry belongs to another room", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[put("/rooms/{id}/equipment/{entry_id}", wrap="HttpAuthentication::bearer(validator)")]
+async fn update_room_equipment(path: web::Path<(String, String)>, req_body: String, req: HttpRequest) -> impl Responder {
+
+    let (id, entry_id) = path.into_inner();
+    let (room_uuid, entry_uuid) = (validate_uuid(id.to_string()), validate_uuid(entry_id.to_string()));
+    if room_uuid.is_none() || entry_uuid.is_none() {
+        error!("invalid param UUIDs: {} {}", id, entry_id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let body_content : Result<OptionalIDRoomEquipment, serde_json::Error> = serde_json::from_str(&req_body);
+    if body_content.is_err() {
+        error!("invalid room equipment request body: {}", req_body);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+    }
+
+    let entry = body_content.unwrap();
+    let (room_id, entry_id) = (room_uuid.unwrap(), entry_uuid.unwrap());
+    if entry.id.is_some_and(|body_id| body_id != entry_id) {
+        error!("request parameter UUID {} and body UUID {:?} do not match", entry_id, entry.id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "mismatched ID in URL and object" }));
+    }
+
+    if let Some(response) = check_room_equipment(room_id, &entry) {
+        return response;
+    }
+
+    let before = find_room_equipment_by_id(entry_id);
+    if before.as_ref().is_some_and(|existing| existing.room_id != room_id) {
+        error!("room equipment {} belongs to another room", entry_id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "equipment entry belongs to another room" }));
+    }
+
+    if let Some(saved) = create_or_update_room_equipment(&request_audit(&req), Some(entry_id), room_id, entry.equipment_id, entry.quantity, entry.serial_number) {
+        info!("room equipment {} newly created or updated", saved.id);
+        HttpResponse::NoContent().finish()
+    } else {
+        error!("room equipment create/update threw an error");
+        HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }))
+    }
+
+}
+
+#[utoipa::path(
+    tag = "equipment",
+    params(("id" = Uuid, Path, description = "UUID of the room"), ("entry_id" = Uuid, Path, description = "UUID of the equipment entry")),
+    responses(
+        (status = 204, description = "Equipment entry deleted"),
+        (status = 400, description = "Invalid UUIDs", body = ErrorMessage),
+        (status = 404, description = "No equipment entry with the UUID in the room")
+    ),
+    security(("bearer" = []))
+)]
+#[delete("/rooms/{id}/equipment/{entry_id}", wrap="HttpAuthentication::bearer(validator)")]
+async fn delete_room_equipment(path: web::Path<(String, String)>, req: HttpRequest) -> impl Responder {
+
+    let (id, entry_id) = path.into_inner();
+    let (room_uuid, entry_uuid) = (validate_uuid(id.to_string()), validate_uuid(entry_id.to_string()));
+    if room_uuid.is_none() || entry_uuid.is_none() {
+        error!("invalid param UUIDs: {} {}", id, entry_id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let (room_id, entry_id) = (room_uuid.unwrap(), entry_uuid.unwrap());
+    if find_room_equipment_by_id(entry_id).is_none_or(|existing| existing.room_id != room_id) {
+        error!("room equipment {} not found in room {}", entry_id, room_id);
+        return HttpResponse::NotFound().finish();
+    }
+
+    if delete_room_equipment_by_id(&request_audit(&req), entry_id) {
+        info!("deleted room equipment {}", entry_id);
+        HttpResponse::NoContent().finish()
+    } else {
+        error!("room equipment with UUID {} not found", entry_id);
+        HttpResponse::NotFound().finish()
+    }
+
+}
+
+/// Validate a room equipment entry before it is saved.
+/// Return the error response to send, or None if the entry is fine.
+fn check_room_equipment(room_id: uuid::Uuid, entry: &OptionalIDRoomEquipment) -> Option<HttpResponse> {
+
+    if find_room_by_id(room_id, false).is_none() {
+        error!("room with UUID {} does not exist", room_id);
+        return Some(HttpResponse::NotFound().json(json!({ "message": "room with UUID not found" })));
+    }
+
+    if find_equipment_by_id(entry.equipment_id).is_none() {
+        error!("equipment with UUID {} does not exist", entry.equipment_id);
+        return Some(HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid equipment UUID" })));
+    }
+
+    if entry.quantity < 1 || (entry.serial_number.is_some() && entry.quantity != 1) {
+        error!("invalid quantity {} for room equipment", entry.quantity);
+        return Some(HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid quantity" })));
+    }
+
+    None
+
+}
@@ -0,0 +1,130 @@
+use actix_web::{get, HttpRequest, HttpResponse, Responder, web};
+use actix_web::web::Bytes;
+
+use futures_util::stream;
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::db::crud::changes_crud::{change_position, changes_after, latest_position, oldest_change_id};
+use crate::db::models::{AssetChange, StreamPosition};
+use crate::dbconn::connection;
+use crate::events::{changes, concerns, sse_event, sse_keepalive};
+use crate::openapi::ErrorMessage;
+
+/// Changes loaded at once when a stream resumes.
+const RESUME_PAGE: i64 = 500;
+/// Idle streams get a comment this often.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize, IntoParams)]
+/// Query parameters for the change stream, `building_id` only streams changes within that building.
+pub struct QueryEvents {
+    building_id: Option<Uuid>,
+    // for clients that cannot send the `Last-Event-ID` header
+    last_event_id: Option<i64>
+}
+
+/// Where a stream stands: `resume_after` is the position of the last change it has sent, or skipped because it was
+/// not after `Last-Event-ID`. While catching up the changes come from the database, afterwards from the channel.
+struct EventStream {
+    receiver: Receiver<Arc<AssetChange>>,
+    building: Option<Uuid>,
+    backlog: VecDeque<AssetChange>,
+    resume_after: StreamPosition,
+    catching_up: bool
+}
+
+#[utoipa::path(
+    tag = "events",
+    params(
+        QueryEvents,
+        ("Last-Event-ID" = Option<i64>, Header, description = "ID of the last change received, the stream resumes after it")
+    ),
+    responses(
+        (status = 200, description = "Server-sent events, one per change, `data` is an `AssetChange`", body = String, content_type = "text/event-stream"),
+        (status = 400, description = "`Last-Event-ID` is not the ID of a change", body = ErrorMessage)
+    )
+)]
+#[get("/events")]
+async fn get_events(query: web::Query<QueryEvents>, req: HttpRequest) -> impl Responder {
+
+    let last_event_id = match req.headers().get("Last-Event-ID") {
+        Some(value) => match value.to_str().ok().and_then(|value| value.trim().parse::<i64>().ok()) {
+            Some(id) => Some(id),
+            None => return HttpResponse::BadRequest().json(json!({ "message": "invalid Last-Event-ID" }))
+        },
+        None => query.last_event_id
+    };
+    info!("streaming asset changes{}", last_event_id.map(|id| format!(" after {}", id)).unwrap_or_default());
+
+    // subscribe before looking at the database, so nothing falls between the two
+    let receiver = changes().subscribe();
+    let conn = connection().unwrap();
+    let resume_after = match last_event_id {
+        Some(id) => match change_position(&conn, id) {
+            Some(position) => position,
+            // a stream resumed after a change that is not kept anymore starts with the oldest one that is,
+            // an ID that never was a change would replay everything, so it is refused
+            None if oldest_change_id(&conn).is_none_or(|oldest| id < oldest) => (0, 0),
+            None => return HttpResponse::BadRequest().json(json!({ "message": "unknown Last-Event-ID" }))
+        },
+        None => latest_position(&conn)
+    };
+    let state = EventStream {
+        receiver,
+        building: query.building_id,
+        backlog: VecDeque::new(),
+        resume_after,
+        catching_up: last_event_id.is_some()
+    };
+
+    let events = stream::unfold(state, |mut state| async move {
+        let event = next_event(&mut state).await?;
+        Some((Ok::<Bytes, Infallible>(event), state))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // keeps nginx style proxies from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events)
+}
+
+/// Wait for the next event of the stream, None ends it.
+async fn next_event(state: &mut EventStream) -> Option<Bytes> {
+    loop {
+        if let Some(change) = state.backlog.pop_front() {
+            state.resume_after = change.position();
+            return Some(sse_event(&change));
+        }
+
+        if state.catching_up {
+            let page = changes_after(&connection().unwrap(), state.resume_after, state.building, RESUME_PAGE);
+            state.catching_up = page.len() as i64 == RESUME_PAGE;
+            state.backlog.extend(page);
+            continue;
+        }
+
+        match tokio::time::timeout(KEEPALIVE, state.receiver.recv()).await {
+            // changes up to `resume_after` were already sent from the database
+            Ok(Ok(change)) if change.position() > state.resume_after && concerns(&change, state.building) => {
+                state.resume_after = change.position();
+                return Some(sse_event(&change));
+            },
+            Ok(Ok(_)) => continue,
+            // the stream fell behind the channel, pick up the missed changes from the database
+            Ok(Err(RecvError::Lagged(_))) => state.catching_up = true,
+            Ok(Err(RecvError::Closed)) => return None,
+            Err(_) => return Some(sse_keepalive())
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use actix_web::{get, HttpResponse, Responder, web};
+use actix_web::web::Bytes;
+
+use log::{info, error};
+use serde_json::json;
+use std::io::{self, Write};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::db::crud::export_crud::export_assets;
+use crate::db::models::ExportFilter;
+use crate::dbconn::connection;
+use crate::export::{ExportFormat, ExportWriter};
+use crate::jobs::env_or_default;
+use crate::openapi::ErrorMessage;
+
+/// Size of the chunks sent to the client.
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Number of chunks that may be waiting for a slow client before the export pauses.
+const EXPORT_CHANNEL_CAPACITY: usize = 16;
+
+/// Exports that may run at the same time, each holds a blocking thread and a database connection.
+/// Can be changed with `EXPORT_MAX_CONCURRENT`.
+fn export_slots() -> &'static Arc<Semaphore> {
+    static SLOTS: OnceLock<Arc<Semaphore>> = OnceLock::new();
+    SLOTS.get_or_init(|| Arc::new(Semaphore::new(env_or_default("EXPORT_MAX_CONCURRENT", 4) as usize)))
+}
+
+/// Writer that hands its output to the response stream in chunks.
+/// The channel is bounded, so the export only runs as fast as the client reads.
+struct ChannelWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= EXPORT_CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        self.sender.blocking_send(Ok(chunk)).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+/// Ends the response with an error unless the export completed, also if the export panicked.
+/// Without it the stream would simply end and the client could take a truncated export for a complete one.
+struct ExportOutcome {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    complete: bool
+}
+
+impl Drop for ExportOutcome {
+    fn drop(&mut self) {
+        if !self.complete {
+            let _ = self.sender.blocking_send(Err(io::Error::other("export failed")));
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "export",
+    params(ExportFilter),
+    responses(
+        (status = 200, description = "The asset hierarchy in the requested format, streamed as an attachment",
+            content(
+                (String = "text/csv"),
+                (String = "application/jsonl"),
+                (String = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+            )),
+        (status = 400, description = "Unknown format, or `as_of` was given", body = ErrorMessage),
+        (status = 503, description = "Too many exports running, or the database is unavailable", body = ErrorMessage)
+    )
+)]
+#[get("/export")]
+async fn get_export(filter: web::Query<ExportFilter>) -> impl Responder {
+
+    if filter.as_of.is_some() {
+        error!("export requested with as_of");
+        return HttpResponse::BadRequest().json(json!({ "message": "as_of is not supported for exports" }));
+    }
+
+    let format = match ExportFormat::parse(filter.format.as_deref()) {
+        Some(format) => format,
+        None => {
+            error!("unknown export format: {:?}", filter.format);
+            return HttpResponse::BadRequest().json(json!({ "message": "format must be csv, jsonl or xlsx" }));
+        }
+    };
+
+    let slot = match export_slots().clone().try_acquire_owned() {
+        Ok(slot) => slot,
+        Err(_) => {
+            error!("too many exports running");
+            return HttpResponse::ServiceUnavailable().json(json!({ "message": "too many exports running, try again later" }));
+        }
+    };
+    // taken before the response starts, so a missing connection is still an error status
+    let conn = match connection() {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("no database connection for export: {}", e);
+            return HttpResponse::ServiceUnavailable().json(json!({ "message": "database unavailable" }));
+        }
+    };
+
+    // the database queries and the writers are blocking, so the export runs on the blocking thread pool;
+    // its outcome reaches the client through the stream, the handle is not awaited
+    let (sender, receiver) = mpsc::channel::<io::Result<Bytes>>(EXPORT_CHANNEL_CAPACITY);
+    let filter = filter.into_inner();
+    drop(web::block(move || {
+        let _slot = slot;
+        let mut outcome = ExportOutcome { sender: sender.clone(), complete: false };
+        let channel = ChannelWriter { sender, buffer: Vec::with_capacity(EXPORT_CHUNK_SIZE) };
+        let mut writer = match ExportWriter::new(format, channel) {
+            Ok(writer) => writer,
+            Err(e) => return error!("could not start export: {}", e)
+        };
+        match export_assets(&conn, &filter, |row| writer.write_row(&row)) {
+            Some(count) => match writer.finish() {
+                Ok(()) => {
+                    outcome.complete = true;
+                    info!("exported {} rows as {}", count, format.extension())
+                },
+                Err(e) => error!("could not finish export: {}", e)
+            },
+            None => error!("export failed, the response is incomplete")
+        }
+    }));
+
+    let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk.map_err(actix_web::Error::from), receiver))
+    });
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"assets.{}\"", format.extension())))
+        .streaming(body)
+}
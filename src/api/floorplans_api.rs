@@ -0,0 +1,290 @@
+use actix_web::{get, put, delete, HttpRequest, HttpResponse, Responder, web};
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+use chrono::Utc;
+use log::{info, error};
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::fs;
+use std::io;
+use utoipa::IntoParams;
+
+use crate::api::audit_api::request_audit;
+use crate::api::auth::validator;
+use crate::api::util::{read_payload, validate_uuid};
+use crate::db::crud::floor_plans_crud::*;
+use crate::db::crud::rooms_crud::rooms_by_storey;
+use crate::db::crud::storeys_crud::find_storey_by_id;
+use crate::db::models::FloorPlan;
+use crate::floorplan::{self, Outline};
+use crate::openapi::ErrorMessage;
+
+/// Largest floor plan accepted by default, can be changed with `FLOOR_PLAN_MAX_BYTES`.
+const DEFAULT_MAX_PLAN_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(Debug, Deserialize, IntoParams)]
+/// Query parameters for the floor plan overlay.
+/// `with_plan` draws the floor plan itself below the room outlines.
+pub struct QueryOverlay {
+    with_plan: Option<bool>
+}
+
+#[utoipa::path(
+    tag = "floor plans",
+    params(("id" = Uuid, Path, description = "UUID of the storey")),
+    request_body(content(
+        (String = "image/svg+xml"),
+        (String = "image/png")
+    ), description = "The floor plan image, replaces the current one"),
+    responses(
+        (status = 201, description = "Floor plan stored", body = FloorPlan),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No storey with the UUID", body = ErrorMessage),
+        (status = 413, description = "The image is larger than `FLOOR_PLAN_MAX_BYTES`", body = ErrorMessage),
+        (status = 415, description = "Neither SVG nor PNG", body = ErrorMessage),
+        (status = 422, description = "The size of the image cannot be read", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[put("/storeys/{id}/floorplan", wrap="HttpAuthentication::bearer(validator)")]
+async fn upload_floor_plan(id: web::Path<String>, mut body: web::Payload, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let param_id = param_id.unwrap();
+    if find_storey_by_id(param_id, false).is_none() {
+        error!("storey with UUID {} not found", param_id);
+        return HttpResponse::NotFound().json(json!({ "message": "storey with UUID not found" }));
+    }
+
+    let content_type = req.headers().get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(';').next().unwrap_or_default().trim().to_lowercase())
+        .unwrap_or_default();
+    if content_type != floorplan::SVG && content_type != floorplan::PNG {
+        error!("unsupported floor plan type: {}", content_type);
+        return HttpResponse::UnsupportedMediaType().json(json!({ "message": "floor plan must be image/svg+xml or image/png" }));
+    }
+
+    let max_size = env::var("FLOOR_PLAN_MAX_BYTES").ok().and_then(|size| size.parse().ok()).unwrap_or(DEFAULT_MAX_PLAN_SIZE);
+    let data = match read_payload(&mut body, max_size).await {
+        Ok(data) => data,
+        Err(response) => return response
+    };
+
+    let (width, height) = match floorplan::dimensions(&content_type, &data) {
+        Some(size) => size,
+        None => {
+            error!("could not read the size of the floor plan for storey {}", param_id);
+            return HttpResponse::UnprocessableEntity().json(json!({ "message": "floor plan has no readable size" }));
+        }
+    };
+
+    let mut plan = FloorPlan {
+        storey_id: param_id,
+        content_type: content_type.clone(),
+        width,
+        height,
+        data: None,
+        path: None,
+        uploaded_at: Utc::now()
+    };
+
+    // every upload gets a file of its own, written under a temporary name until the row is committed,
+    // so a failed upload keeps the old plan and concurrent uploads of a storey never share a file
+    let mut upload = None;
+    match floorplan::plan_dir() {
+        Some(dir) => {
+            match floorplan::StagedPlanFile::write(&dir, floorplan::extension(&content_type), &data) {
+                Ok(staged) => {
+                    plan.path = Some(staged.file_name.clone());
+                    upload = Some(staged);
+                },
+                Err(e) => {
+                    error!("could not write floor plan file of storey {}: {}", param_id, e);
+                    return HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }));
+                }
+            }
+        },
+        None => plan.data = Some(data)
+    }
+
+    let (saved, before) = match save_floor_plan(&request_audit(&req), plan) {
+        Some(saved) => saved,
+        None => {
+            error!("floor plan upload threw an error");
+            if let Some(staged) = upload {
+                staged.discard();
+            }
+            return HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }));
+        }
+    };
+
+    // the plan is committed at this point, a file that cannot be moved is logged but does not undo the upload
+    if let Some(staged) = upload {
+        let file_name = staged.file_name.clone();
+        if let Err(e) = staged.commit() {
+            error!("could not move uploaded floor plan to {}: {}", file_name, e);
+        }
+    }
+    if let Some(old_path) = before.and_then(|old| old.path) {
+        remove_plan_file(&old_path);
+    }
+    info!("floor plan of storey {} uploaded, {} x {}", param_id, width, height);
+    HttpResponse::Created().json(saved)
+
+}
+
+#[utoipa::path(
+    tag = "floor plans",
+    params(("id" = Uuid, Path, description = "UUID of the storey")),
+    responses(
+        (status = 200, description = "The floor plan image, as a download", content(
+            (String = "image/svg+xml"),
+            (String = "image/png")
+        )),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "The storey has no floor plan", body = ErrorMessage)
+    )
+)]
+#[get("/storeys/{id}/floorplan")]
+async fn get_floor_plan(id: web::Path<String>) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let plan = match find_floor_plan(param_id.unwrap()) {
+        Some(plan) => plan,
+        None => {
+            error!("no floor plan for storey {}", id);
+            return HttpResponse::NotFound().json(json!({ "message": "storey has no floor plan" }));
+        }
+    };
+
+    let data = match (plan.data, plan.path) {
+        (Some(data), _) => data,
+        (None, Some(path)) => match floorplan::plan_dir().map(|dir| fs::read(dir.join(&path))) {
+            Some(Ok(data)) => data,
+            _ => {
+                error!("could not read floor plan file {}", path);
+                return HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }));
+            }
+        },
+        (None, None) => Vec::new()
+    };
+
+    // SVG plans are uploaded by users and may carry scripts, they must never run on the origin of the API:
+    // the sandbox keeps them from doing anything if the plan is opened directly, and it is downloaded instead anyway.
+    // Embedding the plan as an image, as the overlay does, is not affected.
+    let file_name = format!("{}.{}", id, floorplan::extension(&plan.content_type));
+    info!("serving floor plan of storey {}", id);
+    HttpResponse::Ok()
+        .content_type(plan.content_type)
+        .insert_header((header::CONTENT_SECURITY_POLICY, "sandbox; default-src 'none'"))
+        .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(file_name)]
+        })
+        .body(data)
+}
+
+#[utoipa::path(
+    tag = "floor plans",
+    params(("id" = Uuid, Path, description = "UUID of the storey")),
+    responses(
+        (status = 204, description = "Floor plan deleted"),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "The storey has no floor plan")
+    ),
+    security(("bearer" = []))
+)]
+#[delete("/storeys/{id}/floorplan", wrap="HttpAuthentication::bearer(validator)")]
+async fn delete_floor_plan_of_storey(id: web::Path<String>, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let param_id = param_id.unwrap();
+    if let Some(plan) = delete_floor_plan(&request_audit(&req), param_id) {
+        if let Some(ref path) = plan.path {
+            remove_plan_file(path);
+        }
+        info!("deleted floor plan of storey {}", param_id);
+        HttpResponse::NoContent().finish()
+    } else {
+        error!("no floor plan for storey {}", param_id);
+        HttpResponse::NotFound().finish()
+    }
+
+}
+
+#[utoipa::path(
+    tag = "floor plans",
+    params(("id" = Uuid, Path, description = "UUID of the storey"), QueryOverlay),
+    responses(
+        (status = 200, description = "SVG with the outlines of the rooms in floor-plan coordinates", body = String, content_type = "image/svg+xml"),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "The storey has no floor plan", body = ErrorMessage)
+    )
+)]
+#[get("/storeys/{id}/floorplan/overlay")]
+async fn get_floor_plan_overlay(id: web::Path<String>, param: web::Query<QueryOverlay>) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let param_id = param_id.unwrap();
+    let plan = match find_floor_plan(param_id) {
+        Some(plan) => plan,
+        None => {
+            error!("no floor plan for storey {}", param_id);
+            return HttpResponse::NotFound().json(json!({ "message": "storey has no floor plan" }));
+        }
+    };
+
+    let outlines : Vec<Outline> = rooms_by_storey(param_id, false)
+        .into_iter()
+        .filter_map(|room| {
+            let points = room.plan_polygon.as_ref().and_then(floorplan::parse_polygon)?;
+            Some(Outline { room_id: room.id, label: room.name, points })
+        })
+        .collect();
+
+    let plan_href = format!("/assets/storeys/{}/floorplan", param_id);
+    let with_plan = param.with_plan.unwrap_or(false);
+    info!("rendering overlay with {} rooms for storey {}", outlines.len(), param_id);
+    HttpResponse::Ok()
+        .content_type(floorplan::SVG)
+        .body(floorplan::overlay_svg(plan.width, plan.height, if with_plan { Some(&plan_href) } else { None }, &outlines))
+}
+
+/// Remove a floor plan file that is no longer referenced, failures are only logged.
+fn remove_plan_file(path: &str) {
+    if let Some(dir) = floorplan::plan_dir() {
+        if let Err(e) = fs::remove_file(dir.join(path)) {
+            error!("could not remove floor plan file {}: {}", path, e);
+        }
+    }
+}
+
+/// Copy a floor plan file for another storey, the copy is committed along with the floor plan of that storey.
+pub(crate) fn copy_plan_file(path: &str) -> io::Result<floorplan::StagedPlanFile> {
+    let dir = floorplan::plan_dir().ok_or_else(|| io::Error::other("FLOOR_PLAN_DIR is not set"))?;
+    floorplan::StagedPlanFile::copy(&dir, path)
+}
@@ -0,0 +1,221 @@
+use actix_web::{post, HttpRequest, HttpResponse, Responder};
+use actix_web::http::header;
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+use diesel::result::Error;
+use log::{info, error};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::api::audit_api::request_audit;
+use crate::api::auth::validator;
+use crate::api::checks::{check_building, check_room, BuildingWrite, Rejection, RoomWrite};
+use crate::cache::invalidate_all;
+use crate::db::crud::buildings_crud::create_or_update_building_with;
+use crate::db::crud::rooms_crud::create_or_update_room_with;
+use crate::db::models::{OptionalIDBuilding, OptionalIDRoom};
+use crate::dbconn::transaction;
+use crate::geo;
+use crate::openapi::ErrorMessage;
+
+/// Media type of GeoJSON documents, see RFC 7946.
+pub const GEOJSON: &str = "application/geo+json";
+
+/// Check if the client asked for GeoJSON in the `Accept` header.
+pub fn wants_geojson(req: &HttpRequest) -> bool {
+    req.headers().get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains(GEOJSON))
+}
+
+/// Turn an asset into a GeoJSON Feature, `kind` ends up in the `asset` property.
+/// The footprint becomes the geometry if there is one, otherwise the position as a Point.
+/// Everything else stays in the properties, so the feature can be imported again.
+pub fn asset_feature<T: Serialize>(kind: &str, asset: &T) -> Value {
+    let mut properties = match serde_json::to_value(asset) {
+        Ok(Value::Object(properties)) => properties,
+        _ => Map::new()
+    };
+
+    let geometry = match properties.remove("footprint") {
+        Some(footprint) if !footprint.is_null() => footprint,
+        _ => match (properties.get("latitude").and_then(Value::as_f64), properties.get("longitude").and_then(Value::as_f64)) {
+            (Some(lat), Some(lon)) => {
+                properties.remove("latitude");
+                properties.remove("longitude");
+                geo::point(lat, lon)
+            },
+            _ => Value::Null
+        }
+    };
+
+    properties.insert("asset".to_string(), json!(kind));
+    json!({
+        "type": "Feature",
+        "id": properties.get("id").cloned().unwrap_or(Value::Null),
+        "geometry": geometry,
+        "properties": properties
+    })
+}
+
+/// Respond with a GeoJSON FeatureCollection of the given features.
+pub fn feature_collection(features: Vec<Value>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(GEOJSON)
+        .body(json!({ "type": "FeatureCollection", "features": features }).to_string())
+}
+
+/// An asset from an imported feature, it is checked right before it is written.
+enum ImportedAsset {
+    Building(OptionalIDBuilding),
+    Room(OptionalIDRoom)
+}
+
+/// Why an import was rolled back.
+enum ImportError {
+    Feature(usize, Rejection),
+    Database(Error)
+}
+
+impl From<Error> for ImportError {
+    fn from(e: Error) -> Self {
+        ImportError::Database(e)
+    }
+}
+
+/// Read an asset from a GeoJSON Feature, the inverse of `asset_feature`.
+/// A Point geometry sets the position, a Polygon or MultiPolygon the footprint.
+fn asset_from_feature(feature: &Value) -> Result<ImportedAsset, String> {
+    let mut properties = match feature.get("properties") {
+        Some(Value::Object(properties)) => properties.clone(),
+        _ => return Err("feature without properties".to_string())
+    };
+
+    if !properties.contains_key("id") {
+        if let Some(id) = feature.get("id").filter(|id| id.is_string()) {
+            properties.insert("id".to_string(), id.clone());
+        }
+    }
+
+    match feature.get("geometry") {
+        None | Some(Value::Null) => (),
+        Some(geometry) => match geometry.get("type").and_then(Value::as_str) {
+            Some("Point") => {
+                let (lat, lon) = geo::point_position(geometry).ok_or("invalid Point geometry")?;
+                properties.insert("latitude".to_string(), json!(lat));
+                properties.insert("longitude".to_string(), json!(lon));
+            },
+            Some("Polygon") | Some("MultiPolygon") => {
+                properties.insert("footprint".to_string(), geometry.clone());
+            },
+            _ => return Err("geometry must be a Point, Polygon or MultiPolygon".to_string())
+        }
+    }
+
+    let kind = properties.remove("asset").and_then(|kind| kind.as_str().map(|kind| kind.to_string()));
+    match kind.as_deref() {
+        Some("building") => {
+            let request : OptionalIDBuilding = serde_json::from_value(Value::Object(properties)).map_err(|e| e.to_string())?;
+            Ok(ImportedAsset::Building(request))
+        },
+        Some("room") => {
+            let request : OptionalIDRoom = serde_json::from_value(Value::Object(properties)).map_err(|e| e.to_string())?;
+            Ok(ImportedAsset::Room(request))
+        },
+        _ => Err("property `asset` must be `building` or `room`".to_string())
+    }
+}
+
+/// Create or update buildings and rooms from a GeoJSON FeatureCollection, e.g. exported from QGIS.
+/// All features are read before anything is written, so a feature that cannot be read rejects the whole import.
+/// They are written in a single transaction and each is checked right before it is written,
+/// against the assets as the import has left them so far; a refused or failed write leaves nothing of it behind.
+#[utoipa::path(
+    tag = "geojson",
+    request_body(content = serde_json::Value, description = "FeatureCollection of buildings and rooms, the `asset` property tells which", content_type = "application/geo+json"),
+    responses(
+        (status = 200, description = "Written features with their asset type, UUID and whether they were created or updated", body = [serde_json::Value]),
+        (status = 400, description = "Not a GeoJSON FeatureCollection", body = ErrorMessage),
+        (status = 422, description = "Invalid features, nothing was imported; `errors` lists them by index", body = serde_json::Value),
+        (status = 500, description = "Writing a feature failed, nothing was imported", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/geojson", wrap="HttpAuthentication::bearer(validator)")]
+async fn import_geojson(req_body: String, req: HttpRequest) -> impl Responder {
+
+    let collection : Value = match serde_json::from_str(&req_body) {
+        Ok(collection) => collection,
+        Err(_) => {
+            error!("invalid GeoJSON request body");
+            return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+        }
+    };
+
+    let features = match (collection.get("type").and_then(Value::as_str), collection.get("features").and_then(Value::as_array)) {
+        (Some("FeatureCollection"), Some(features)) => features,
+        _ => {
+            error!("GeoJSON import is not a FeatureCollection");
+            return HttpResponse::BadRequest().json(json!({ "message": "expected a GeoJSON FeatureCollection" }));
+        }
+    };
+
+    let mut assets = Vec::new();
+    let mut problems = Vec::new();
+    for (index, feature) in features.iter().enumerate() {
+        match asset_from_feature(feature) {
+            Ok(asset) => assets.push(asset),
+            Err(message) => problems.push(json!({ "feature": index, "message": message }))
+        }
+    }
+    if !problems.is_empty() {
+        error!("rejected GeoJSON import, {} of {} features are invalid", problems.len(), features.len());
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid features", "errors": problems }));
+    }
+
+    let audit = request_audit(&req);
+    let mut imported = Vec::new();
+    let mut written = Vec::new();
+    let outcome = transaction::<_, ImportError, _>(|conn| {
+        for (index, asset) in assets.into_iter().enumerate() {
+            let result = match asset {
+                ImportedAsset::Building(request) => {
+                    let BuildingWrite { request, address, before } = check_building(conn, request).map_err(|e| ImportError::Feature(index, e))?;
+                    create_or_update_building_with(conn, &audit, request.id, request.name, address, request.attributes).map(|building| {
+                        written.push(("building", building.id));
+                        let action = if before.is_some() { "update" } else { "create" };
+                        json!({ "feature": index, "asset": "building", "id": building.id, "action": action })
+                    })
+                },
+                ImportedAsset::Room(request) => {
+                    let RoomWrite { request, before } = check_room(conn, request).map_err(|e| ImportError::Feature(index, e))?;
+                    create_or_update_room_with(conn, &audit, request.id, request.name, request.storey_id, request.attributes).map(|room| {
+                        written.push(("room", room.id));
+                        let action = if before.is_some() { "update" } else { "create" };
+                        json!({ "feature": index, "asset": "room", "id": room.id, "action": action })
+                    })
+                }
+            };
+            imported.push(result.ok_or(Error::RollbackTransaction)?);
+        }
+        Ok(())
+    });
+
+    match outcome {
+        Ok(()) => {
+            info!("imported {} features from GeoJSON", imported.len());
+            invalidate_all(written);
+            HttpResponse::Ok().json(imported)
+        },
+        Err(ImportError::Feature(index, e)) => {
+            error!("rejected GeoJSON import at feature {}, nothing was imported: {}", index, e.message);
+            let problems = [json!({ "feature": index, "message": e.message })];
+            HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid features", "errors": problems }))
+        },
+        Err(ImportError::Database(e)) => {
+            // the feature that failed is the one after the last written
+            error!("GeoJSON import failed at feature {}, nothing was imported: {}", imported.len(), e);
+            HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O", "feature": imported.len() }))
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use actix_web::{post, HttpRequest, HttpResponse, Responder, web};
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+use log::{info, error};
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use utoipa::IntoParams;
+
+use crate::api::audit_api::request_audit;
+use crate::api::auth::validator;
+use crate::api::util::read_payload;
+use crate::db::crud::import_crud::ImportReport;
+use crate::import::{run_import, ImportPolicy};
+use crate::openapi::ErrorMessage;
+
+/// Largest import file accepted by default, can be changed with `IMPORT_MAX_BYTES`.
+const DEFAULT_MAX_IMPORT_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(Debug, Deserialize, IntoParams)]
+/// Query parameters for the bulk import.
+/// `dry_run` only reports what would happen, `policy` is `upsert` (default) or `create_only`.
+pub struct QueryImport {
+    dry_run: Option<bool>,
+    policy: Option<ImportPolicy>
+}
+
+#[utoipa::path(
+    tag = "import",
+    params(QueryImport),
+    request_body(content = String, description = "CSV file with a header row, one building, storey and room per row", content_type = "text/csv"),
+    responses(
+        (status = 200, description = "Import report, the changes are committed unless it is a dry run", body = ImportReport),
+        (status = 400, description = "The file cannot be read as CSV", body = ErrorMessage),
+        (status = 413, description = "The file is larger than `IMPORT_MAX_BYTES`", body = ErrorMessage),
+        (status = 422, description = "Rows have errors, nothing was imported", body = ImportReport)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/import", wrap="HttpAuthentication::bearer(validator)")]
+async fn import_assets(mut body: web::Payload, param: web::Query<QueryImport>, req: HttpRequest) -> impl Responder {
+
+    let max_size = env::var("IMPORT_MAX_BYTES").ok().and_then(|size| size.parse().ok()).unwrap_or(DEFAULT_MAX_IMPORT_SIZE);
+    let data = match read_payload(&mut body, max_size).await {
+        Ok(data) => data,
+        Err(response) => return response
+    };
+
+    let policy = param.policy.unwrap_or(ImportPolicy::Upsert);
+    let dry_run = param.dry_run.unwrap_or(false);
+
+    match run_import(&request_audit(&req), &data, policy, dry_run) {
+        Ok(report) if report.errors.is_empty() => {
+            info!("imported {} rows, {} assets created, {} updated, dry run: {}", report.rows, report.created, report.updated, dry_run);
+            HttpResponse::Ok().json(report)
+        },
+        Ok(report) => {
+            error!("rejected import, {} of {} rows have errors", report.errors.len(), report.rows);
+            HttpResponse::UnprocessableEntity().json(report)
+        },
+        Err(message) => {
+            error!("invalid import file: {}", message);
+            HttpResponse::BadRequest().json(json!({ "message": message }))
+        }
+    }
+}
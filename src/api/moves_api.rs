@@ -0,0 +1,197 @@
+use actix_web::{post, HttpRequest, HttpResponse, Responder, web};
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+use log::{info, error};
+use serde_json::json;
+
+use crate::api::audit_api::request_audit;
+use crate::api::auth::validator;
+use crate::api::util::validate_uuid;
+use crate::db::crud::buildings_crud::find_building_by_id;
+use crate::db::crud::rooms_crud::{find_room_by_id, move_rooms_by_id, rooms_by_storey};
+use crate::db::crud::storeys_crud::{find_storey_by_id, level_taken, move_storey_by_id};
+use crate::db::models::{MoveRoom, MoveRooms, MoveStorey, Room, Storey};
+use crate::openapi::ErrorMessage;
+
+#[utoipa::path(
+    tag = "storeys",
+    params(("id" = Uuid, Path, description = "UUID of the storey")),
+    request_body = MoveStorey,
+    responses(
+        (status = 200, description = "The storey in its new place, its rooms moved along", body = Storey),
+        (status = 400, description = "Invalid UUID or request body", body = ErrorMessage),
+        (status = 404, description = "No storey with the UUID", body = ErrorMessage),
+        (status = 422, description = "Invalid building UUID, or level already exists in the building", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/storeys/{id}/move", wrap="HttpAuthentication::bearer(validator)")]
+async fn move_storey(id: web::Path<String>, req_body: String, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let target : MoveStorey = match serde_json::from_str(&req_body) {
+        Ok(target) => target,
+        Err(_) => {
+            error!("invalid storey move request body: {}", req_body);
+            return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+        }
+    };
+
+    let param_id = param_id.unwrap();
+    let before = match find_storey_by_id(param_id, false) {
+        Some(storey) => storey,
+        None => {
+            error!("storey with UUID {} not found", param_id);
+            return HttpResponse::NotFound().json(json!({ "message": "storey with UUID not found" }));
+        }
+    };
+
+    if find_building_by_id(target.building_id, false).is_none() {
+        error!("building with UUID {} does not exist", target.building_id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid building UUID" }));
+    }
+
+    let storey_level = target.level.or(before.level);
+    if storey_level.is_some_and(|storey_level| level_taken(target.building_id, storey_level, Some(param_id))) {
+        error!("building {} already has a storey on level {:?}", target.building_id, storey_level);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "level already exists in building" }));
+    }
+
+    let after = match move_storey_by_id(&request_audit(&req), param_id, target.building_id, target.level) {
+        Some(storey) => storey,
+        None => {
+            error!("storey move threw an error");
+            return HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }));
+        }
+    };
+
+    info!("moved storey {} to building {}, level {:?}", param_id, after.building_id, after.level);
+    HttpResponse::Ok().json(after)
+}
+
+#[utoipa::path(
+    tag = "rooms",
+    params(("id" = Uuid, Path, description = "UUID of the room")),
+    request_body = MoveRoom,
+    responses(
+        (status = 200, description = "The room on its new storey", body = Room),
+        (status = 400, description = "Invalid UUID or request body", body = ErrorMessage),
+        (status = 404, description = "No room with the UUID", body = ErrorMessage),
+        (status = 422, description = "Invalid storey UUID", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/rooms/{id}/move", wrap="HttpAuthentication::bearer(validator)")]
+async fn move_room(id: web::Path<String>, req_body: String, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let target : MoveRoom = match serde_json::from_str(&req_body) {
+        Ok(target) => target,
+        Err(_) => {
+            error!("invalid room move request body: {}", req_body);
+            return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+        }
+    };
+
+    let param_id = param_id.unwrap();
+    if find_room_by_id(param_id, false).is_none() {
+        error!("room with UUID {} not found", param_id);
+        return HttpResponse::NotFound().json(json!({ "message": "room with UUID not found" }));
+    }
+
+    if find_storey_by_id(target.storey_id, false).is_none() {
+        error!("storey with UUID {} does not exist", target.storey_id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid storey UUID" }));
+    }
+
+    let after = match move_rooms_by_id(&request_audit(&req), &[param_id], target.storey_id).and_then(|mut moved| moved.pop()) {
+        Some(room) => room,
+        None => {
+            error!("room move threw an error");
+            return HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }));
+        }
+    };
+
+    info!("moved room {} to storey {}", param_id, target.storey_id);
+    HttpResponse::Ok().json(after)
+}
+
+#[utoipa::path(
+    tag = "rooms",
+    params(("id" = Uuid, Path, description = "UUID of the storey")),
+    request_body = MoveRooms,
+    responses(
+        (status = 200, description = "The moved rooms on their new storey", body = [Room]),
+        (status = 400, description = "Invalid UUID or request body", body = ErrorMessage),
+        (status = 404, description = "No storey with the UUID", body = ErrorMessage),
+        (status = 409, description = "The rooms changed during the move, nothing was moved", body = ErrorMessage),
+        (status = 422, description = "Invalid target storey, or rooms that are not on the storey", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/storeys/{id}/rooms/move", wrap="HttpAuthentication::bearer(validator)")]
+async fn move_rooms_of_storey(id: web::Path<String>, req_body: String, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let target : MoveRooms = match serde_json::from_str(&req_body) {
+        Ok(target) => target,
+        Err(_) => {
+            error!("invalid room move request body: {}", req_body);
+            return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+        }
+    };
+
+    let param_id = param_id.unwrap();
+    if find_storey_by_id(param_id, false).is_none() {
+        error!("storey with UUID {} not found", param_id);
+        return HttpResponse::NotFound().json(json!({ "message": "storey with UUID not found" }));
+    }
+
+    if target.storey_id == param_id {
+        error!("rooms of storey {} are already on the target storey", param_id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "rooms are already on that storey" }));
+    }
+
+    if find_storey_by_id(target.storey_id, false).is_none() {
+        error!("storey with UUID {} does not exist", target.storey_id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid storey UUID" }));
+    }
+
+    let mut before = rooms_by_storey(param_id, false);
+    if let Some(ref room_ids) = target.room_ids {
+        let elsewhere : Vec<_> = room_ids.iter().filter(|id| !before.iter().any(|room| room.id == **id)).collect();
+        if !elsewhere.is_empty() {
+            error!("{} rooms to move are not on storey {}", elsewhere.len(), param_id);
+            return HttpResponse::UnprocessableEntity().json(json!({ "message": "rooms are not on the storey", "rooms": elsewhere }));
+        }
+        before.retain(|room| room_ids.contains(&room.id));
+    }
+
+    let room_ids : Vec<_> = before.iter().map(|room| room.id).collect();
+
+    let after = match move_rooms_by_id(&request_audit(&req), &room_ids, target.storey_id) {
+        Some(rooms) => rooms,
+        None => {
+            error!("rooms of storey {} changed while moving, rolled back", param_id);
+            return HttpResponse::Conflict().json(json!({ "message": "rooms changed during move, try again" }));
+        }
+    };
+
+    info!("moved {} rooms from storey {} to storey {}", after.len(), param_id, target.storey_id);
+    HttpResponse::Ok().json(after)
+}
@@ -0,0 +1,17 @@
+use actix_web::{get, HttpResponse, Responder, web};
+use utoipa::OpenApi;
+
+use crate::openapi::ApiDoc;
+
+#[get("/openapi.json")]
+async fn get_openapi() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Serve Swagger UI for the document at `/assets/swagger-ui/index.html`, if built with the `swagger-ui` feature.
+pub fn swagger_ui(cfg: &mut web::ServiceConfig) {
+    #[cfg(feature = "swagger-ui")]
+    cfg.service(utoipa_swagger_ui::SwaggerUi::new("/swagger-ui/{_:.*}").config(utoipa_swagger_ui::Config::from("/assets/openapi.json")));
+    #[cfg(not(feature = "swagger-ui"))]
+    let _ = cfg;
+}
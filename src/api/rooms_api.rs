@@ -1,36 +1,118 @@
-use actix_web::{dev::ServiceRequest, get, post, put, delete, HttpRequest, HttpResponse, Responder, web};
+use actix_web::{get, post, put, delete, HttpRequest, HttpResponse, Responder, web};
 use actix_web_httpauth::middleware::HttpAuthentication;
 
-use log::{debug, info, error};
+use log::{info, error};
 use serde_json::json;
-use serde::Deserialize;
-use std::env;
+use std::sync::Arc;
+use uuid::Uuid;
 
+use crate::api::audit_api::request_audit;
 use crate::api::auth::validator;
-use crate::api::util::{get_jaeger_params, validate_uuid};
+use crate::api::geojson_api::{asset_feature, feature_collection, wants_geojson};
+use crate::api::cascade::{blocking_reservations, delete_with_reservations};
+use crate::api::checks::check_room;
+use crate::api::conditional::conditional_get;
+use crate::api::util::{history_unreadable, get_trace_id, reservations_unavailable, validate_uuid, QueryReadOptions, QueryReservationPolicy, ReservationPolicy};
+use crate::cache;
+use crate::db::crud::audit_crud::Audit;
+use crate::db::crud::buildings_crud::find_building_by_id;
+use crate::db::crud::equipment_crud::rooms_with_equipment;
+use crate::db::crud::history_crud::{asset_as_of, assets_as_of, asset_history_of};
 use crate::db::crud::rooms_crud::*;
-use crate::db::crud::storeys_crud::find_storey_by_id;
-use crate::db::models::OptionalIDRoom;
-use crate::db::models::Reservation;
+use crate::db::crud::storeys_crud::{find_storey_by_id, storeys_by_building};
+use crate::db::models::{AssetVersion, AvailabilityQuery, OptionalIDRoom, Room, RoomFilter};
+use crate::dbconn::connection;
+use crate::openapi::ErrorMessage;
+use crate::reservations;
 
-#[derive(Debug, Deserialize)]
-pub struct QueryByStorey {
-    storey_id: Option<uuid::Uuid>
+#[utoipa::path(
+    tag = "rooms",
+    params(RoomFilter),
+    responses(
+        (status = 200, description = "Rooms matching the filter; GeoJSON with `Accept: application/geo+json`", body = [Room]),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag")
+    )
+)]
+#[get("/rooms")]
+async fn get_rooms_by_storey(filter: web::Query<RoomFilter>, req: HttpRequest) -> impl Responder {
+    // equipment changes don't count up the collection version, so it can't version the rooms filtered by them
+    if filter.equipment.is_some() {
+        return list_rooms(&filter, &req, None);
+    }
+    conditional_get(&req, |version| list_rooms(&filter, &req, version))
 }
 
-#[get("/rooms")]
-async fn get_rooms_by_storey(param: web::Query<QueryByStorey>) -> impl Responder {
-    let rooms = if param.storey_id.is_some() {
-        rooms_by_storey(param.storey_id.unwrap())
-    } else {
-        get_rooms()
+fn list_rooms(filter: &RoomFilter, req: &HttpRequest, version: Option<i64>) -> HttpResponse {
+    let include_deleted = filter.include_deleted.unwrap_or(false);
+    let rooms : Arc<Vec<Room>> = match filter.as_of {
+        Some(as_of) => {
+            let equipment_keys = filter.equipment_keys();
+            let equipped = if equipment_keys.is_empty() { None } else { Some(rooms_with_equipment(&equipment_keys)) };
+            let history = match assets_as_of::<Room>("room", as_of) {
+                Ok(history) => history,
+                Err(e) => return history_unreadable(e)
+            };
+            Arc::new(history.into_iter()
+                .filter(|room| filter.matches(room))
+                .filter(|room| equipped.as_ref().is_none_or(|ids| ids.contains(&room.id)))
+                .collect())
+        },
+        None if filter.only_by_storey() => cache::read_at(cache::room_lists, (filter.storey_id, include_deleted), version, || find_rooms(filter)),
+        None => Arc::new(find_rooms(filter))
     };
     info!("found {} rooms", rooms.len());
+    if wants_geojson(req) {
+        return feature_collection(rooms.iter().map(|room| asset_feature("room", room)).collect());
+    }
+    HttpResponse::Ok().json(&*rooms)
+}
+
+/// Rooms without any reservation between `from` and `to`, optionally with a minimum capacity and in one building.
+#[utoipa::path(
+    tag = "rooms",
+    params(AvailabilityQuery),
+    responses(
+        (status = 200, description = "Rooms without a reservation between `from` and `to`", body = [Room]),
+        (status = 400, description = "`from` is not before `to`", body = ErrorMessage),
+        (status = 503, description = "Reservations service unavailable", body = ErrorMessage)
+    )
+)]
+#[get("/rooms/available")]
+async fn get_available_rooms(query: web::Query<AvailabilityQuery>, req: HttpRequest) -> impl Responder {
+    if query.from >= query.to {
+        error!("availability requested for empty interval {} to {}", query.from, query.to);
+        return HttpResponse::BadRequest().json(json!({ "message": "from must be before to" }));
+    }
+
+    let mut rooms = find_rooms(&RoomFilter { min_capacity: query.min_capacity, ..RoomFilter::default() });
+    if let Some(building_id) = query.building_id {
+        let storey_ids : Vec<_> = storeys_by_building(building_id, false).into_iter().map(|storey| storey.id).collect();
+        rooms.retain(|room| storey_ids.contains(&room.storey_id));
+    }
+
+    let reservations = match reservations::client().reservations_between(query.from, query.to, get_trace_id(&req).as_deref()).await {
+        Ok(reservations) => reservations,
+        Err(e) => return reservations_unavailable(&e)
+    };
+    rooms.retain(|room| !reservations.iter().any(|res| res.room_id == room.id && res.overlaps(query.from, query.to)));
+
+    info!("found {} rooms available from {} to {}", rooms.len(), query.from, query.to);
     HttpResponse::Ok().json(rooms)
 }
 
+#[utoipa::path(
+    tag = "rooms",
+    request_body = OptionalIDRoom,
+    responses(
+        (status = 201, description = "Room created, or updated if one with the given UUID exists", body = Room),
+        (status = 400, description = "Invalid request body", body = ErrorMessage),
+        (status = 409, description = "Room with the UUID is in the trash", body = ErrorMessage),
+        (status = 422, description = "Invalid attributes", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
 #[post("/rooms", wrap="HttpAuthentication::bearer(validator)")]
-async fn add_room(req_body: String) -> impl Responder {
+async fn add_room(req_body: String, req: HttpRequest) -> impl Responder {
 
     let body_content : Result<OptionalIDRoom, serde_json::Error> = serde_json::from_str(&req_body);
     if body_content.is_err() { 
@@ -39,15 +121,13 @@ async fn add_room(req_body: String) -> impl Responder {
     }
     
     let room = body_content.unwrap();
-    let room_name = room.name.to_string();
-    let room_storey_id = room.storey_id;
 
-    if find_storey_by_id(room_storey_id).is_none() {
-        error!("storey with UUID {} does not exist", room_storey_id);
-        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid storey UUID" }));
-    }
-    
-    if let Some(new_room) = create_or_update_room(room.id, room_name, room_storey_id) {
+    let room = match check_room(&connection().unwrap(), room) {
+        Ok(checked) => checked.request,
+        Err(rejection) => return rejection.response()
+    };
+
+    if let Some(new_room) = create_or_update_room(&request_audit(&req), room.id, room.name, room.storey_id, room.attributes) {
         info!("room {} newly created or updated", new_room.id);
         HttpResponse::Created().json(new_room)
     } else {
@@ -56,14 +136,31 @@ async fn add_room(req_body: String) -> impl Responder {
     }
 }
 
+#[utoipa::path(
+    tag = "rooms",
+    params(("id" = Uuid, Path, description = "UUID of the room"), QueryReadOptions),
+    responses(
+        (status = 200, description = "The room", body = Room),
+        (status = 404, description = "No room with the UUID", body = ErrorMessage)
+    )
+)]
 #[get("/rooms/{id}")]
-async fn get_room_by_id(id: web::Path<String>) -> impl Responder {
+async fn get_room_by_id(id: web::Path<String>, param: web::Query<QueryReadOptions>) -> impl Responder {
 
     let room_uuid = validate_uuid(id.to_string());
 
     if let Some(room_id) = room_uuid {
 
-        match find_room_by_id(room_id) {
+        let include_deleted = param.include_deleted.unwrap_or(false);
+        let room = match param.as_of {
+            Some(as_of) => match asset_as_of::<Room>("room", room_id, as_of) {
+                Ok(room) => Arc::new(room),
+                Err(e) => return history_unreadable(e)
+            },
+            None => cache::read(cache::rooms, (room_id, include_deleted), || find_room_by_id(room_id, include_deleted))
+        };
+
+        match room.as_ref() {
             Some(room) => {
                 info!("found room with UUID: {}", id);
                 HttpResponse::Ok().json(room)
@@ -81,8 +178,20 @@ async fn get_room_by_id(id: web::Path<String>) -> impl Responder {
 
 }
 
+#[utoipa::path(
+    tag = "rooms",
+    params(("id" = Uuid, Path, description = "UUID of the room")),
+    request_body = OptionalIDRoom,
+    responses(
+        (status = 204, description = "Room updated, or created if it did not exist"),
+        (status = 400, description = "Invalid UUID or request body", body = ErrorMessage),
+        (status = 409, description = "Room with the UUID is in the trash", body = ErrorMessage),
+        (status = 422, description = "UUID in the body does not match, or invalid attributes", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
 #[put("/rooms/{id}", wrap="HttpAuthentication::bearer(validator)")]
-async fn update_room(id: web::Path<String>, req_body: String) -> impl Responder {
+async fn update_room(id: web::Path<String>, req_body: String, req: HttpRequest) -> impl Responder {
 
     let param_id = validate_uuid(id.to_string());
     if param_id.is_none() {
@@ -97,8 +206,6 @@ async fn update_room(id: web::Path<String>, req_body: String) -> impl Responder
     }
 
     let room = body_content.unwrap();
-    let room_name = room.name.to_string();
-    let room_storey_id = room.storey_id;
 
     if let Some(body_id) = room.id {
         let param_id = param_id.unwrap();
@@ -108,12 +215,12 @@ async fn update_room(id: web::Path<String>, req_body: String) -> impl Responder
         }
     }
 
-    if find_storey_by_id(room_storey_id).is_none() {
-        error!("storey with UUID {} does not exist", room_storey_id);
-        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid storey UUID" }));
-    }
-    
-    if let Some(new_room) = create_or_update_room(room.id, room_name, room_storey_id) {
+    let room = match check_room(&connection().unwrap(), room) {
+        Ok(checked) => checked.request,
+        Err(rejection) => return rejection.response()
+    };
+
+    if let Some(new_room) = create_or_update_room(&request_audit(&req), room.id, room.name, room.storey_id, room.attributes) {
         info!("room {} newly created or updated", new_room.id);
         HttpResponse::NoContent().finish()
     } else {
@@ -123,8 +230,21 @@ async fn update_room(id: web::Path<String>, req_body: String) -> impl Responder
 
 }
 
+#[utoipa::path(
+    tag = "rooms",
+    params(("id" = Uuid, Path, description = "UUID of the room"), QueryReservationPolicy),
+    responses(
+        (status = 204, description = "Room moved to the trash"),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No room with the UUID"),
+        (status = 409, description = "The room was reserved while deleting it", body = ErrorMessage),
+        (status = 422, description = "rooms have reservations the policy does not allow to cancel", body = ErrorMessage),
+        (status = 503, description = "Reservations service unavailable", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
 #[delete("/rooms/{id}", wrap="HttpAuthentication::bearer(validator)")]
-async fn delete_room(id: web::Path<String>, req: HttpRequest) -> impl Responder {
+async fn delete_room(id: web::Path<String>, policy: web::Query<QueryReservationPolicy>, req: HttpRequest) -> impl Responder {
     
     let param_id = validate_uuid(id.to_string());
     if param_id.is_none() {
@@ -133,49 +253,113 @@ async fn delete_room(id: web::Path<String>, req: HttpRequest) -> impl Responder
     }
 
     let param_id = param_id.unwrap();
-    let (jaeger_key, jaeger_id) = get_jaeger_params(&ServiceRequest::from_request(req));
-    if let Some(has_reservations) = has_room_reservations(param_id, &jaeger_key, &jaeger_id) {
-        if has_reservations {
-            info!("room {} has existing reservations, cannot delete", param_id);
-            return HttpResponse::UnprocessableEntity().json(
-                json!({ "message": format!("room {} has existing reservations", param_id) })
-            );
-        } else {
-            info!("room {} has no associated reservations, ok to delete", param_id);
-        }
-    } else {
-        error!("error getting reservation data for room {}, not deleting", param_id);
-        return HttpResponse::NotFound().finish();
+    let policy = policy.on_reservations.unwrap_or_default();
+    let reservations = match blocking_reservations(&[param_id], policy, &req).await {
+        Ok(reservations) => reservations,
+        Err(response) => return response
+    };
+    if !reservations.is_empty() && policy != ReservationPolicy::Cancel {
+        info!("room {} has existing reservations, cannot delete", param_id);
+        return HttpResponse::UnprocessableEntity().json(
+            json!({ "message": format!("room {} has existing reservations", param_id) })
+        );
     }
+    info!("room {} has {} reservations to cancel, ok to delete", param_id, reservations.len());
     
-    if delete_room_by_id(param_id) {
-        info!("deleted room {}", param_id);
+    let delete = |audit: &Audit, id: &Uuid| {
+        if delete_room_by_id(audit, *id) {
+            return Ok(());
+        }
+        error!("room with UUID {} not found", id);
+        Err(HttpResponse::NotFound().finish())
+    };
+    match delete_with_reservations(param_id, vec![param_id], policy, delete, |audit, id| restore_room_by_id(audit, *id), &req).await {
+        Ok(_) => {
+            info!("deleted room {}", param_id);
+            HttpResponse::NoContent().finish()
+        },
+        Err(response) => response
+    }
+
+}
+
+#[utoipa::path(
+    tag = "rooms",
+    params(("id" = Uuid, Path, description = "UUID of the room")),
+    responses(
+        (status = 204, description = "Room restored from the trash"),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No room with the UUID in the trash", body = ErrorMessage),
+        (status = 422, description = "The room cannot come back, e.g. its parent is deleted", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/rooms/{id}/restore", wrap="HttpAuthentication::bearer(validator)")]
+async fn restore_room(id: web::Path<String>, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let param_id = param_id.unwrap();
+    let room = match find_room_by_id(param_id, true) {
+        Some(room) if room.deleted_at.is_some() => room,
+        _ => {
+            error!("room with UUID {} not found in trash", param_id);
+            return HttpResponse::NotFound().json(json!({ "message": "room with UUID not found in trash" }));
+        }
+    };
+
+    // a room can only come back if its storey and building are still (or again) around
+    let storey = match find_storey_by_id(room.storey_id, false) {
+        Some(storey) => storey,
+        None => {
+            error!("cannot restore room {}, storey {} is deleted", param_id, room.storey_id);
+            return HttpResponse::UnprocessableEntity().json(json!({ "message": "storey of room is deleted" }));
+        }
+    };
+    if find_building_by_id(storey.building_id, false).is_none() {
+        error!("cannot restore room {}, building {} is deleted", param_id, storey.building_id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "building of room is deleted" }));
+    }
+
+    if restore_room_by_id(&request_audit(&req), param_id) {
+        info!("restored room {}", param_id);
         HttpResponse::NoContent().finish()
     } else {
-        error!("room with UUID {} not found", param_id);
-        HttpResponse::NotFound().finish()
+        error!("room with UUID {} not found in trash", param_id);
+        HttpResponse::NotFound().json(json!({ "message": "room with UUID not found in trash" }))
     }
 
 }
 
-fn has_room_reservations(delete_room_id: uuid::Uuid, jaeger_key: &str, jaeger_id: &str) -> Option<bool> {
+#[utoipa::path(
+    tag = "rooms",
+    params(("id" = Uuid, Path, description = "UUID of the room")),
+    responses(
+        (status = 200, description = "All versions of the room, oldest first", body = [AssetVersion]),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No room with the UUID", body = ErrorMessage)
+    )
+)]
+#[get("/rooms/{id}/history")]
+async fn get_room_history(id: web::Path<String>) -> impl Responder {
 
-    let reservations_host = env::var("RESERVATIONS_HOST").expect("RESERVATIONS_HOST variable not set");
-    let reservations_port = env::var("RESERVATIONS_PORT").expect("RESERVATIONS_PORT variable not set");
-    
-    let reservations_url  = format!("http://{}:{}/api/reservations/", reservations_host, reservations_port);
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
 
-    let client = reqwest::blocking::Client::new();
-    let resp = client.get(reservations_url)
-                     .header(jaeger_key, jaeger_id)
-                     .send().ok()?;
-    
-    if resp.status().is_success() {
-        let reservations : Vec<Reservation> = resp.json().ok()?;
-        debug!("received reservations from backend");
-        Some(reservations.iter().any(|res| res.room_id == delete_room_id))
-    } else {
-        debug!("no reservations found");
-        None
+    let versions = asset_history_of("room", param_id.unwrap());
+    if versions.is_empty() {
+        error!("no history for room with UUID: {}", id);
+        return HttpResponse::NotFound().json(json!({ "message": "room with UUID not found" }));
     }
-}
\ No newline at end of file
+
+    info!("found {} versions of room {}", versions.len(), id);
+    HttpResponse::Ok().json(versions)
+
+}
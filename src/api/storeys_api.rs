@@ -1,35 +1,81 @@
-use actix_web::{get, post, put, delete, HttpResponse, Responder, web};
+use actix_web::{get, post, put, delete, HttpRequest, HttpResponse, Responder, web};
 use actix_web_httpauth::middleware::HttpAuthentication;
 
+use chrono::{DateTime, Utc};
 use log::{info, error};
 use serde_json::json;
 use serde::Deserialize;
+use utoipa::IntoParams;
 
+use crate::api::audit_api::request_audit;
 use crate::api::auth::validator;
-use crate::api::util::validate_uuid;
+use crate::api::cascade::cascade_delete;
+use crate::api::checks::check_storey;
+use crate::api::conditional::conditional_get;
+use crate::api::util::{history_unreadable, validate_uuid, QueryDeleteMode, QueryReadOptions, QueryReservationPolicy};
+use crate::db::crud::cascade_crud::storey_subtree;
+use crate::db::crud::history_crud::{asset_as_of, assets_as_of, asset_history_of};
 use crate::db::crud::storeys_crud::*;
 use crate::db::crud::rooms_crud::has_rooms;
 use crate::db::crud::buildings_crud::find_building_by_id;
-use crate::db::models::OptionalIDStorey;
+use crate::db::models::{AssetVersion, DeletionReport, OptionalIDStorey, Storey};
+use crate::dbconn::connection;
+use crate::openapi::ErrorMessage;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
 pub struct QueryByBuilding {
-    building_id: Option<uuid::Uuid>
+    building_id: Option<uuid::Uuid>,
+    include_deleted: Option<bool>,
+    as_of: Option<DateTime<Utc>>
 }
 
+#[utoipa::path(
+    tag = "storeys",
+    params(QueryByBuilding),
+    responses(
+        (status = 200, description = "Storeys, ordered by level", body = [Storey]),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag")
+    )
+)]
 #[get("/storeys")]
-async fn get_storeys_by_building(param: web::Query<QueryByBuilding>) -> impl Responder {
-    let storeys = if param.building_id.is_some() {
-        storeys_by_building(param.building_id.unwrap())
-    } else {
-        get_storeys()
+async fn get_storeys_by_building(param: web::Query<QueryByBuilding>, req: HttpRequest) -> impl Responder {
+    conditional_get(&req, |_| list_storeys(&param))
+}
+
+fn list_storeys(param: &QueryByBuilding) -> HttpResponse {
+    let include_deleted = param.include_deleted.unwrap_or(false);
+    let storeys = match (param.as_of, param.building_id) {
+        (Some(as_of), building_id) => {
+            let history = match assets_as_of::<Storey>("storey", as_of) {
+                Ok(history) => history,
+                Err(e) => return history_unreadable(e)
+            };
+            let mut storeys : Vec<Storey> = history.into_iter()
+                .filter(|storey| building_id.is_none_or(|id| storey.building_id == id))
+                .collect();
+            sort_storeys(&mut storeys);
+            storeys
+        },
+        (None, Some(building_id)) => storeys_by_building(building_id, include_deleted),
+        (None, None) => get_storeys(include_deleted)
     };
     info!("found {} storeys", storeys.len());
     HttpResponse::Ok().json(storeys)
 }
 
+#[utoipa::path(
+    tag = "storeys",
+    request_body = OptionalIDStorey,
+    responses(
+        (status = 201, description = "Storey created, or updated if one with the given UUID exists", body = Storey),
+        (status = 400, description = "Invalid request body", body = ErrorMessage),
+        (status = 409, description = "Storey with the UUID is in the trash", body = ErrorMessage),
+        (status = 422, description = "Invalid attributes", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
 #[post("/storeys", wrap="HttpAuthentication::bearer(validator)")]
-async fn add_storey(req_body: String) -> impl Responder {
+async fn add_storey(req_body: String, req: HttpRequest) -> impl Responder {
 
     let body_content : Result<OptionalIDStorey, serde_json::Error> = serde_json::from_str(&req_body);
     if body_content.is_err() {
@@ -38,15 +84,13 @@ async fn add_storey(req_body: String) -> impl Responder {
     }
     
     let storey = body_content.unwrap();
-    let storey_name = storey.name.to_string();
-    let storey_building_id = storey.building_id;
 
-    if find_building_by_id(storey_building_id).is_none() {
-        error!("building with UUID {} does not exist", storey_building_id);
-        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid building UUID" }));
-    }
-    
-    if let Some(new_storey) = create_or_update_storey(storey.id, storey_name, storey_building_id) {
+    let storey = match check_storey(&connection().unwrap(), storey) {
+        Ok(checked) => checked.request,
+        Err(rejection) => return rejection.response()
+    };
+
+    if let Some(new_storey) = create_or_update_storey(&request_audit(&req), storey.id, storey.name, storey.building_id, storey.attributes) {
         info!("storey {} newly created or updated", new_storey.id);
         HttpResponse::Created().json(new_storey)
     } else {
@@ -56,14 +100,30 @@ async fn add_storey(req_body: String) -> impl Responder {
 
 }
 
+#[utoipa::path(
+    tag = "storeys",
+    params(("id" = Uuid, Path, description = "UUID of the storey"), QueryReadOptions),
+    responses(
+        (status = 200, description = "The storey", body = Storey),
+        (status = 404, description = "No storey with the UUID", body = ErrorMessage)
+    )
+)]
 #[get("/storeys/{id}")]
-async fn get_storey_by_id(id: web::Path<String>) -> impl Responder {
+async fn get_storey_by_id(id: web::Path<String>, param: web::Query<QueryReadOptions>) -> impl Responder {
     
     let storey_uuid = validate_uuid(id.to_string());
 
     if let Some(storey_id) = storey_uuid {
 
-        match find_storey_by_id(storey_id) {
+        let storey = match param.as_of {
+            Some(as_of) => match asset_as_of::<Storey>("storey", storey_id, as_of) {
+                Ok(storey) => storey,
+                Err(e) => return history_unreadable(e)
+            },
+            None => find_storey_by_id(storey_id, param.include_deleted.unwrap_or(false))
+        };
+
+        match storey {
             Some(storey) => {
                 info!("found storey with UUID: {}", id);
                 HttpResponse::Ok().json(storey)
@@ -80,8 +140,20 @@ async fn get_storey_by_id(id: web::Path<String>) -> impl Responder {
     }
 }
 
+#[utoipa::path(
+    tag = "storeys",
+    params(("id" = Uuid, Path, description = "UUID of the storey")),
+    request_body = OptionalIDStorey,
+    responses(
+        (status = 204, description = "Storey updated, or created if it did not exist"),
+        (status = 400, description = "Invalid UUID or request body", body = ErrorMessage),
+        (status = 409, description = "Storey with the UUID is in the trash", body = ErrorMessage),
+        (status = 422, description = "UUID in the body does not match, or invalid attributes", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
 #[put("/storeys/{id}", wrap="HttpAuthentication::bearer(validator)")]
-async fn update_storey(id: web::Path<String>, req_body: String) -> impl Responder {
+async fn update_storey(id: web::Path<String>, req_body: String, req: HttpRequest) -> impl Responder {
 
     let param_id = validate_uuid(id.to_string());
     if param_id.is_none() {
@@ -96,8 +168,6 @@ async fn update_storey(id: web::Path<String>, req_body: String) -> impl Responde
     }
 
     let storey = body_content.unwrap();
-    let storey_name = storey.name.to_string();
-    let storey_building_id = storey.building_id;
 
     if let Some(body_id) = storey.id {
         let param_id = param_id.unwrap();
@@ -107,12 +177,12 @@ async fn update_storey(id: web::Path<String>, req_body: String) -> impl Responde
         }
     }
 
-    if find_building_by_id(storey_building_id).is_none() {
-        error!("building with UUID {} does not exist", storey_building_id);
-        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid building UUID" }));
-    }
-    
-    if let Some(new_storey) = create_or_update_storey(storey.id, storey_name, storey_building_id) {
+    let storey = match check_storey(&connection().unwrap(), storey) {
+        Ok(checked) => checked.request,
+        Err(rejection) => return rejection.response()
+    };
+
+    if let Some(new_storey) = create_or_update_storey(&request_audit(&req), storey.id, storey.name, storey.building_id, storey.attributes) {
         info!("storey {} newly created or updated", new_storey.id);
         HttpResponse::NoContent().finish()
     } else {
@@ -122,8 +192,22 @@ async fn update_storey(id: web::Path<String>, req_body: String) -> impl Responde
 
 }
 
+#[utoipa::path(
+    tag = "storeys",
+    params(("id" = Uuid, Path, description = "UUID of the storey"), QueryDeleteMode, QueryReservationPolicy),
+    responses(
+        (status = 200, description = "Dry run, the assets that would be deleted", body = DeletionReport),
+        (status = 204, description = "Storey moved to the trash"),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No storey with the UUID"),
+        (status = 409, description = "Assets changed or rooms were reserved during a cascading delete", body = ErrorMessage),
+        (status = 422, description = "Has children without cascade, or rooms have reservations the policy does not allow to cancel", body = ErrorMessage),
+        (status = 503, description = "Reservations service unavailable", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
 #[delete("/storeys/{id}", wrap="HttpAuthentication::bearer(validator)")]
-async fn delete_storey(id: web::Path<String>) -> impl Responder {
+async fn delete_storey(id: web::Path<String>, mode: web::Query<QueryDeleteMode>, policy: web::Query<QueryReservationPolicy>, req: HttpRequest) -> impl Responder {
 
     let param_id = validate_uuid(id.to_string());
     if param_id.is_none() {
@@ -132,12 +216,27 @@ async fn delete_storey(id: web::Path<String>) -> impl Responder {
     }
 
     let param_id = param_id.unwrap();
+    let dry_run = mode.dry_run.unwrap_or(false);
+    if (mode.cascade.unwrap_or(false) || dry_run) && find_storey_by_id(param_id, false).is_none() {
+        error!("storey with UUID {} not found", param_id);
+        return HttpResponse::NotFound().finish();
+    }
+
+    if mode.cascade.unwrap_or(false) {
+        return cascade_delete(storey_subtree(param_id), dry_run, policy.on_reservations.unwrap_or_default(), &req).await;
+    }
+
     if has_rooms(param_id) {
         error!("cannot delete storey {}, has existing rooms", param_id);
         return HttpResponse::UnprocessableEntity().json(json!({ "message": "storey has existing rooms" }));
     }
+
+    if dry_run {
+        info!("dry run: would delete storey {}", param_id);
+        return HttpResponse::Ok().json(DeletionReport { buildings: Vec::new(), storeys: vec![param_id], rooms: Vec::new() });
+    }
     
-    if delete_storey_by_id(param_id) {
+    if delete_storey_by_id(&request_audit(&req), param_id) {
         info!("deleted storey {}", param_id);
         HttpResponse::NoContent().finish()
     } else {
@@ -146,3 +245,82 @@ async fn delete_storey(id: web::Path<String>) -> impl Responder {
     }
 
 }
+
+#[utoipa::path(
+    tag = "storeys",
+    params(("id" = Uuid, Path, description = "UUID of the storey")),
+    responses(
+        (status = 204, description = "Storey restored from the trash"),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No storey with the UUID in the trash", body = ErrorMessage),
+        (status = 422, description = "The storey cannot come back, e.g. its parent is deleted", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/storeys/{id}/restore", wrap="HttpAuthentication::bearer(validator)")]
+async fn restore_storey(id: web::Path<String>, req: HttpRequest) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let param_id = param_id.unwrap();
+    let storey = match find_storey_by_id(param_id, true) {
+        Some(storey) if storey.deleted_at.is_some() => storey,
+        _ => {
+            error!("storey with UUID {} not found in trash", param_id);
+            return HttpResponse::NotFound().json(json!({ "message": "storey with UUID not found in trash" }));
+        }
+    };
+
+    // a storey can only come back if its building is still (or again) around
+    if find_building_by_id(storey.building_id, false).is_none() {
+        error!("cannot restore storey {}, building {} is deleted", param_id, storey.building_id);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "building of storey is deleted" }));
+    }
+
+    if storey.level.is_some_and(|storey_level| level_taken(storey.building_id, storey_level, Some(param_id))) {
+        error!("cannot restore storey {}, level {:?} is taken", param_id, storey.level);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "level already exists in building" }));
+    }
+
+    if restore_storey_by_id(&request_audit(&req), param_id) {
+        info!("restored storey {}", param_id);
+        HttpResponse::NoContent().finish()
+    } else {
+        error!("storey with UUID {} not found in trash", param_id);
+        HttpResponse::NotFound().json(json!({ "message": "storey with UUID not found in trash" }))
+    }
+
+}
+
+#[utoipa::path(
+    tag = "storeys",
+    params(("id" = Uuid, Path, description = "UUID of the storey")),
+    responses(
+        (status = 200, description = "All versions of the storey, oldest first", body = [AssetVersion]),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No storey with the UUID", body = ErrorMessage)
+    )
+)]
+#[get("/storeys/{id}/history")]
+async fn get_storey_history(id: web::Path<String>) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let versions = asset_history_of("storey", param_id.unwrap());
+    if versions.is_empty() {
+        error!("no history for storey with UUID: {}", id);
+        return HttpResponse::NotFound().json(json!({ "message": "storey with UUID not found" }));
+    }
+
+    info!("found {} versions of storey {}", versions.len(), id);
+    HttpResponse::Ok().json(versions)
+
+}
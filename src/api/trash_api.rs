@@ -0,0 +1,31 @@
+use actix_web::{get, HttpResponse, Responder};
+
+use log::info;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::db::crud::buildings_crud::get_deleted_buildings;
+use crate::db::crud::storeys_crud::get_deleted_storeys;
+use crate::db::crud::rooms_crud::get_deleted_rooms;
+use crate::db::models::{Building, Room, Storey};
+
+#[derive(Serialize, ToSchema)]
+/// Assets in the trash, they can be restored until they are purged.
+pub struct Trash {
+    pub buildings: Vec<Building>,
+    pub storeys: Vec<Storey>,
+    pub rooms: Vec<Room>
+}
+
+#[utoipa::path(
+    tag = "trash",
+    responses(
+        (status = 200, description = "Buildings, storeys and rooms in the trash", body = Trash)
+    )
+)]
+#[get("/trash")]
+async fn get_trash() -> impl Responder {
+    let trash = Trash { buildings: get_deleted_buildings(), storeys: get_deleted_storeys(), rooms: get_deleted_rooms() };
+    info!("found {} buildings, {} storeys and {} rooms in trash", trash.buildings.len(), trash.storeys.len(), trash.rooms.len());
+    HttpResponse::Ok().json(trash)
+}
@@ -1,11 +1,22 @@
+use actix_web::{HttpRequest, HttpResponse, web};
 use actix_web::dev::ServiceRequest;
 
-use log::debug;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use log::{debug, error};
+use serde::Deserialize;
+use serde_json::json;
 
 use std::env;
 
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
+use crate::db::models::{BuildingAttributes, RoomAttributes, ROOM_TYPES};
+use crate::floorplan::parse_polygon;
+use crate::geo::{valid_footprint, valid_position};
+use crate::reservations::ReservationsError;
+
 /// Extract Jaeger tracing header from the received request.
 /// The header key is an environment variable with the ID `JAEGER_HEADER`.
 pub fn get_jaeger_params(req: &ServiceRequest) -> (String, String) {
@@ -19,15 +30,133 @@ pub fn get_jaeger_params(req: &ServiceRequest) -> (String, String) {
     
 }
 
+/// Extract the Jaeger trace ID from the request, if there is one.
+/// Unlike `get_jaeger_params`, this does not expect the header to be present.
+pub fn get_trace_id(req: &HttpRequest) -> Option<String> {
+    let jaeger_key = env::var("JAEGER_HEADER").unwrap_or("Uber-Trace-Id".to_string());
+    req.headers().get(&jaeger_key)?.to_str().ok().map(|id| id.to_string())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+/// Query parameters for reading assets.
+/// `include_deleted` also returns assets from the trash,
+/// `as_of` returns the assets as they were at that point in time instead of their current state.
+pub struct QueryReadOptions {
+    pub include_deleted: Option<bool>,
+    pub as_of: Option<DateTime<Utc>>
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+/// Query parameters for delete endpoints that can remove a whole subtree of assets.
+/// `dry_run` only reports what would be deleted.
+pub struct QueryDeleteMode {
+    pub cascade: Option<bool>,
+    pub dry_run: Option<bool>
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+/// What to do when rooms that are about to be deleted have reservations.
+pub enum ReservationPolicy {
+    /// Refuse to delete rooms with any reservation, even past ones.
+    Refuse,
+    /// Refuse to delete rooms with reservations that have not ended yet.
+    #[default]
+    Future,
+    /// Cancel the reservations that have not ended yet, then delete.
+    Cancel
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+/// Query parameter for delete endpoints that remove rooms, `on_reservations` picks the `ReservationPolicy`.
+pub struct QueryReservationPolicy {
+    pub on_reservations: Option<ReservationPolicy>
+}
+
+/// Response for requests that need the reservations service while it cannot be asked.
+pub fn reservations_unavailable(e: &ReservationsError) -> HttpResponse {
+    error!("could not get reservation data: {}", e);
+    HttpResponse::ServiceUnavailable().json(json!({ "message": "reservations service unavailable" }))
+}
+
+/// Response for point-in-time queries that run into a version of an asset that cannot be read.
+pub fn history_unreadable(e: String) -> HttpResponse {
+    error!("could not read asset history: {}", e);
+    HttpResponse::InternalServerError().json(json!({ "message": "asset history cannot be read" }))
+}
+
+/// Read a request body of at most `max_size` bytes, for uploads that are larger than the default body limit.
+/// Returns the response to send instead if the body is too large or cannot be read.
+pub async fn read_payload(payload: &mut web::Payload, max_size: usize) -> Result<Vec<u8>, HttpResponse> {
+    let mut data = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        match chunk {
+            Ok(chunk) if data.len() + chunk.len() <= max_size => data.extend_from_slice(&chunk),
+            Ok(_) => {
+                error!("request body is larger than {} bytes", max_size);
+                return Err(HttpResponse::PayloadTooLarge().json(json!({ "message": "request body too large" })));
+            },
+            Err(e) => {
+                error!("could not read request body: {}", e);
+                return Err(HttpResponse::BadRequest().json(json!({ "message": "invalid input" })));
+            }
+        }
+    }
+    Ok(data)
+}
+
 /// Wraps the `uuid` module's string parse function to return an optional UUID from a string.
 /// A very useful function that does very useful things.
 pub fn validate_uuid(input: String) -> Option<uuid::Uuid> {
     Uuid::parse_str(&input).ok()
 }
 
+/// Check the optional attributes of a room for values that make no sense.
+/// Return a message describing the first problem, or None if the attributes are fine.
+pub fn validate_room_attributes(attributes: &RoomAttributes) -> Option<&'static str> {
+    if attributes.seated_capacity.flatten().is_some_and(|cap| cap < 0) || attributes.standing_capacity.flatten().is_some_and(|cap| cap < 0) {
+        return Some("capacity must not be negative");
+    }
+    if attributes.area.flatten().is_some_and(|area| area.is_nan() || area <= 0.0) {
+        return Some("area must be positive");
+    }
+    if attributes.room_type.iter().flatten().any(|kind| !ROOM_TYPES.contains(&kind.as_str())) {
+        return Some("unknown room type");
+    }
+    if attributes.plan_polygon.iter().flatten().any(|polygon| parse_polygon(polygon).is_none()) {
+        return Some("plan polygon must be a list of at least three [x, y] points");
+    }
+    validate_geometry(attributes.latitude.flatten(), attributes.longitude.flatten(), attributes.footprint.as_ref().and_then(Option::as_ref))
+}
+
+/// Check the position and footprint of a building, latitude and longitude have to be given together and within range.
+/// Return a message describing the problem, or None if the attributes are fine.
+pub fn validate_building_attributes(attributes: &BuildingAttributes) -> Option<&'static str> {
+    validate_geometry(attributes.latitude.flatten(), attributes.longitude.flatten(), attributes.footprint.as_ref().and_then(Option::as_ref))
+}
+
+fn validate_geometry(latitude: Option<f64>, longitude: Option<f64>, footprint: Option<&serde_json::Value>) -> Option<&'static str> {
+    match (latitude, longitude) {
+        (Some(lat), Some(lon)) if !valid_position(lat, lon) => return Some("position out of range"),
+        (Some(_), None) | (None, Some(_)) => return Some("latitude and longitude must be given together"),
+        _ => ()
+    }
+    if footprint.is_some_and(|footprint| !valid_footprint(footprint)) {
+        return Some("footprint must be a GeoJSON Polygon or MultiPolygon");
+    }
+    None
+}
+
+/// Check an equipment key for characters that would break the comma-separated equipment filter.
+/// Keys are lowercase ASCII letters, digits, `-` and `_`.
+pub fn validate_equipment_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::models::OptionalIDRoom;
 
     #[test]
     fn test_successful_validation() {
@@ -42,4 +171,59 @@ mod tests {
         let uuid = validate_uuid(test_input);
         assert!(uuid.is_none());
     }
+
+    #[test]
+    fn test_valid_room_attributes() {
+        let attributes = RoomAttributes {
+            seated_capacity: Some(Some(120)),
+            standing_capacity: Some(Some(0)),
+            room_type: Some(Some("lecture_hall".to_string())),
+            area: Some(Some(180.5)),
+            ..Default::default()
+        };
+        assert!(validate_room_attributes(&attributes).is_none());
+        assert!(validate_room_attributes(&RoomAttributes::default()).is_none());
+    }
+
+    #[test]
+    fn test_invalid_room_attributes() {
+        let negative = RoomAttributes { seated_capacity: Some(Some(-1)), ..Default::default() };
+        assert!(validate_room_attributes(&negative).is_some());
+
+        let empty = RoomAttributes { area: Some(Some(0.0)), ..Default::default() };
+        assert!(validate_room_attributes(&empty).is_some());
+
+        let unknown = RoomAttributes { room_type: Some(Some("ballroom".to_string())), ..Default::default() };
+        assert!(validate_room_attributes(&unknown).is_some());
+    }
+
+    #[test]
+    fn test_room_attributes_null_clears() {
+        let room : OptionalIDRoom = serde_json::from_str(r#"{"name": "A 101", "storey_id": "a4a443c6-0aad-4c1f-a623-e2c2dfc5780c", "area": null, "seated_capacity": 30}"#).unwrap();
+        assert_eq!(room.attributes.area, Some(None));
+        assert_eq!(room.attributes.seated_capacity, Some(Some(30)));
+        assert_eq!(room.attributes.room_type, None);
+    }
+
+    #[test]
+    fn test_equipment_keys() {
+        assert!(validate_equipment_key("projector"));
+        assert!(validate_equipment_key("vc-kit_2"));
+        assert!(!validate_equipment_key(""));
+        assert!(!validate_equipment_key("projector,vc"));
+        assert!(!validate_equipment_key("Projector"));
+    }
+
+    #[test]
+    fn test_building_position() {
+        let karlsruhe = BuildingAttributes { latitude: Some(Some(49.0069)), longitude: Some(Some(8.4037)), ..Default::default() };
+        assert!(validate_building_attributes(&karlsruhe).is_none());
+        assert!(validate_building_attributes(&BuildingAttributes::default()).is_none());
+
+        let half = BuildingAttributes { latitude: Some(Some(49.0069)), ..Default::default() };
+        assert!(validate_building_attributes(&half).is_some());
+
+        let off_the_map = BuildingAttributes { latitude: Some(Some(123.0)), longitude: Some(Some(8.4037)), ..Default::default() };
+        assert!(validate_building_attributes(&off_the_map).is_some());
+    }
 }
@@ -0,0 +1,163 @@
+use actix_web::{get, post, delete, HttpResponse, Responder, web};
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+use chrono::Utc;
+use log::{info, error};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::api::auth::validator;
+use crate::api::util::validate_uuid;
+use crate::db::crud::webhooks_crud::*;
+use crate::db::models::{NewWebhook, Webhook, WebhookDelivery};
+use crate::openapi::ErrorMessage;
+use crate::webhooks::{allowed_hosts, check_target, generate_secret, valid_pattern};
+
+/// Secrets shorter than this are too easy to guess.
+const MIN_SECRET_LENGTH: usize = 16;
+
+#[utoipa::path(
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "All webhook subscriptions, without their secrets", body = [Webhook])
+    ),
+    security(("bearer" = []))
+)]
+#[get("/webhooks", wrap="HttpAuthentication::bearer(validator)")]
+async fn get_webhook_subscriptions() -> impl Responder {
+    let subscriptions = get_webhooks();
+    info!("found {} webhooks", subscriptions.len());
+    HttpResponse::Ok().json(subscriptions)
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    request_body = NewWebhook,
+    responses(
+        (status = 201, description = "Webhook created, the only response that includes its `secret`", body = Webhook),
+        (status = 400, description = "Invalid request body", body = ErrorMessage),
+        (status = 422, description = "URL not allowed, invalid event pattern, or secret too short", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/webhooks", wrap="HttpAuthentication::bearer(validator)")]
+async fn add_webhook(req_body: String) -> impl Responder {
+
+    let body_content : Result<NewWebhook, serde_json::Error> = serde_json::from_str(&req_body);
+    if body_content.is_err() {
+        error!("invalid webhook request body: {}", req_body);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid input" }));
+    }
+
+    let new_webhook = body_content.unwrap();
+    if let Err(problem) = check_target(&new_webhook.url, &allowed_hosts()) {
+        error!("invalid webhook URL {}: {}", new_webhook.url, problem);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": format!("invalid webhook URL: {}", problem) }));
+    }
+    if new_webhook.events.is_empty() || !new_webhook.events.iter().all(|pattern| valid_pattern(pattern)) {
+        error!("invalid webhook events: {:?}", new_webhook.events);
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "invalid event pattern" }));
+    }
+    if new_webhook.secret.as_ref().is_some_and(|secret| secret.len() < MIN_SECRET_LENGTH) {
+        error!("webhook secret is too short");
+        return HttpResponse::UnprocessableEntity().json(
+            json!({ "message": format!("secret must have at least {} characters", MIN_SECRET_LENGTH) })
+        );
+    }
+
+    let webhook = Webhook {
+        id: Uuid::new_v4(),
+        url: new_webhook.url,
+        secret: new_webhook.secret.unwrap_or_else(generate_secret),
+        events: new_webhook.events,
+        created_at: Utc::now()
+    };
+    match create_webhook(webhook) {
+        Some(saved) => {
+            info!("webhook {} created for {}", saved.id, saved.url);
+            // the only time the secret is shown
+            let mut body = json!(saved);
+            body["secret"] = json!(saved.secret);
+            HttpResponse::Created().json(body)
+        },
+        None => {
+            error!("webhook create threw an error");
+            HttpResponse::InternalServerError().json(json!({ "message": "something went wrong :O" }))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Deliveries that ran out of attempts", body = [WebhookDelivery])
+    ),
+    security(("bearer" = []))
+)]
+#[get("/webhooks/dead-letters", wrap="HttpAuthentication::bearer(validator)")]
+async fn get_webhook_dead_letters() -> impl Responder {
+    let dead_letters = get_dead_letters();
+    info!("found {} webhook dead letters", dead_letters.len());
+    HttpResponse::Ok().json(dead_letters)
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(("id" = Uuid, Path, description = "UUID of the dead letter")),
+    responses(
+        (status = 200, description = "The delivery, queued again", body = WebhookDelivery),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No dead letter with the UUID", body = ErrorMessage)
+    ),
+    security(("bearer" = []))
+)]
+#[post("/webhooks/dead-letters/{id}/retry", wrap="HttpAuthentication::bearer(validator)")]
+async fn retry_webhook_dead_letter(id: web::Path<String>) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let param_id = param_id.unwrap();
+    match retry_dead_letter(param_id) {
+        Some(delivery) => {
+            info!("queued dead letter {} again", param_id);
+            HttpResponse::Ok().json(delivery)
+        },
+        None => {
+            error!("dead letter with UUID {} not found", param_id);
+            HttpResponse::NotFound().json(json!({ "message": "dead letter with UUID not found" }))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(("id" = Uuid, Path, description = "UUID of the webhook")),
+    responses(
+        (status = 204, description = "Webhook deleted"),
+        (status = 400, description = "Invalid UUID", body = ErrorMessage),
+        (status = 404, description = "No webhook with the UUID")
+    ),
+    security(("bearer" = []))
+)]
+#[delete("/webhooks/{id}", wrap="HttpAuthentication::bearer(validator)")]
+async fn delete_webhook(id: web::Path<String>) -> impl Responder {
+
+    let param_id = validate_uuid(id.to_string());
+    if param_id.is_none() {
+        error!("invalid param UUID: {}", id);
+        return HttpResponse::BadRequest().json(json!({ "message": "invalid UUID in parameters" }));
+    }
+
+    let param_id = param_id.unwrap();
+    if delete_webhook_by_id(param_id) {
+        info!("deleted webhook {}", param_id);
+        HttpResponse::NoContent().finish()
+    } else {
+        error!("webhook with UUID {} not found", param_id);
+        HttpResponse::NotFound().finish()
+    }
+}
@@ -0,0 +1,170 @@
+// Operations of a batch write, which creates, updates and deletes assets in one transaction:
+//
+//   { "operations": [
+//       { "op": "create", "type": "storey", "ref": "attic", "data": { "name": "Attic", "building_id": "..." } },
+//       { "op": "create", "type": "room", "data": { "name": "A.301", "storey_id": "$attic" } },
+//       { "op": "delete", "type": "room", "id": "..." }
+//   ] }
+//
+// A create can name its asset with `ref`, later operations use `$name` in place of the UUID.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Fields of the operation data that can hold a reference instead of a UUID.
+const REFERENCE_FIELDS: [&str; 3] = ["id", "building_id", "storey_id"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchAction {
+    Create,
+    Update,
+    Delete
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetType {
+    Building,
+    Storey,
+    Room
+}
+
+impl AssetType {
+    /// Name of the asset type in the audit log and the change events.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AssetType::Building => "building",
+            AssetType::Storey => "storey",
+            AssetType::Room => "room"
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+/// A single operation of a batch. `data` is the body the create or update endpoint of the asset type takes,
+/// `id` is the asset to update or delete, as a UUID or a reference.
+pub struct BatchOperation {
+    pub op: BatchAction,
+    #[serde(rename = "type")]
+    pub asset_type: AssetType,
+    pub id: Option<String>,
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+    #[serde(default)]
+    pub data: Value
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperation>
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+/// Outcome of one operation. `status` is the HTTP status the single endpoint would have answered with,
+/// it is missing for operations that did not run because an earlier one failed.
+pub struct OperationResult {
+    pub index: usize,
+    pub op: BatchAction,
+    #[serde(rename = "type")]
+    pub asset_type: AssetType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset: Option<Value>
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+/// Result of a batch, nothing has been written unless `committed` is set.
+pub struct BatchReport {
+    pub committed: bool,
+    pub results: Vec<OperationResult>
+}
+
+#[derive(Debug, Default)]
+/// UUIDs of the assets created so far in a batch, by the name given in `ref`.
+pub struct References(HashMap<String, Uuid>);
+
+impl References {
+    /// Remember the UUID of a created asset. Return false if the name is already taken.
+    pub fn insert(&mut self, name: &str, id: Uuid) -> bool {
+        if self.0.contains_key(name) {
+            return false;
+        }
+        self.0.insert(name.to_string(), id);
+        true
+    }
+
+    /// Turn a UUID or a `$name` reference into a UUID.
+    pub fn resolve(&self, value: &str) -> Result<Uuid, String> {
+        match value.strip_prefix('$') {
+            Some(name) => self.0.get(name).copied().ok_or_else(|| format!("unknown reference {}", value)),
+            None => Uuid::parse_str(value).map_err(|_| format!("invalid UUID {}", value))
+        }
+    }
+
+    /// Replace the references in the ID fields of operation data by their UUIDs.
+    pub fn resolve_fields(&self, data: &mut Value) -> Result<(), String> {
+        if let Some(fields) = data.as_object_mut() {
+            for field in REFERENCE_FIELDS {
+                if let Some(Value::String(value)) = fields.get(field) {
+                    if value.starts_with('$') {
+                        let id = self.resolve(value)?;
+                        fields.insert(field.to_string(), Value::String(id.to_string()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_operations() {
+        let body = json!({ "operations": [
+            { "op": "create", "type": "building", "ref": "main", "data": { "name": "Main", "address": "Hauptstraße 1" } },
+            { "op": "delete", "type": "room", "id": "$main" }
+        ] });
+        let request : BatchRequest = serde_json::from_value(body).unwrap();
+        assert_eq!(request.operations.len(), 2);
+        assert_eq!(request.operations[0].asset_type, AssetType::Building);
+        assert_eq!(request.operations[0].reference.as_deref(), Some("main"));
+        assert_eq!(request.operations[1].op, BatchAction::Delete);
+        assert!(request.operations[1].data.is_null());
+
+        let unknown = json!({ "operations": [{ "op": "move", "type": "room" }] });
+        assert!(serde_json::from_value::<BatchRequest>(unknown).is_err());
+    }
+
+    #[test]
+    fn test_references() {
+        let storey = Uuid::new_v4();
+        let mut references = References::default();
+        assert!(references.insert("attic", storey));
+        assert!(!references.insert("attic", Uuid::new_v4()));
+
+        assert_eq!(references.resolve("$attic"), Ok(storey));
+        assert_eq!(references.resolve(&storey.to_string()), Ok(storey));
+        assert!(references.resolve("$cellar").is_err());
+        assert!(references.resolve("attic").is_err());
+
+        let mut data = json!({ "name": "$attic", "storey_id": "$attic" });
+        references.resolve_fields(&mut data).unwrap();
+        assert_eq!(data, json!({ "name": "$attic", "storey_id": storey.to_string() }));
+
+        let mut dangling = json!({ "storey_id": "$cellar" });
+        assert!(references.resolve_fields(&mut dangling).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+// Optional in-process cache for the busiest reads: the building list, room lists and single rooms.
+//
+// Turned on with `READ_CACHE=true`. Entries live for `READ_CACHE_TTL_SECS` and every cache holds at most
+// `READ_CACHE_MAX_ENTRIES`, the oldest entry makes room for a new one. Changes invalidate the affected entries,
+// local ones right away by the write that committed them and those of other instances (or direct SQL) through the
+// `asset_changes` notifications that `jobs::changes` listens to.
+// Lists served with an ETag are also versioned: an entry is only used for a collection version it was loaded at
+// or after, so a list that is stale because its invalidation has not arrived yet is never sent under a newer ETag.
+
+use log::{debug, info};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db::models::{Building, Room};
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+/// Counters of a cache since the start of the service.
+pub struct CacheStats {
+    pub name: &'static str,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub invalidations: u64
+}
+
+/// Cached value with the time it was stored and the collection version read before it was loaded.
+struct Entry<V> {
+    stored: Instant,
+    version: i64,
+    value: Arc<V>
+}
+
+pub struct ReadCache<K, V> {
+    name: &'static str,
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<K, Entry<V>>>,
+    // bumped by every invalidation, a value loaded across one is not stored because it might be stale
+    generation: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    invalidations: AtomicU64
+}
+
+impl<K: Eq + Hash + Clone, V> ReadCache<K, V> {
+
+    pub fn new(name: &'static str, ttl: Duration, max_entries: usize) -> ReadCache<K, V> {
+        ReadCache {
+            name,
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0)
+        }
+    }
+
+    /// Return the cached value for the key, or load and cache it.
+    pub fn get_or_load(&self, key: K, load: impl FnOnce() -> V) -> Arc<V> {
+        self.get_or_load_at(key, i64::MIN, load)
+    }
+
+    /// Like `get_or_load`, but only use a value loaded at `version` or a later one.
+    /// `version` has to be read before `load` runs, the value is then at least as new as the version.
+    pub fn get_or_load_at(&self, key: K, version: i64, load: impl FnOnce() -> V) -> Arc<V> {
+        if let Some(entry) = self.entries.lock().unwrap().get(&key) {
+            if entry.stored.elapsed() < self.ttl && entry.version >= version {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return entry.value.clone();
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let value = Arc::new(load());
+
+        let mut entries = self.entries.lock().unwrap();
+        // a slow load must not replace what a later version put there in the meantime
+        let newer_stored = entries.get(&key).is_some_and(|entry| entry.version > version);
+        if self.generation.load(Ordering::SeqCst) == generation && self.max_entries > 0 && !newer_stored {
+            if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+                self.make_room(&mut entries);
+            }
+            entries.insert(key, Entry { stored: Instant::now(), version, value: value.clone() });
+        }
+        value
+    }
+
+    /// Drop expired entries, or the oldest one if none has expired.
+    fn make_room(&self, entries: &mut HashMap<K, Entry<V>>) {
+        let before = entries.len();
+        entries.retain(|_, entry| entry.stored.elapsed() < self.ttl);
+        if entries.len() == before {
+            let oldest = entries.iter().min_by_key(|(_, entry)| entry.stored).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        self.evictions.fetch_add((before - entries.len()) as u64, Ordering::Relaxed);
+    }
+
+    /// Drop the entries whose key matches.
+    pub fn invalidate(&self, matches: impl Fn(&K) -> bool) {
+        let mut entries = self.entries.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        let before = entries.len();
+        entries.retain(|key, _| !matches(key));
+        self.invalidations.fetch_add((before - entries.len()) as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            name: self.name,
+            entries: self.entries.lock().unwrap().len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed)
+        }
+    }
+}
+
+/// Check if the read cache is turned on for this deployment.
+pub fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        let enabled = env::var("READ_CACHE").is_ok_and(|value| value == "true" || value == "1");
+        if enabled {
+            info!("read cache enabled, entries live for {}s", ttl().as_secs());
+        }
+        enabled
+    })
+}
+
+fn ttl() -> Duration {
+    Duration::from_secs(env::var("READ_CACHE_TTL_SECS").ok().and_then(|value| value.parse().ok()).unwrap_or(30))
+}
+
+fn max_entries() -> usize {
+    env::var("READ_CACHE_MAX_ENTRIES").ok().and_then(|value| value.parse().ok()).unwrap_or(1000)
+}
+
+/// Building lists, by `include_deleted`.
+pub fn building_lists() -> &'static ReadCache<bool, Vec<Building>> {
+    static CACHE: OnceLock<ReadCache<bool, Vec<Building>>> = OnceLock::new();
+    CACHE.get_or_init(|| ReadCache::new("building_lists", ttl(), max_entries()))
+}
+
+type RoomLists = ReadCache<(Option<Uuid>, bool), Vec<Room>>;
+
+/// Room lists, by storey (None for all rooms) and `include_deleted`.
+pub fn room_lists() -> &'static RoomLists {
+    static CACHE: OnceLock<RoomLists> = OnceLock::new();
+    CACHE.get_or_init(|| ReadCache::new("room_lists", ttl(), max_entries()))
+}
+
+/// Single rooms, by UUID and `include_deleted`. Rooms that were not found are cached as well.
+pub fn rooms() -> &'static ReadCache<(Uuid, bool), Option<Room>> {
+    static CACHE: OnceLock<ReadCache<(Uuid, bool), Option<Room>>> = OnceLock::new();
+    CACHE.get_or_init(|| ReadCache::new("rooms", ttl(), max_entries()))
+}
+
+/// Look the value up in the cache if it is turned on, otherwise just load it.
+pub fn read<K: Eq + Hash + Clone, V>(cache: fn() -> &'static ReadCache<K, V>, key: K, load: impl FnOnce() -> V) -> Arc<V> {
+    if enabled() {
+        cache().get_or_load(key, load)
+    } else {
+        Arc::new(load())
+    }
+}
+
+/// Look a list up that is sent with the ETag of collection `version`, see `conditional_get`.
+/// Without a version nothing can vouch for a cached list, so it is loaded.
+pub fn read_at<K: Eq + Hash + Clone, V>(cache: fn() -> &'static ReadCache<K, V>, key: K, version: Option<i64>, load: impl FnOnce() -> V) -> Arc<V> {
+    match version {
+        Some(version) if enabled() => cache().get_or_load_at(key, version, load),
+        _ => Arc::new(load())
+    }
+}
+
+/// Drop everything a change of the asset may have made stale.
+pub fn invalidate(entity_type: &str, entity_id: Uuid) {
+    if !enabled() {
+        return;
+    }
+    debug!("invalidating cached reads of {} {}", entity_type, entity_id);
+    match entity_type {
+        "building" => building_lists().invalidate(|_| true),
+        // rooms are listed without the storeys they are on, but deleting a storey deletes its rooms as well
+        "storey" => room_lists().invalidate(|_| true),
+        "room" => {
+            room_lists().invalidate(|_| true);
+            rooms().invalidate(|(id, _)| *id == entity_id);
+        },
+        _ => {}
+    }
+}
+
+/// Drop everything changes of the assets may have made stale.
+/// Call it once their transaction has committed, a read before that could put the old state back.
+pub fn invalidate_all<'a>(changed: impl IntoIterator<Item = (&'a str, Uuid)>) {
+    for (entity_type, entity_id) in changed {
+        invalidate(entity_type, entity_id);
+    }
+}
+
+/// Counters of all caches.
+pub fn stats() -> Vec<CacheStats> {
+    vec![building_lists().stats(), room_lists().stats(), rooms().stats()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_get_or_load() {
+        let cache : ReadCache<u32, String> = ReadCache::new("test", Duration::from_millis(50), 2);
+        assert_eq!(*cache.get_or_load(1, || "one".to_string()), "one");
+        assert_eq!(*cache.get_or_load(1, || "uno".to_string()), "one");
+
+        // full, the oldest entry goes
+        cache.get_or_load(2, || "two".to_string());
+        cache.get_or_load(3, || "three".to_string());
+        assert_eq!(*cache.get_or_load(1, || "uno".to_string()), "uno");
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(*cache.get_or_load(1, || "eins".to_string()), "eins");
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 5, 2));
+        assert!(stats.evictions >= 2);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache : ReadCache<u32, u32> = ReadCache::new("test", Duration::from_secs(60), 10);
+        cache.get_or_load(1, || 1);
+        cache.get_or_load(2, || 2);
+        cache.invalidate(|key| *key == 1);
+        assert_eq!(*cache.get_or_load(1, || 10), 10);
+        assert_eq!(*cache.get_or_load(2, || 20), 2);
+
+        // a value loaded while an invalidation happens is returned, but not kept
+        let value = cache.get_or_load(3, || {
+            cache.invalidate(|_| false);
+            3
+        });
+        assert_eq!(*value, 3);
+        assert_eq!(*cache.get_or_load(3, || 30), 30);
+        assert_eq!(cache.stats().invalidations, 1);
+    }
+
+    #[test]
+    fn test_get_or_load_at() {
+        let cache : ReadCache<u32, &str> = ReadCache::new("test", Duration::from_secs(60), 10);
+        assert_eq!(*cache.get_or_load_at(1, 5, || "five"), "five");
+        assert_eq!(*cache.get_or_load_at(1, 5, || "again"), "five");
+
+        // the invalidation has not arrived, but a newer version never gets the older list
+        assert_eq!(*cache.get_or_load_at(1, 6, || "six"), "six");
+        // a newer list is fine for an older version
+        assert_eq!(*cache.get_or_load_at(1, 5, || "again"), "six");
+        // a request that read its version before the last write does not put its list back
+        let slow = cache.get_or_load_at(2, 7, || {
+            cache.get_or_load_at(2, 8, || "eight");
+            "seven"
+        });
+        assert_eq!(*slow, "seven");
+        assert_eq!(*cache.get_or_load_at(2, 8, || "again"), "eight");
+    }
+}
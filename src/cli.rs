@@ -0,0 +1,42 @@
+use log::info;
+use std::env;
+use std::fs;
+use std::io::Error;
+
+use crate::api::audit_api::actor_audit;
+use crate::api::auth::Actor;
+use crate::import::{run_import, ImportPolicy};
+
+/// Usage of the command line, printed if the arguments make no sense.
+const IMPORT_USAGE: &str = "usage: biletado-assets import <file.csv> [--dry-run] [--create-only]";
+
+/// Import a CSV file of assets from the command line, e.g. `biletado-assets import rooms.csv --dry-run`.
+/// The report is printed to stdout, the command fails if any row has errors.
+/// Changes are audited with the name of the local user, there is no token on the command line.
+pub fn import(args: &[String]) -> Result<(), Error> {
+
+    let mut path = None;
+    let mut dry_run = false;
+    let mut policy = ImportPolicy::Upsert;
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--create-only" => policy = ImportPolicy::CreateOnly,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(Error::other(IMPORT_USAGE))
+        }
+    }
+
+    let path = path.ok_or_else(|| Error::other(IMPORT_USAGE))?;
+    let data = fs::read(path)?;
+    let actor = Actor { id: None, name: Some(format!("cli:{}", env::var("USER").unwrap_or("unknown".to_string()))) };
+
+    let report = run_import(&actor_audit(Some(actor), None), &data, policy, dry_run).map_err(Error::other)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    if !report.errors.is_empty() {
+        return Err(Error::other(format!("{} of {} rows have errors, nothing was imported", report.errors.len(), report.rows)));
+    }
+    info!("imported {} rows from {}, {} assets created, {} updated, dry run: {}", report.rows, path, report.created, report.updated, dry_run);
+    Ok(())
+}
@@ -1,3 +1,20 @@
+// Database access for the API and the background jobs.
+// Functions ending in `_with` run on the connection they are given, e.g. that of a transaction a batch or import
+// is running in, and leave the cache alone. The ones without take a connection of their own
+// and drop the cached asset once their change is committed.
+
 pub mod buildings_crud;
 pub mod storeys_crud;
 pub mod rooms_crud;
+pub mod equipment_crud;
+pub mod cascade_crud;
+pub mod audit_crud;
+pub mod history_crud;
+pub mod floor_plans_crud;
+pub mod import_crud;
+pub mod export_crud;
+pub mod clone_crud;
+pub mod webhooks_crud;
+pub mod room_moves_crud;
+pub mod outbox_crud;
+pub mod changes_crud;
\ No newline at end of file
@@ -0,0 +1,86 @@
+use chrono::Utc;
+use diesel::{BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::result::Error;
+use log::debug;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::crud::webhooks_crud::queue_deliveries_with;
+use crate::db::models::{AuditEntry, AuditFilter};
+use crate::db::schema::audit_log;
+use crate::webhooks::WebhookEvent;
+
+use crate::dbconn::connection;
+
+/// Upper bound for the number of audit entries returned by a single query.
+const MAX_AUDIT_ENTRIES: i64 = 1000;
+
+/// Who makes a change, every write to an asset takes one and records the change in the audit log with it.
+pub struct Audit {
+    actor_id: Option<String>,
+    actor_name: Option<String>,
+    trace_id: Option<String>
+}
+
+impl Audit {
+    pub fn new(actor_id: Option<String>, actor_name: Option<String>, trace_id: Option<String>) -> Audit {
+        Audit { actor_id, actor_name, trace_id }
+    }
+}
+
+/// Append a change to the audit log and queue it for the webhooks subscribed to it.
+/// Call it on the connection the change was made on, inside the same transaction, next to `record_event`,
+/// so a change is never committed without its audit entry.
+pub fn record_change<T: Serialize>(conn: &PgConnection, audit: &Audit, action: &str, entity_type: &str, entity_id: Uuid, before: Option<&T>, after: Option<&T>) -> Result<(), Error> {
+    let snapshot = |asset: Option<&T>| asset.map(serde_json::to_value).transpose().map_err(|e| Error::SerializationError(Box::new(e)));
+    let entry = AuditEntry {
+        id: Uuid::new_v4(),
+        occurred_at: Utc::now(),
+        actor_id: audit.actor_id.clone(),
+        actor_name: audit.actor_name.clone(),
+        action: action.to_string(),
+        entity_type: entity_type.to_string(),
+        entity_id,
+        before: snapshot(before)?,
+        after: snapshot(after)?,
+        trace_id: audit.trace_id.clone()
+    };
+
+    let queued = queue_deliveries_with(conn, &WebhookEvent::from_audit(&entry))?;
+    if queued > 0 {
+        debug!("queued {} webhook deliveries for {} of {} {}", queued, action, entity_type, entity_id);
+    }
+    diesel::insert_into(audit_log::table).values(entry).execute(conn)?;
+    Ok(())
+}
+
+/// Return the audit entries matching the filter, newest first.
+/// At most `filter.limit` entries (100 by default, 1000 at most) are returned.
+pub fn find_audit_entries(filter: &AuditFilter) -> Vec<AuditEntry> {
+    use crate::db::schema::audit_log::dsl::*;
+
+    let conn = connection().unwrap();
+    let mut query = audit_log.into_boxed();
+
+    if let Some(ref kind) = filter.entity_type {
+        query = query.filter(entity_type.eq(kind));
+    }
+    if let Some(entity) = filter.entity_id {
+        query = query.filter(entity_id.eq(entity));
+    }
+    if let Some(ref actor) = filter.actor {
+        query = query.filter(actor_id.eq(actor).or(actor_name.eq(actor)));
+    }
+    if let Some(ref act) = filter.action {
+        query = query.filter(action.eq(act));
+    }
+    if let Some(since) = filter.from {
+        query = query.filter(occurred_at.ge(since));
+    }
+    if let Some(until) = filter.to {
+        query = query.filter(occurred_at.lt(until));
+    }
+
+    let limit = filter.limit.unwrap_or(100).clamp(1, MAX_AUDIT_ENTRIES);
+    query.order(occurred_at.desc()).limit(limit).load::<AuditEntry>(&conn).unwrap_or_default()
+}
@@ -1,74 +1,294 @@
 use uuid::Uuid;
 
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use chrono::{DateTime, Utc};
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::result::Error;
+use diesel::sql_types::{Bool, Double, Text};
+use log::info;
+use std::sync::OnceLock;
 
-use crate::db::models::Building;
+use crate::db::models::{Building, BuildingAttributes, NearbyBuilding};
 use crate::db::schema::buildings::dsl::buildings;
+use crate::db::schema::buildings::id as b_id;
 use crate::db::schema::buildings::name as b_name;
 use crate::db::schema::buildings::address as b_address;
+use crate::db::schema::buildings::deleted_at as b_deleted_at;
+use crate::db::schema::buildings::{street, house_number, postal_code, city, country, latitude, longitude, footprint};
+use crate::db::schema::storeys;
+use crate::db::crud::audit_crud::{record_change, Audit};
+use crate::db::crud::outbox_crud::record_event;
+use crate::cache::invalidate;
+use crate::outbox::Change;
 
 use crate::dbconn::connection;
+use crate::geo;
 
 /// Get a vector of all buildings in the database.
-pub fn get_buildings() -> Vec<Building> {
+/// Buildings in the trash are only included if `include_deleted` is set.
+pub fn get_buildings(include_deleted: bool) -> Vec<Building> {
     let conn = connection().unwrap();
-    buildings.load::<Building>(&conn).expect("Error loading buildings")
+    let mut query = buildings.into_boxed();
+    if !include_deleted {
+        query = query.filter(b_deleted_at.is_null());
+    }
+    query.load::<Building>(&conn).expect("Error loading buildings")
+}
+
+/// Get a vector of all buildings currently in the trash.
+pub fn get_deleted_buildings() -> Vec<Building> {
+    let conn = connection().unwrap();
+    buildings.filter(b_deleted_at.is_not_null()).load::<Building>(&conn).unwrap_or_default()
 }
 
 /// Find a building by UUID.
 /// Returns a building struct with the corresponding UUID or None if the UUID is not in the DB.
-pub fn find_building_by_id(id: uuid::Uuid) -> Option<Building> {
-    let conn = connection().unwrap();
-    buildings.find(id).first::<Building>(&conn).ok()
+/// Buildings in the trash are only found if `include_deleted` is set.
+pub fn find_building_by_id(id: uuid::Uuid, include_deleted: bool) -> Option<Building> {
+    find_building_by_id_with(&connection().unwrap(), id, include_deleted)
+}
+
+pub fn find_building_by_id_with(conn: &PgConnection, id: uuid::Uuid, include_deleted: bool) -> Option<Building> {
+    let mut query = buildings.filter(b_id.eq(id)).into_boxed();
+    if !include_deleted {
+        query = query.filter(b_deleted_at.is_null());
+    }
+    query.first::<Building>(conn).ok()
 }
 
-/// Pass a building name and address, maybe a UUID.
-/// If the UUID already exists, update the building with the new name and address.
+/// Pass a building name and address, maybe a UUID, and the optional structured address and position.
+/// If the UUID already exists, update the building with the new name and address, unless it is in the trash.
 /// If the UUID does not exist, create a new building with that UUID.
 /// If there is no UUID, generate a new one and insert a new building with that name, address, and new UUID.
-pub fn create_or_update_building(id: Option<uuid::Uuid>, building_name: String, building_address: String) -> Option<Building> {
-    let conn = connection().unwrap();
+pub fn create_or_update_building(audit: &Audit, id: Option<uuid::Uuid>, building_name: String, building_address: String, attributes: BuildingAttributes) -> Option<Building> {
+    create_or_update_building_with(&connection().unwrap(), audit, id, building_name, building_address, attributes)
+        .inspect(|building| invalidate("building", building.id))
+}
+
+pub fn create_or_update_building_with(conn: &PgConnection, audit: &Audit, id: Option<uuid::Uuid>, building_name: String, building_address: String, attributes: BuildingAttributes) -> Option<Building> {
+    conn.transaction::<_, Error, _>(|| {
+        let (building, before) = save_building(conn, id, building_name, building_address, attributes)?;
+        let change = if before.is_some() { Change::Updated } else { Change::Created };
+        record_event(conn, "building", change, building.id, &building)?;
+        record_change(conn, audit, change.action(), "building", building.id, before.as_ref(), Some(&building))?;
+        Ok(building)
+    }).ok()
+}
+
+fn save_building(conn: &PgConnection, id: Option<uuid::Uuid>, building_name: String, building_address: String, attributes: BuildingAttributes) -> Result<(Building, Option<Building>), Error> {
 
     match id {
         Some(uuid) => {
-            if let Ok(building) = buildings.find(uuid).get_result::<Building>(&conn) {
+            if let Ok(building) = buildings.find(uuid).get_result::<Building>(conn) {
+                if building.deleted_at.is_some() {
+                    return Err(Error::RollbackTransaction);
+                }
                 
-                diesel::update(&building)
-                    .set((b_name.eq(building_name), b_address.eq(building_address)))
-                    .get_result(&conn).ok()
+                let attributes = attributes.or(&building);
+                let updated = diesel::update(&building)
+                    .set((
+                        b_name.eq(building_name),
+                        b_address.eq(building_address),
+                        street.eq(attributes.street.flatten()),
+                        house_number.eq(attributes.house_number.flatten()),
+                        postal_code.eq(attributes.postal_code.flatten()),
+                        city.eq(attributes.city.flatten()),
+                        country.eq(attributes.country.flatten()),
+                        latitude.eq(attributes.latitude.flatten()),
+                        longitude.eq(attributes.longitude.flatten()),
+                        footprint.eq(attributes.footprint.flatten())
+                    ))
+                    .get_result(conn)?;
+                Ok((updated, Some(building)))
 
             } else {
             
-                let new_building = Building {
-                    id: uuid,
-                    name: building_name,
-                    address: building_address
-                };
+                let new_building = new_building(uuid, building_name, building_address, attributes);
 
-                diesel::insert_into(buildings)
+                let created = diesel::insert_into(buildings)
                     .values(new_building)
-                    .get_result(&conn).ok()
+                    .get_result(conn)?;
+                Ok((created, None))
             }
         },
         None => {
             
-            let new_building = Building {
-                id: Uuid::new_v4(),
-                name: building_name,
-                address: building_address
-            };
+            let new_building = new_building(Uuid::new_v4(), building_name, building_address, attributes);
             
-            diesel::insert_into(buildings)
+            let created = diesel::insert_into(buildings)
                 .values(new_building)
-                .get_result(&conn).ok()
+                .get_result(conn)?;
+            Ok((created, None))
         
         }
     }
 }
 
-/// Delete the building with the UUID id.
-/// Return true if deletion was successful, false if the UUID was not found.
-pub fn delete_building_by_id(id: uuid::Uuid) -> bool {
+fn new_building(id: uuid::Uuid, building_name: String, building_address: String, attributes: BuildingAttributes) -> Building {
+    Building {
+        id,
+        name: building_name,
+        address: building_address,
+        deleted_at: None,
+        street: attributes.street.flatten(),
+        house_number: attributes.house_number.flatten(),
+        postal_code: attributes.postal_code.flatten(),
+        city: attributes.city.flatten(),
+        country: attributes.country.flatten(),
+        latitude: attributes.latitude.flatten(),
+        longitude: attributes.longitude.flatten(),
+        footprint: attributes.footprint.flatten()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// How distances between buildings are computed, depending on the extensions installed in the database.
+enum GeoBackend {
+    PostGis,
+    EarthDistance,
+    InMemory
+}
+
+#[derive(QueryableByName)]
+struct InstalledExtension {
+    #[sql_type = "Text"]
+    extname: String
+}
+
+#[derive(QueryableByName)]
+struct BuildingDistance {
+    #[sql_type = "diesel::sql_types::Uuid"]
+    id: uuid::Uuid,
+    #[sql_type = "Double"]
+    distance: f64
+}
+
+static GEO_BACKEND: OnceLock<GeoBackend> = OnceLock::new();
+
+/// Pick the best available distance backend once and remember it.
+fn geo_backend() -> GeoBackend {
+    *GEO_BACKEND.get_or_init(|| {
+        let conn = connection().unwrap();
+        let installed : Vec<String> = diesel::sql_query("SELECT extname FROM pg_extension")
+            .load::<InstalledExtension>(&conn)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|extension| extension.extname)
+            .collect();
+
+        let backend = if installed.iter().any(|name| name == "postgis") {
+            GeoBackend::PostGis
+        } else if installed.iter().any(|name| name == "earthdistance") {
+            GeoBackend::EarthDistance
+        } else {
+            GeoBackend::InMemory
+        };
+        info!("using {:?} backend for building distances", backend);
+        backend
+    })
+}
+
+/// Return the buildings within `radius` metres of a position, nearest first.
+/// Buildings in the trash are only included if `include_deleted` is set.
+pub fn buildings_near(position: (f64, f64), radius: f64, include_deleted: bool) -> Vec<NearbyBuilding> {
+    let conn = connection().unwrap();
+    let (lat, lon) = position;
+
+    let query = match geo_backend() {
+        GeoBackend::PostGis => Some(
+            "SELECT id, ST_DistanceSphere(ST_MakePoint(longitude, latitude), ST_MakePoint($2, $1)) AS distance
+             FROM buildings
+             WHERE ($4 OR deleted_at IS NULL) AND latitude IS NOT NULL
+               AND ST_DWithin(ST_MakePoint(longitude, latitude)::geography, ST_MakePoint($2, $1)::geography, $3)
+             ORDER BY distance"
+        ),
+        GeoBackend::EarthDistance => Some(
+            "SELECT id, earth_distance(ll_to_earth(latitude, longitude), ll_to_earth($1, $2)) AS distance
+             FROM buildings
+             WHERE ($4 OR deleted_at IS NULL) AND latitude IS NOT NULL
+               AND earth_box(ll_to_earth($1, $2), $3) @> ll_to_earth(latitude, longitude)
+               AND earth_distance(ll_to_earth(latitude, longitude), ll_to_earth($1, $2)) <= $3
+             ORDER BY distance"
+        ),
+        GeoBackend::InMemory => None
+    };
+
+    match query {
+        Some(sql) => {
+            let distances = diesel::sql_query(sql)
+                .bind::<Double, _>(lat)
+                .bind::<Double, _>(lon)
+                .bind::<Double, _>(radius)
+                .bind::<Bool, _>(include_deleted)
+                .load::<BuildingDistance>(&conn)
+                .unwrap_or_default();
+
+            let ids : Vec<uuid::Uuid> = distances.iter().map(|found| found.id).collect();
+            let mut found : Vec<Building> = buildings.filter(b_id.eq_any(ids)).load::<Building>(&conn).unwrap_or_default();
+
+            distances.into_iter().filter_map(|entry| {
+                let index = found.iter().position(|building| building.id == entry.id)?;
+                Some(NearbyBuilding { building: found.swap_remove(index), distance: entry.distance })
+            }).collect()
+        },
+        None => nearest_first(get_buildings(include_deleted), position, radius)
+    }
+}
+
+/// Compute the distances of the buildings to a position in memory,
+/// keep the ones within `radius` metres and sort them nearest first.
+pub fn nearest_first(candidates: Vec<Building>, position: (f64, f64), radius: f64) -> Vec<NearbyBuilding> {
+    let mut nearby : Vec<NearbyBuilding> = candidates.into_iter()
+        .filter_map(|building| {
+            let distance = geo::distance(position, (building.latitude?, building.longitude?));
+            Some(NearbyBuilding { building, distance })
+        })
+        .filter(|nearby| nearby.distance <= radius)
+        .collect();
+    nearby.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    nearby
+}
+
+/// Move the building with the UUID id to the trash.
+/// Return true if deletion was successful, false if the UUID was not found or is already deleted.
+pub fn delete_building_by_id(audit: &Audit, id: uuid::Uuid) -> bool {
+    let deleted = delete_building_by_id_with(&connection().unwrap(), audit, id);
+    if deleted {
+        invalidate("building", id);
+    }
+    deleted
+}
+
+pub fn delete_building_by_id_with(conn: &PgConnection, audit: &Audit, id: uuid::Uuid) -> bool {
+    conn.transaction::<_, Error, _>(|| {
+        let building : Building = diesel::update(buildings.find(id).filter(b_deleted_at.is_null()))
+            .set(b_deleted_at.eq(Utc::now()))
+            .get_result(conn)?;
+        record_event(conn, "building", Change::Deleted, id, &building)?;
+        record_change(conn, audit, "delete", "building", id, Some(&Building { deleted_at: None, ..building }), None)
+    }).is_ok()
+}
+
+/// Take the building with the UUID id out of the trash.
+/// Return true if the building was restored, false if the UUID was not found in the trash.
+pub fn restore_building_by_id(audit: &Audit, id: uuid::Uuid) -> bool {
     let conn = connection().unwrap();
-    matches!(diesel::delete(buildings.find(id)).execute(&conn), Ok(1))
-}
\ No newline at end of file
+    conn.transaction::<_, Error, _>(|| {
+        let trashed : Building = buildings.find(id).filter(b_deleted_at.is_not_null()).for_update().first(&conn)?;
+        let building : Building = diesel::update(&trashed)
+            .set(b_deleted_at.eq(None::<DateTime<Utc>>))
+            .get_result(&conn)?;
+        record_event(&conn, "building", Change::Created, id, &building)?;
+        record_change(&conn, audit, "restore", "building", id, Some(&trashed), Some(&building))
+    }).inspect(|_| invalidate("building", id)).is_ok()
+}
+
+/// Permanently remove buildings that were moved to the trash before `cutoff`.
+/// Buildings that still have storeys (deleted or not) are kept until those are purged.
+/// Return the UUIDs of the removed buildings.
+pub fn purge_deleted_buildings(conn: &PgConnection, cutoff: DateTime<Utc>) -> Vec<uuid::Uuid> {
+    diesel::delete(
+        buildings
+            .filter(b_deleted_at.lt(cutoff))
+            .filter(b_id.ne_all(storeys::table.select(storeys::building_id)))
+    ).returning(b_id).get_results(conn).unwrap_or_default()
+}
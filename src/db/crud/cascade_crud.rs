@@ -0,0 +1,161 @@
+use chrono::{DateTime, Utc};
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::result::Error;
+
+use crate::db::crud::rooms_crud::rooms_by_storey;
+use crate::db::crud::storeys_crud::storeys_by_building;
+use crate::db::crud::audit_crud::{record_change, Audit};
+use crate::db::crud::outbox_crud::record_event;
+use crate::db::models::{Building, DeletionReport, Room, Storey};
+use crate::db::schema::{buildings, storeys, rooms};
+use crate::cache::invalidate_all;
+use crate::outbox::Change;
+use std::collections::HashSet;
+
+use crate::dbconn::connection;
+
+/// Collect a building and all of its storeys and rooms that are not in the trash yet.
+pub fn building_subtree(id: uuid::Uuid) -> DeletionReport {
+    let storey_ids : Vec<uuid::Uuid> = storeys_by_building(id, false).iter().map(|storey| storey.id).collect();
+    let room_ids = storey_ids.iter()
+        .flat_map(|storey_id| rooms_by_storey(*storey_id, false))
+        .map(|room| room.id)
+        .collect();
+
+    DeletionReport { buildings: vec![id], storeys: storey_ids, rooms: room_ids }
+}
+
+/// Collect a storey and all of its rooms that are not in the trash yet.
+pub fn storey_subtree(id: uuid::Uuid) -> DeletionReport {
+    let room_ids = rooms_by_storey(id, false).iter().map(|room| room.id).collect();
+    DeletionReport { buildings: Vec::new(), storeys: vec![id], rooms: room_ids }
+}
+
+/// Lock the assets of the report and everything below them, top down, and check that they still make up the report.
+/// With the parents locked, a storey or room cannot be added below them until the transaction ends.
+/// Return false if an asset is gone or in the trash, or if one was added or moved below them after the report was created.
+fn lock_subtree(conn: &PgConnection, report: &DeletionReport) -> Result<bool, Error> {
+    let same = |found: Vec<uuid::Uuid>, expected: &[uuid::Uuid]| {
+        found.into_iter().collect::<HashSet<_>>() == expected.iter().copied().collect::<HashSet<_>>()
+    };
+
+    let found_buildings : Vec<uuid::Uuid> = buildings::table
+        .select(buildings::id)
+        .filter(buildings::id.eq_any(&report.buildings))
+        .filter(buildings::deleted_at.is_null())
+        .for_update()
+        .load(conn)?;
+    let found_storeys : Vec<uuid::Uuid> = storeys::table
+        .select(storeys::id)
+        .filter(storeys::id.eq_any(&report.storeys).or(storeys::building_id.eq_any(&report.buildings)))
+        .filter(storeys::deleted_at.is_null())
+        .for_update()
+        .load(conn)?;
+    let found_rooms : Vec<uuid::Uuid> = rooms::table
+        .select(rooms::id)
+        .filter(rooms::id.eq_any(&report.rooms).or(rooms::storey_id.eq_any(&report.storeys)))
+        .filter(rooms::deleted_at.is_null())
+        .for_update()
+        .load(conn)?;
+
+    Ok(same(found_buildings, &report.buildings) && same(found_storeys, &report.storeys) && same(found_rooms, &report.rooms))
+}
+
+/// Move every asset in the report to the trash in a single transaction.
+/// Nothing is deleted if one of the assets is already gone
+/// or if a storey or room was added below them after the report was created.
+/// Return true if the whole subtree was deleted.
+pub fn delete_subtree(audit: &Audit, report: &DeletionReport) -> bool {
+    let conn = connection().unwrap();
+    let now = Utc::now();
+
+    conn.transaction::<_, Error, _>(|| {
+
+        if !lock_subtree(&conn, report)? {
+            return Err(Error::RollbackTransaction);
+        }
+
+        let deleted_rooms : Vec<Room> = diesel::update(
+            rooms::table.filter(rooms::id.eq_any(&report.rooms)).filter(rooms::deleted_at.is_null())
+        ).set(rooms::deleted_at.eq(now)).get_results(&conn)?;
+        for room in &deleted_rooms {
+            record_event(&conn, "room", Change::Deleted, room.id, room)?;
+            record_change(&conn, audit, "delete", "room", room.id, Some(&Room { deleted_at: None, ..room.clone() }), None)?;
+        }
+
+        let deleted_storeys : Vec<Storey> = diesel::update(
+            storeys::table.filter(storeys::id.eq_any(&report.storeys)).filter(storeys::deleted_at.is_null())
+        ).set(storeys::deleted_at.eq(now)).get_results(&conn)?;
+        for storey in &deleted_storeys {
+            record_event(&conn, "storey", Change::Deleted, storey.id, storey)?;
+            record_change(&conn, audit, "delete", "storey", storey.id, Some(&Storey { deleted_at: None, ..storey.clone() }), None)?;
+        }
+
+        let deleted_buildings : Vec<Building> = diesel::update(
+            buildings::table.filter(buildings::id.eq_any(&report.buildings)).filter(buildings::deleted_at.is_null())
+        ).set(buildings::deleted_at.eq(now)).get_results(&conn)?;
+        for building in &deleted_buildings {
+            record_event(&conn, "building", Change::Deleted, building.id, building)?;
+            record_change(&conn, audit, "delete", "building", building.id, Some(&Building { deleted_at: None, ..building.clone() }), None)?;
+        }
+
+        Ok(())
+
+    }).inspect(|_| invalidate_subtree(report)).is_ok()
+}
+
+/// Take every asset in the report out of the trash again in a single transaction, undoing `delete_subtree`.
+/// Return true if all of them were restored.
+pub fn restore_subtree(audit: &Audit, report: &DeletionReport) -> bool {
+    let conn = connection().unwrap();
+    let restored = None::<DateTime<Utc>>;
+
+    conn.transaction::<_, Error, _>(|| {
+
+        // the trashed state goes to the audit log, so it is locked until the assets are back
+        let trashed_buildings : Vec<Building> = buildings::table.filter(buildings::id.eq_any(&report.buildings)).for_update().load(&conn)?;
+        let trashed_storeys : Vec<Storey> = storeys::table.filter(storeys::id.eq_any(&report.storeys)).for_update().load(&conn)?;
+        let trashed_rooms : Vec<Room> = rooms::table.filter(rooms::id.eq_any(&report.rooms)).for_update().load(&conn)?;
+
+        let restored_buildings : Vec<Building> = diesel::update(
+            buildings::table.filter(buildings::id.eq_any(&report.buildings)).filter(buildings::deleted_at.is_not_null())
+        ).set(buildings::deleted_at.eq(restored)).get_results(&conn)?;
+        for building in &restored_buildings {
+            record_event(&conn, "building", Change::Created, building.id, building)?;
+            record_change(&conn, audit, "restore", "building", building.id, trashed_buildings.iter().find(|old| old.id == building.id), Some(building))?;
+        }
+
+        let restored_storeys : Vec<Storey> = diesel::update(
+            storeys::table.filter(storeys::id.eq_any(&report.storeys)).filter(storeys::deleted_at.is_not_null())
+        ).set(storeys::deleted_at.eq(restored)).get_results(&conn)?;
+        for storey in &restored_storeys {
+            record_event(&conn, "storey", Change::Created, storey.id, storey)?;
+            record_change(&conn, audit, "restore", "storey", storey.id, trashed_storeys.iter().find(|old| old.id == storey.id), Some(storey))?;
+        }
+
+        let restored_rooms : Vec<Room> = diesel::update(
+            rooms::table.filter(rooms::id.eq_any(&report.rooms)).filter(rooms::deleted_at.is_not_null())
+        ).set(rooms::deleted_at.eq(restored)).get_results(&conn)?;
+        for room in &restored_rooms {
+            record_event(&conn, "room", Change::Created, room.id, room)?;
+            record_change(&conn, audit, "restore", "room", room.id, trashed_rooms.iter().find(|old| old.id == room.id), Some(room))?;
+        }
+
+        if restored_rooms.len() != report.rooms.len()
+            || restored_storeys.len() != report.storeys.len()
+            || restored_buildings.len() != report.buildings.len() {
+            return Err(Error::RollbackTransaction);
+        }
+
+        Ok(())
+
+    }).inspect(|_| invalidate_subtree(report)).is_ok()
+}
+
+/// Drop the cached reads of every asset in the report, once its transaction has committed.
+fn invalidate_subtree(report: &DeletionReport) {
+    let buildings = report.buildings.iter().map(|id| ("building", *id));
+    let storeys = report.storeys.iter().map(|id| ("storey", *id));
+    let rooms = report.rooms.iter().map(|id| ("room", *id));
+    invalidate_all(buildings.chain(storeys).chain(rooms));
+}
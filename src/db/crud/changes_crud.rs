@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use diesel::{BoolExpressionMethods, ExpressionMethods, PgArrayExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::dsl::sql;
+use diesel::expression::SqlLiteral;
+use diesel::sql_types::BigInt;
+use uuid::Uuid;
+
+use crate::db::models::{AssetChange, StreamPosition};
+use crate::db::schema::{asset_changes, collection_version};
+
+use crate::dbconn::connection;
+
+// The ID of a change is taken when it is written, so a change can commit after others with higher IDs.
+// Readers therefore only go as far as the settled changes, those of transactions older than every running one:
+// every change that turns up later comes from a newer transaction and has a higher `StreamPosition`.
+
+/// Transactions before this one have ended, their changes are settled.
+fn settled_before() -> SqlLiteral<BigInt> {
+    sql("pg_snapshot_xmin(pg_current_snapshot())::text::bigint")
+}
+
+/// Return up to `limit` settled changes after `position`, in the order of their position.
+/// With a building, only the changes of that building and the storeys and rooms in it are returned.
+pub fn changes_after(conn: &PgConnection, position: StreamPosition, building: Option<Uuid>, limit: i64) -> Vec<AssetChange> {
+    let (tx, id) = position;
+    let mut query = asset_changes::table
+        .filter(asset_changes::tx.gt(tx).or(asset_changes::tx.eq(tx).and(asset_changes::id.gt(id))))
+        .filter(asset_changes::tx.lt(settled_before()))
+        .into_boxed();
+    if let Some(building) = building {
+        query = query.filter(asset_changes::building_ids.contains(vec![building]));
+    }
+    query.order((asset_changes::tx, asset_changes::id)).limit(limit).load::<AssetChange>(conn).unwrap_or_default()
+}
+
+/// Check if there are committed changes after `position` that are not settled yet.
+pub fn unsettled_changes_after(conn: &PgConnection, position: StreamPosition) -> bool {
+    let (tx, id) = position;
+    diesel::select(diesel::dsl::exists(asset_changes::table
+        .filter(asset_changes::tx.gt(tx).or(asset_changes::tx.eq(tx).and(asset_changes::id.gt(id))))
+        .filter(asset_changes::tx.ge(settled_before()))))
+        .get_result(conn)
+        .unwrap_or(false)
+}
+
+/// Return the position of the newest settled change, where a new stream starts, or (0, 0) if there is none.
+pub fn latest_position(conn: &PgConnection) -> StreamPosition {
+    asset_changes::table
+        .select((asset_changes::tx, asset_changes::id))
+        .filter(asset_changes::tx.lt(settled_before()))
+        .order((asset_changes::tx.desc(), asset_changes::id.desc()))
+        .first::<StreamPosition>(conn)
+        .unwrap_or((0, 0))
+}
+
+/// Return the position of the change with the ID, None if it is not kept anymore.
+pub fn change_position(conn: &PgConnection, id: i64) -> Option<StreamPosition> {
+    asset_changes::table
+        .select((asset_changes::tx, asset_changes::id))
+        .find(id)
+        .first::<StreamPosition>(conn)
+        .ok()
+}
+
+/// Return the ID of the oldest change that is kept, None if there is none.
+pub fn oldest_change_id(conn: &PgConnection) -> Option<i64> {
+    asset_changes::table
+        .select(diesel::dsl::min(asset_changes::id))
+        .first::<Option<i64>>(conn)
+        .unwrap_or(None)
+}
+
+/// Return the version of the asset collections and when it last went up.
+/// Every committed write to buildings, storeys or rooms counts it up, see the `collection_version` migration.
+pub fn collection_version() -> Option<(i64, DateTime<Utc>)> {
+    let conn = connection().unwrap();
+    collection_version::table
+        .select((collection_version::version, collection_version::changed_at))
+        .first::<(i64, DateTime<Utc>)>(&conn)
+        .ok()
+}
+
+/// Remove changes made before `cutoff`, streams can't be resumed from them anymore.
+/// Return the number of removed changes.
+pub fn purge_changes(conn: &PgConnection, cutoff: DateTime<Utc>) -> usize {
+    diesel::delete(asset_changes::table.filter(asset_changes::occurred_at.lt(cutoff)))
+        .execute(conn)
+        .unwrap_or(0)
+}
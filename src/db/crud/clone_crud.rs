@@ -0,0 +1,224 @@
+use uuid::Uuid;
+
+use chrono::Utc;
+use diesel::{Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::result::Error;
+use log::{error, warn};
+use std::io;
+
+use crate::db::crud::audit_crud::{record_change, Audit};
+use crate::db::crud::outbox_crud::record_event;
+use crate::db::models::{Building, CloneMapping, FloorPlan, NamePattern, Room, RoomEquipment, Storey};
+use crate::db::schema::{buildings, floor_plans, room_equipment, rooms, storeys};
+use crate::cache::invalidate_all;
+use crate::floorplan::StagedPlanFile;
+use crate::outbox::Change;
+
+use crate::dbconn::connection;
+
+#[derive(Default)]
+/// Assets created by a clone, each with the UUID of its original.
+pub struct ClonedSubtree {
+    pub buildings: Vec<(Uuid, Building)>,
+    pub storeys: Vec<(Uuid, Storey)>,
+    pub rooms: Vec<(Uuid, Room)>,
+    pub room_equipment: Vec<(Uuid, RoomEquipment)>,
+    plan_files: Vec<StagedPlanFile>
+}
+
+impl ClonedSubtree {
+    /// The created buildings, storeys and rooms by type and UUID.
+    fn assets(&self) -> impl Iterator<Item = (&'static str, Uuid)> + '_ {
+        let buildings = self.buildings.iter().map(|(_, building)| ("building", building.id));
+        let storeys = self.storeys.iter().map(|(_, storey)| ("storey", storey.id));
+        let rooms = self.rooms.iter().map(|(_, room)| ("room", room.id));
+        buildings.chain(storeys).chain(rooms)
+    }
+
+    /// Give the copied floor plan files their names if the clone was committed, remove them otherwise.
+    fn finish_plan_files(&mut self, committed: bool) {
+        for file in self.plan_files.drain(..) {
+            if !committed {
+                file.discard();
+                continue;
+            }
+            let file_name = file.file_name.clone();
+            if let Err(e) = file.commit() {
+                warn!("could not move copied floor plan file to {}: {}", file_name, e);
+            }
+        }
+    }
+
+    pub fn mapping(&self) -> CloneMapping {
+        CloneMapping {
+            buildings: self.buildings.iter().map(|(old, new)| (*old, new.id)).collect(),
+            storeys: self.storeys.iter().map(|(old, new)| (*old, new.id)).collect(),
+            rooms: self.rooms.iter().map(|(old, new)| (*old, new.id)).collect(),
+            room_equipment: self.room_equipment.iter().map(|(old, new)| (*old, new.id)).collect()
+        }
+    }
+}
+
+/// Copy a storey that is not in the trash, with its rooms, their equipment and the floor plan, into a building.
+/// `copy_plan_file` copies a floor plan file for the new storey, the copies get their names once the clone is committed.
+/// Everything is copied in one transaction, return None if the storey is not found or copying failed.
+pub fn clone_storey_by_id<F>(audit: &Audit, id: Uuid, target_building: Uuid, storey_name: String, storey_level: Option<i32>, pattern: Option<&NamePattern>, copy_plan_file: F) -> Option<ClonedSubtree>
+where F: Fn(&str) -> io::Result<StagedPlanFile> {
+    let conn = connection().unwrap();
+    let mut cloned = ClonedSubtree::default();
+
+    let copied = conn.transaction::<_, Error, _>(|| {
+        let source : Storey = storeys::table.find(id).filter(storeys::deleted_at.is_null()).first(&conn)?;
+        copy_storey(&conn, audit, &source, target_building, storey_name, storey_level, pattern, &copy_plan_file, &mut cloned)
+    });
+    cloned.finish_plan_files(copied.is_ok());
+    copied.ok()?;
+
+    invalidate_all(cloned.assets());
+    Some(cloned)
+}
+
+/// Copy a building that is not in the trash, with all of its storeys and their contents, under a new name and maybe address.
+/// The position and footprint of the building and its rooms are not copied, the copy stands somewhere else.
+/// Return None if the building is not found or copying failed.
+pub fn clone_building_by_id<F>(audit: &Audit, id: Uuid, building_name: String, building_address: Option<String>, pattern: Option<&NamePattern>, copy_plan_file: F) -> Option<ClonedSubtree>
+where F: Fn(&str) -> io::Result<StagedPlanFile> {
+    let conn = connection().unwrap();
+    let mut cloned = ClonedSubtree::default();
+
+    let copied = conn.transaction::<_, Error, _>(|| {
+        let source : Building = buildings::table.find(id).filter(buildings::deleted_at.is_null()).first(&conn)?;
+
+        // a new address replaces the structured one as well, it would not match anymore
+        let same_address = building_address.is_none();
+        let building : Building = diesel::insert_into(buildings::table)
+            .values(Building {
+                id: Uuid::new_v4(),
+                name: building_name,
+                address: building_address.unwrap_or(source.address.clone()),
+                deleted_at: None,
+                street: source.street.clone().filter(|_| same_address),
+                house_number: source.house_number.clone().filter(|_| same_address),
+                postal_code: source.postal_code.clone().filter(|_| same_address),
+                city: source.city.clone().filter(|_| same_address),
+                country: source.country.clone().filter(|_| same_address),
+                latitude: None,
+                longitude: None,
+                footprint: None
+            })
+            .get_result(&conn)?;
+        record_event(&conn, "building", Change::Created, building.id, &building)?;
+        record_change(&conn, audit, "create", "building", building.id, None, Some(&building))?;
+
+        let source_storeys : Vec<Storey> = storeys::table
+            .filter(storeys::building_id.eq(id))
+            .filter(storeys::deleted_at.is_null())
+            .load(&conn)?;
+        for storey in &source_storeys {
+            let storey_name = rename(&storey.name, pattern);
+            copy_storey(&conn, audit, storey, building.id, storey_name, storey.level, pattern, &copy_plan_file, &mut cloned)?;
+        }
+
+        cloned.buildings.push((source.id, building));
+        Ok(())
+    });
+    cloned.finish_plan_files(copied.is_ok());
+    copied.ok()?;
+
+    invalidate_all(cloned.assets());
+    Some(cloned)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn copy_storey<F>(conn: &PgConnection, audit: &Audit, source: &Storey, target_building: Uuid, storey_name: String, storey_level: Option<i32>, pattern: Option<&NamePattern>, copy_plan_file: &F, cloned: &mut ClonedSubtree) -> Result<(), Error>
+where F: Fn(&str) -> io::Result<StagedPlanFile> {
+    let storey : Storey = diesel::insert_into(storeys::table)
+        .values(Storey {
+            id: Uuid::new_v4(),
+            name: storey_name,
+            building_id: target_building,
+            deleted_at: None,
+            level: storey_level,
+            elevation: source.elevation,
+            display_order: source.display_order
+        })
+        .get_result(conn)?;
+    record_event(conn, "storey", Change::Created, storey.id, &storey)?;
+    record_change(conn, audit, "create", "storey", storey.id, None, Some(&storey))?;
+
+    let source_rooms : Vec<Room> = rooms::table
+        .filter(rooms::storey_id.eq(source.id))
+        .filter(rooms::deleted_at.is_null())
+        .load(conn)?;
+    for room in source_rooms {
+        let copy : Room = diesel::insert_into(rooms::table)
+            .values(Room {
+                id: Uuid::new_v4(),
+                name: rename(&room.name, pattern),
+                storey_id: storey.id,
+                deleted_at: None,
+                seated_capacity: room.seated_capacity,
+                standing_capacity: room.standing_capacity,
+                room_type: room.room_type.clone(),
+                area: room.area,
+                wheelchair_accessible: room.wheelchair_accessible,
+                hearing_loop: room.hearing_loop,
+                latitude: None,
+                longitude: None,
+                footprint: None,
+                plan_polygon: room.plan_polygon.clone()
+            })
+            .get_result(conn)?;
+        record_event(conn, "room", Change::Created, copy.id, &copy)?;
+        record_change(conn, audit, "create", "room", copy.id, None, Some(&copy))?;
+
+        // serial numbers belong to the actual items, the copy only gets the same kind of equipment
+        let entries : Vec<RoomEquipment> = room_equipment::table.filter(room_equipment::room_id.eq(room.id)).load(conn)?;
+        for entry in entries {
+            let entry_copy : RoomEquipment = diesel::insert_into(room_equipment::table)
+                .values(RoomEquipment {
+                    id: Uuid::new_v4(),
+                    room_id: copy.id,
+                    equipment_id: entry.equipment_id,
+                    quantity: entry.quantity,
+                    serial_number: None
+                })
+                .get_result(conn)?;
+            record_change(conn, audit, "create", "room_equipment", entry_copy.id, None, Some(&entry_copy))?;
+            cloned.room_equipment.push((entry.id, entry_copy));
+        }
+
+        cloned.rooms.push((room.id, copy));
+    }
+
+    let plan : Option<FloorPlan> = floor_plans::table.find(source.id).first(conn).optional()?;
+    if let Some(plan) = plan {
+        let path = match plan.path {
+            Some(ref old_path) => match copy_plan_file(old_path) {
+                Ok(copy) => {
+                    let new_path = copy.file_name.clone();
+                    cloned.plan_files.push(copy);
+                    Some(new_path)
+                },
+                Err(e) => {
+                    error!("could not copy floor plan file {}: {}", old_path, e);
+                    return Err(Error::RollbackTransaction);
+                }
+            },
+            None => None
+        };
+        diesel::insert_into(floor_plans::table)
+            .values(FloorPlan { storey_id: storey.id, path, uploaded_at: Utc::now(), ..plan })
+            .execute(conn)?;
+    }
+
+    cloned.storeys.push((source.id, storey));
+    Ok(())
+}
+
+fn rename(name: &str, pattern: Option<&NamePattern>) -> String {
+    match pattern {
+        Some(pattern) => pattern.apply(name),
+        None => name.to_string()
+    }
+}
@@ -0,0 +1,153 @@
+use uuid::Uuid;
+
+use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::result::Error;
+
+use crate::db::crud::audit_crud::{record_change, Audit};
+use crate::db::models::{Equipment, RoomEquipment};
+use crate::db::schema::equipment;
+use crate::db::schema::room_equipment;
+
+use crate::dbconn::connection;
+
+/// Return the whole equipment catalogue.
+pub fn get_equipment() -> Vec<Equipment> {
+    let conn = connection().unwrap();
+    equipment::table.order(equipment::key.asc()).load::<Equipment>(&conn).unwrap_or_default()
+}
+
+/// Find an equipment type by UUID.
+pub fn find_equipment_by_id(id: uuid::Uuid) -> Option<Equipment> {
+    let conn = connection().unwrap();
+    equipment::table.find(id).first::<Equipment>(&conn).ok()
+}
+
+/// Pass an equipment key and name, maybe a UUID.
+/// If the UUID already exists, update the equipment type, otherwise create it (with a new UUID if none is given).
+/// Return None if the key is already used by another equipment type.
+pub fn create_or_update_equipment(audit: &Audit, id: Option<uuid::Uuid>, equipment_key: String, equipment_name: String) -> Option<Equipment> {
+    let conn = connection().unwrap();
+
+    conn.transaction::<_, Error, _>(|| {
+        if let Some(uuid) = id {
+            if let Ok(existing) = equipment::table.find(uuid).get_result::<Equipment>(&conn) {
+                let updated : Equipment = diesel::update(&existing)
+                    .set((equipment::key.eq(equipment_key), equipment::name.eq(equipment_name)))
+                    .get_result(&conn)?;
+                record_change(&conn, audit, "update", "equipment", uuid, Some(&existing), Some(&updated))?;
+                return Ok(updated);
+            }
+        }
+
+        let new_equipment = Equipment {
+            id: id.unwrap_or_else(Uuid::new_v4),
+            key: equipment_key,
+            name: equipment_name
+        };
+
+        let created : Equipment = diesel::insert_into(equipment::table)
+            .values(new_equipment)
+            .get_result(&conn)?;
+        record_change(&conn, audit, "create", "equipment", created.id, None, Some(&created))?;
+        Ok(created)
+    }).ok()
+}
+
+/// Check if any room has equipment of this type.
+pub fn equipment_in_use(id: uuid::Uuid) -> bool {
+    let conn = connection().unwrap();
+    diesel::select(diesel::dsl::exists(room_equipment::table.filter(room_equipment::equipment_id.eq(id))))
+        .get_result(&conn)
+        .unwrap_or(false)
+}
+
+/// Delete the equipment type with the UUID id from the catalogue.
+/// Return true if deletion was successful, false if the UUID was not found.
+pub fn delete_equipment_by_id(audit: &Audit, id: uuid::Uuid) -> bool {
+    let conn = connection().unwrap();
+    conn.transaction::<_, Error, _>(|| {
+        let deleted : Equipment = diesel::delete(equipment::table.find(id)).get_result(&conn)?;
+        record_change(&conn, audit, "delete", "equipment", id, Some(&deleted), None)
+    }).is_ok()
+}
+
+/// Return the equipment of a room.
+pub fn equipment_of_room(id: uuid::Uuid) -> Vec<RoomEquipment> {
+    let conn = connection().unwrap();
+    room_equipment::table.filter(room_equipment::room_id.eq(id)).load::<RoomEquipment>(&conn).unwrap_or_default()
+}
+
+/// Find a room equipment entry by UUID.
+pub fn find_room_equipment_by_id(id: uuid::Uuid) -> Option<RoomEquipment> {
+    let conn = connection().unwrap();
+    room_equipment::table.find(id).first::<RoomEquipment>(&conn).ok()
+}
+
+/// Pass the equipment type, quantity and serial number of equipment in a room, maybe a UUID.
+/// If the UUID already exists, update the entry, otherwise create it (with a new UUID if none is given).
+pub fn create_or_update_room_equipment(audit: &Audit, id: Option<uuid::Uuid>, room: uuid::Uuid, equipment_type: uuid::Uuid, amount: i32, serial: Option<String>) -> Option<RoomEquipment> {
+    let conn = connection().unwrap();
+
+    conn.transaction::<_, Error, _>(|| {
+        if let Some(uuid) = id {
+            if let Ok(existing) = room_equipment::table.find(uuid).get_result::<RoomEquipment>(&conn) {
+                let updated : RoomEquipment = diesel::update(&existing)
+                    .set((
+                        room_equipment::room_id.eq(room),
+                        room_equipment::equipment_id.eq(equipment_type),
+                        room_equipment::quantity.eq(amount),
+                        room_equipment::serial_number.eq(serial)
+                    ))
+                    .get_result(&conn)?;
+                record_change(&conn, audit, "update", "room_equipment", uuid, Some(&existing), Some(&updated))?;
+                return Ok(updated);
+            }
+        }
+
+        let new_entry = RoomEquipment {
+            id: id.unwrap_or_else(Uuid::new_v4),
+            room_id: room,
+            equipment_id: equipment_type,
+            quantity: amount,
+            serial_number: serial
+        };
+
+        let created : RoomEquipment = diesel::insert_into(room_equipment::table)
+            .values(new_entry)
+            .get_result(&conn)?;
+        record_change(&conn, audit, "create", "room_equipment", created.id, None, Some(&created))?;
+        Ok(created)
+    }).ok()
+}
+
+/// Delete the room equipment entry with the UUID id.
+/// Return true if deletion was successful, false if the UUID was not found.
+pub fn delete_room_equipment_by_id(audit: &Audit, id: uuid::Uuid) -> bool {
+    let conn = connection().unwrap();
+    conn.transaction::<_, Error, _>(|| {
+        let deleted : RoomEquipment = diesel::delete(room_equipment::table.find(id)).get_result(&conn)?;
+        record_change(&conn, audit, "delete", "room_equipment", id, Some(&deleted), None)
+    }).is_ok()
+}
+
+/// Return the UUIDs of all rooms that have every one of the given equipment keys.
+pub fn rooms_with_equipment(keys: &[String]) -> Vec<uuid::Uuid> {
+    let conn = connection().unwrap();
+    let mut matching : Option<Vec<uuid::Uuid>> = None;
+
+    for equipment_key in keys {
+        let room_ids : Vec<uuid::Uuid> = room_equipment::table
+            .inner_join(equipment::table)
+            .filter(equipment::key.eq(equipment_key))
+            .select(room_equipment::room_id)
+            .load(&conn)
+            .unwrap_or_default();
+
+        matching = Some(match matching {
+            Some(previous) => previous.into_iter().filter(|id| room_ids.contains(id)).collect(),
+            None => room_ids
+        });
+    }
+
+    matching.unwrap_or_default()
+}
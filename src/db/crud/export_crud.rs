@@ -0,0 +1,74 @@
+use diesel::{Connection, PgConnection, RunQueryDsl};
+use diesel::sql_types::{Array, Bool, Integer, Nullable, Text};
+use log::error;
+
+use crate::db::models::{ExportFilter, ExportRow};
+
+/// Number of rows fetched from the export cursor at a time.
+const EXPORT_BATCH_SIZE: usize = 500;
+
+/// The flattened hierarchy, every filter is a bind parameter that is ignored if it is NULL (or empty for the equipment keys).
+/// Storeys are joined only if they are not in the trash, unless `include_deleted` ($3) is set, rooms likewise.
+/// If any room criteria are set, rows without a matching room are left out.
+const EXPORT_QUERY: &str = "
+DECLARE asset_export NO SCROLL CURSOR FOR
+SELECT b.id AS building_id, b.name AS building_name, b.address, b.street, b.house_number, b.postal_code, b.city, b.country,
+       b.latitude AS building_latitude, b.longitude AS building_longitude, b.deleted_at AS building_deleted_at,
+       s.id AS storey_id, s.name AS storey_name, s.level, s.elevation, s.display_order, s.deleted_at AS storey_deleted_at,
+       r.id AS room_id, r.name AS room_name, r.seated_capacity, r.standing_capacity, r.room_type, r.area,
+       r.wheelchair_accessible, r.hearing_loop, r.latitude AS room_latitude, r.longitude AS room_longitude, r.deleted_at AS room_deleted_at
+FROM buildings b
+LEFT JOIN storeys s ON s.building_id = b.id AND ($3 OR s.deleted_at IS NULL)
+LEFT JOIN rooms r ON r.storey_id = s.id AND ($3 OR r.deleted_at IS NULL)
+    AND ($4::integer IS NULL OR r.seated_capacity >= $4 OR r.standing_capacity >= $4)
+    AND ($5::text IS NULL OR r.room_type = $5)
+    AND ($6::boolean IS NULL OR r.wheelchair_accessible = $6)
+    AND ($7::boolean IS NULL OR r.hearing_loop = $7)
+    AND (cardinality($8::text[]) = 0 OR r.id IN (
+        SELECT re.room_id FROM room_equipment re JOIN equipment e ON e.id = re.equipment_id
+        WHERE e.key = ANY($8) GROUP BY re.room_id HAVING count(DISTINCT e.key) = cardinality($8)))
+WHERE ($3 OR b.deleted_at IS NULL)
+    AND ($1::uuid IS NULL OR b.id = $1)
+    AND ($2::uuid IS NULL OR s.id = $2)
+    AND (NOT $9 OR r.id IS NOT NULL)
+ORDER BY b.name, b.id, s.level NULLS LAST, s.display_order NULLS LAST, s.name, s.id, r.name, r.id";
+
+/// Export the asset hierarchy matching the filter row by row, without loading it all at once.
+/// The rows are read from a cursor in batches and handed to `write_row`, which can stop the export by returning an error,
+/// e.g. when the client has gone away. Returns the number of exported rows, or None if the export failed.
+/// The connection is passed in, so the caller can answer with an error before streaming if there is none.
+pub fn export_assets<F>(conn: &PgConnection, filter: &ExportFilter, mut write_row: F) -> Option<usize>
+where F: FnMut(ExportRow) -> std::io::Result<()> {
+
+    let result = conn.transaction::<usize, diesel::result::Error, _>(|| {
+        diesel::sql_query(EXPORT_QUERY)
+            .bind::<Nullable<diesel::sql_types::Uuid>, _>(filter.building_id)
+            .bind::<Nullable<diesel::sql_types::Uuid>, _>(filter.storey_id)
+            .bind::<Bool, _>(filter.include_deleted.unwrap_or(false))
+            .bind::<Nullable<Integer>, _>(filter.min_capacity)
+            .bind::<Nullable<Text>, _>(filter.room_type.clone())
+            .bind::<Nullable<Bool>, _>(filter.wheelchair_accessible)
+            .bind::<Nullable<Bool>, _>(filter.hearing_loop)
+            .bind::<Array<Text>, _>(filter.equipment_keys())
+            .bind::<Bool, _>(filter.filters_rooms())
+            .execute(conn)?;
+
+        let mut count = 0;
+        loop {
+            let batch = diesel::sql_query(format!("FETCH {} FROM asset_export", EXPORT_BATCH_SIZE)).load::<ExportRow>(conn)?;
+            let done = batch.len() < EXPORT_BATCH_SIZE;
+            for row in batch {
+                if let Err(e) = write_row(row) {
+                    error!("export stopped after {} rows: {}", count, e);
+                    return Err(diesel::result::Error::RollbackTransaction);
+                }
+                count += 1;
+            }
+            if done {
+                return Ok(count);
+            }
+        }
+    });
+
+    result.ok()
+}
@@ -0,0 +1,55 @@
+use diesel::{Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::result::Error;
+
+use crate::db::crud::audit_crud::{record_change, Audit};
+
+use crate::db::models::FloorPlan;
+use crate::db::schema::floor_plans::dsl::floor_plans;
+use crate::db::schema::floor_plans::{path, storey_id};
+
+use crate::dbconn::connection;
+
+/// Find the floor plan of a storey.
+/// Returns None if the storey has no floor plan.
+pub fn find_floor_plan(id: uuid::Uuid) -> Option<FloorPlan> {
+    let conn = connection().unwrap();
+    floor_plans.find(id).first::<FloorPlan>(&conn).ok()
+}
+
+/// Store the floor plan of a storey, replacing the previous one.
+/// Returns the stored floor plan and the one it replaced, so its file can be removed once this is committed.
+pub fn save_floor_plan(audit: &Audit, plan: FloorPlan) -> Option<(FloorPlan, Option<FloorPlan>)> {
+    let conn = connection().unwrap();
+    conn.transaction::<_, Error, _>(|| {
+        let before : Option<FloorPlan> = floor_plans.find(plan.storey_id).for_update().first(&conn).optional()?;
+        let saved : FloorPlan = diesel::insert_into(floor_plans)
+            .values(&plan)
+            .on_conflict(storey_id)
+            .do_update()
+            .set(&plan)
+            .get_result(&conn)?;
+        let change = if before.is_some() { "update" } else { "create" };
+        record_change(&conn, audit, change, "floor_plan", saved.storey_id, before.as_ref(), Some(&saved))?;
+        Ok((saved, before))
+    }).ok()
+}
+
+/// Remove the floor plan of a storey.
+/// Returns the removed floor plan, so a plan file can be cleaned up, or None if there was none.
+pub fn delete_floor_plan(audit: &Audit, id: uuid::Uuid) -> Option<FloorPlan> {
+    let conn = connection().unwrap();
+    conn.transaction::<_, Error, _>(|| {
+        let deleted : FloorPlan = diesel::delete(floor_plans.filter(storey_id.eq(id))).get_result(&conn)?;
+        record_change(&conn, audit, "delete", "floor_plan", id, Some(&deleted), None)?;
+        Ok(deleted)
+    }).ok()
+}
+
+/// Get the file names of all floor plans stored in the floor plan directory.
+pub fn floor_plan_paths(conn: &PgConnection) -> Vec<String> {
+    floor_plans.select(path).filter(path.is_not_null()).load::<Option<String>>(conn)
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .collect()
+}
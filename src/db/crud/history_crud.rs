@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use log::error;
+use serde::de::DeserializeOwned;
+
+use crate::db::models::AssetVersion;
+use crate::db::schema::asset_history::dsl::*;
+
+use crate::dbconn::connection;
+
+// The history itself is written by triggers in the database (see the `asset_history` migration),
+// this module only reads it. Versions are snapshots of whole rows, attributes that were added to an asset
+// later are missing from older ones and read as None, as all of them are optional.
+
+/// Return all assets of a type (`building`, `storey`, or `room`) as they were at the time `as_of`.
+/// Fails if a version cannot be read, instead of leaving the asset out of the answer.
+pub fn assets_as_of<T: DeserializeOwned>(kind: &str, as_of: DateTime<Utc>) -> Result<Vec<T>, String> {
+    let conn = connection().unwrap();
+    asset_history
+        .filter(entity_type.eq(kind))
+        .filter(valid_from.le(as_of))
+        .filter(valid_to.is_null().or(valid_to.gt(as_of)))
+        .order(valid_from.asc())
+        .load::<AssetVersion>(&conn)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(read_version)
+        .collect()
+}
+
+/// Find an asset as it was at the time `as_of`.
+/// Return None if the asset did not exist (or was in the trash) at that time, an error if its version cannot be read.
+pub fn asset_as_of<T: DeserializeOwned>(kind: &str, id_of_asset: uuid::Uuid, as_of: DateTime<Utc>) -> Result<Option<T>, String> {
+    let conn = connection().unwrap();
+    let version = asset_history
+        .filter(entity_type.eq(kind))
+        .filter(entity_id.eq(id_of_asset))
+        .filter(valid_from.le(as_of))
+        .filter(valid_to.is_null().or(valid_to.gt(as_of)))
+        .order(valid_from.desc())
+        .first::<AssetVersion>(&conn);
+    match version {
+        Ok(version) => read_version(version).map(Some),
+        Err(diesel::result::Error::NotFound) => Ok(None),
+        Err(e) => Err(e.to_string())
+    }
+}
+
+fn read_version<T: DeserializeOwned>(version: AssetVersion) -> Result<T, String> {
+    serde_json::from_value(version.data).map_err(|e| {
+        error!("version {} of {} {} cannot be read: {}", version.id, version.entity_type, version.entity_id, e);
+        format!("version {} of {} {} cannot be read", version.id, version.entity_type, version.entity_id)
+    })
+}
+
+/// Return all versions of an asset, oldest first.
+pub fn asset_history_of(kind: &str, id_of_asset: uuid::Uuid) -> Vec<AssetVersion> {
+    let conn = connection().unwrap();
+    asset_history
+        .filter(entity_type.eq(kind))
+        .filter(entity_id.eq(id_of_asset))
+        .order((valid_from.asc(), id.asc()))
+        .load::<AssetVersion>(&conn)
+        .unwrap_or_default()
+}
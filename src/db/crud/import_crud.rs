@@ -0,0 +1,341 @@
+use uuid::Uuid;
+
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde::Serialize;
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::db::crud::audit_crud::{record_change, Audit};
+use crate::db::crud::outbox_crud::record_event;
+use crate::db::models::{Building, Room, Storey};
+use crate::db::schema::{buildings, rooms, storeys};
+use crate::import::{ImportPolicy, ImportRow, RowError};
+use crate::cache::invalidate_all;
+use crate::outbox::Change;
+
+use crate::dbconn::connection;
+
+#[derive(Serialize, ToSchema)]
+/// Asset created or updated by an import.
+/// The snapshots before and after the change are kept for the audit log, but not reported.
+pub struct ImportChange {
+    pub line: usize,
+    pub entity_type: &'static str,
+    pub id: Uuid,
+    pub action: &'static str,
+    #[serde(skip)]
+    pub before: Option<serde_json::Value>,
+    #[serde(skip)]
+    pub after: Option<serde_json::Value>
+}
+
+#[derive(Serialize, ToSchema)]
+/// Result of an import. Nothing is written unless `committed` is set,
+/// which only happens if it is not a dry run and no row has errors.
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub committed: bool,
+    pub rows: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub errors: Vec<RowError>,
+    pub changes: Vec<ImportChange>
+}
+
+/// Why a row could not be imported.
+enum RowFailure {
+    Invalid(String),
+    Database(diesel::result::Error)
+}
+
+impl From<diesel::result::Error> for RowFailure {
+    fn from(e: diesel::result::Error) -> Self {
+        RowFailure::Database(e)
+    }
+}
+
+impl fmt::Display for RowFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RowFailure::Invalid(message) => write!(f, "{}", message),
+            RowFailure::Database(e) => write!(f, "database error: {}", e)
+        }
+    }
+}
+
+/// Import rows of assets in a single transaction.
+/// Buildings, storeys and rooms are matched by name (storeys within their building, rooms within their storey),
+/// assets that don't exist yet are created. Existing assets are updated with the values of the row if `policy` is `Upsert`,
+/// with `CreateOnly` a row fails if the asset it describes already exists, and existing parents are left as they are.
+/// Every row runs in its own savepoint, so all rows are checked even if some fail.
+/// The transaction is rolled back if any row failed or if `dry_run` is set, otherwise the changes are audited in it.
+/// Returns None if the transaction itself failed.
+pub fn import_rows(audit: &Audit, rows: &[ImportRow], policy: ImportPolicy, dry_run: bool) -> Option<ImportReport> {
+    let conn = connection().unwrap();
+    let mut changes = Vec::new();
+    let mut errors = Vec::new();
+
+    let result = conn.transaction::<(), diesel::result::Error, _>(|| {
+        for row in rows {
+            let mut row_changes = Vec::new();
+            match conn.transaction::<(), RowFailure, _>(|| import_row(&conn, row, policy, &mut row_changes)) {
+                Ok(()) => changes.extend(row_changes),
+                Err(e) => errors.push(RowError { line: row.line, message: e.to_string() })
+            }
+        }
+        if dry_run || !errors.is_empty() {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+        for change in &changes {
+            record_change(&conn, audit, change.action, change.entity_type, change.id, change.before.as_ref(), change.after.as_ref())?;
+        }
+        Ok(())
+    });
+
+    let committed = match result {
+        Ok(()) => true,
+        Err(diesel::result::Error::RollbackTransaction) => false,
+        Err(_) => return None
+    };
+    if committed {
+        invalidate_all(changes.iter().map(|change| (change.entity_type, change.id)));
+    }
+
+    Some(ImportReport {
+        dry_run,
+        committed,
+        rows: rows.len(),
+        created: changes.iter().filter(|change| change.action == "create").count(),
+        updated: changes.iter().filter(|change| change.action == "update").count(),
+        errors,
+        changes
+    })
+}
+
+fn import_row(conn: &PgConnection, row: &ImportRow, policy: ImportPolicy, changes: &mut Vec<ImportChange>) -> Result<(), RowFailure> {
+    let building = import_building(conn, row, policy, changes)?;
+    if let Some(ref storey_name) = row.storey {
+        let storey = import_storey(conn, row, storey_name, building.id, policy, changes)?;
+        if let Some(ref room_name) = row.room {
+            import_room(conn, row, room_name, storey.id, policy, changes)?;
+        }
+    }
+    Ok(())
+}
+
+fn import_building(conn: &PgConnection, row: &ImportRow, policy: ImportPolicy, changes: &mut Vec<ImportChange>) -> Result<Building, RowFailure> {
+    let mut found : Vec<Building> = buildings::table
+        .filter(buildings::name.eq(&row.building))
+        .filter(buildings::deleted_at.is_null())
+        .load(conn)?;
+    if found.len() > 1 {
+        return Err(RowFailure::Invalid(format!("more than one building named {}", row.building)));
+    }
+
+    let attributes = &row.building_attributes;
+    let has_address = [&attributes.street, &attributes.house_number, &attributes.postal_code, &attributes.city, &attributes.country]
+        .iter().any(|field| field.is_some());
+    let has_values = row.address.is_some() || has_address || attributes.latitude.is_some() || attributes.longitude.is_some();
+
+    let existing = match found.pop() {
+        Some(existing) => existing,
+        None => {
+            let address = row.address.clone().or_else(|| attributes.format_address())
+                .ok_or_else(|| RowFailure::Invalid(format!("building {} does not exist and has no address", row.building)))?;
+            let attributes = attributes.clone();
+            let building : Building = diesel::insert_into(buildings::table)
+                .values(Building {
+                    id: Uuid::new_v4(),
+                    name: row.building.clone(),
+                    address,
+                    deleted_at: None,
+                    street: attributes.street.flatten(),
+                    house_number: attributes.house_number.flatten(),
+                    postal_code: attributes.postal_code.flatten(),
+                    city: attributes.city.flatten(),
+                    country: attributes.country.flatten(),
+                    latitude: attributes.latitude.flatten(),
+                    longitude: attributes.longitude.flatten(),
+                    footprint: None
+                })
+                .get_result(conn)?;
+            record_event(conn, "building", Change::Created, building.id, &building)?;
+            changes.push(created(row.line, "building", building.id, &building));
+            return Ok(building);
+        }
+    };
+
+    let is_leaf = row.storey.is_none();
+    match policy {
+        ImportPolicy::CreateOnly if is_leaf => Err(RowFailure::Invalid(format!("building {} already exists", row.building))),
+        ImportPolicy::Upsert if has_values => {
+            let merged = attributes.clone().or(&existing);
+            let address = match row.address {
+                Some(ref address) => address.clone(),
+                None if has_address => merged.format_address().unwrap_or(existing.address.clone()),
+                None => existing.address.clone()
+            };
+            let building : Building = diesel::update(&existing)
+                .set((
+                    buildings::address.eq(address),
+                    buildings::street.eq(merged.street.flatten()),
+                    buildings::house_number.eq(merged.house_number.flatten()),
+                    buildings::postal_code.eq(merged.postal_code.flatten()),
+                    buildings::city.eq(merged.city.flatten()),
+                    buildings::country.eq(merged.country.flatten()),
+                    buildings::latitude.eq(merged.latitude.flatten()),
+                    buildings::longitude.eq(merged.longitude.flatten())
+                ))
+                .get_result(conn)?;
+            if let Some(change) = update(row.line, "building", &existing, &building) {
+                record_event(conn, "building", Change::Updated, building.id, &building)?;
+                changes.push(change);
+            }
+            Ok(building)
+        },
+        _ => Ok(existing)
+    }
+}
+
+fn import_storey(conn: &PgConnection, row: &ImportRow, storey_name: &str, building: Uuid, policy: ImportPolicy, changes: &mut Vec<ImportChange>) -> Result<Storey, RowFailure> {
+    let mut found : Vec<Storey> = storeys::table
+        .filter(storeys::building_id.eq(building))
+        .filter(storeys::name.eq(storey_name))
+        .filter(storeys::deleted_at.is_null())
+        .load(conn)?;
+    if found.len() > 1 {
+        return Err(RowFailure::Invalid(format!("more than one storey named {} in building {}", storey_name, row.building)));
+    }
+
+    let attributes = &row.storey_attributes;
+    let has_values = attributes.level.is_some() || attributes.elevation.is_some() || attributes.display_order.is_some();
+    let existing = found.pop();
+    let is_leaf = row.room.is_none();
+
+    let existing = match existing {
+        Some(_) if policy == ImportPolicy::CreateOnly && is_leaf => {
+            return Err(RowFailure::Invalid(format!("storey {} already exists", storey_name)));
+        },
+        Some(existing) if !(policy == ImportPolicy::Upsert && has_values) => return Ok(existing),
+        existing => existing
+    };
+
+    // the level is unique per building, check it here instead of running into the unique index
+    if let Some(Some(storey_level)) = attributes.level {
+        let mut taken = storeys::table
+            .filter(storeys::building_id.eq(building))
+            .filter(storeys::level.eq(storey_level))
+            .filter(storeys::deleted_at.is_null())
+            .into_boxed();
+        if let Some(ref existing) = existing {
+            taken = taken.filter(storeys::id.ne(existing.id));
+        }
+        if diesel::select(diesel::dsl::exists(taken)).get_result(conn)? {
+            return Err(RowFailure::Invalid(format!("level {} is already taken in building {}", storey_level, row.building)));
+        }
+    }
+
+    match existing {
+        Some(existing) => {
+            let storey : Storey = diesel::update(&existing)
+                .set((
+                    storeys::level.eq(attributes.level.unwrap_or(existing.level)),
+                    storeys::elevation.eq(attributes.elevation.unwrap_or(existing.elevation)),
+                    storeys::display_order.eq(attributes.display_order.unwrap_or(existing.display_order))
+                ))
+                .get_result(conn)?;
+            if let Some(change) = update(row.line, "storey", &existing, &storey) {
+                record_event(conn, "storey", Change::Updated, storey.id, &storey)?;
+                changes.push(change);
+            }
+            Ok(storey)
+        },
+        None => {
+            let storey : Storey = diesel::insert_into(storeys::table)
+                .values(Storey {
+                    id: Uuid::new_v4(),
+                    name: storey_name.to_string(),
+                    building_id: building,
+                    deleted_at: None,
+                    level: attributes.level.flatten(),
+                    elevation: attributes.elevation.flatten(),
+                    display_order: attributes.display_order.flatten()
+                })
+                .get_result(conn)?;
+            record_event(conn, "storey", Change::Created, storey.id, &storey)?;
+            changes.push(created(row.line, "storey", storey.id, &storey));
+            Ok(storey)
+        }
+    }
+}
+
+fn import_room(conn: &PgConnection, row: &ImportRow, room_name: &str, storey: Uuid, policy: ImportPolicy, changes: &mut Vec<ImportChange>) -> Result<Room, RowFailure> {
+    let mut found : Vec<Room> = rooms::table
+        .filter(rooms::storey_id.eq(storey))
+        .filter(rooms::name.eq(room_name))
+        .filter(rooms::deleted_at.is_null())
+        .load(conn)?;
+    if found.len() > 1 {
+        return Err(RowFailure::Invalid(format!("more than one room named {} on that storey", room_name)));
+    }
+
+    let attributes = row.room_attributes.clone();
+    match (found.pop(), policy) {
+        (Some(_), ImportPolicy::CreateOnly) => Err(RowFailure::Invalid(format!("room {} already exists", room_name))),
+        (Some(existing), ImportPolicy::Upsert) => {
+            let room : Room = diesel::update(&existing)
+                .set((
+                    rooms::seated_capacity.eq(attributes.seated_capacity.unwrap_or(existing.seated_capacity)),
+                    rooms::standing_capacity.eq(attributes.standing_capacity.unwrap_or(existing.standing_capacity)),
+                    rooms::room_type.eq(attributes.room_type.unwrap_or(existing.room_type.clone())),
+                    rooms::area.eq(attributes.area.unwrap_or(existing.area)),
+                    rooms::wheelchair_accessible.eq(attributes.wheelchair_accessible.unwrap_or(existing.wheelchair_accessible)),
+                    rooms::hearing_loop.eq(attributes.hearing_loop.unwrap_or(existing.hearing_loop))
+                ))
+                .get_result(conn)?;
+            if let Some(change) = update(row.line, "room", &existing, &room) {
+                record_event(conn, "room", Change::Updated, room.id, &room)?;
+                changes.push(change);
+            }
+            Ok(room)
+        },
+        (None, _) => {
+            let room : Room = diesel::insert_into(rooms::table)
+                .values(Room {
+                    id: Uuid::new_v4(),
+                    name: room_name.to_string(),
+                    storey_id: storey,
+                    deleted_at: None,
+                    seated_capacity: attributes.seated_capacity.flatten(),
+                    standing_capacity: attributes.standing_capacity.flatten(),
+                    room_type: attributes.room_type.flatten(),
+                    area: attributes.area.flatten(),
+                    wheelchair_accessible: attributes.wheelchair_accessible.flatten(),
+                    hearing_loop: attributes.hearing_loop.flatten(),
+                    latitude: None,
+                    longitude: None,
+                    footprint: None,
+                    plan_polygon: None
+                })
+                .get_result(conn)?;
+            record_event(conn, "room", Change::Created, room.id, &room)?;
+            changes.push(created(row.line, "room", room.id, &room));
+            Ok(room)
+        }
+    }
+}
+
+/// Change for a newly created asset.
+fn created<T: Serialize>(line: usize, entity_type: &'static str, id: Uuid, after: &T) -> ImportChange {
+    ImportChange { line, entity_type, id, action: "create", before: None, after: serde_json::to_value(after).ok() }
+}
+
+/// Change for an updated asset, or None if the row did not actually change anything.
+fn update<T: Serialize>(line: usize, entity_type: &'static str, before: &T, after: &T) -> Option<ImportChange> {
+    let (before, after) = (serde_json::to_value(before).ok(), serde_json::to_value(after).ok());
+    if before == after {
+        return None;
+    }
+    let id = after.as_ref().and_then(|entity| entity.get("id")).and_then(|id| serde_json::from_value(id.clone()).ok())?;
+    Some(ImportChange { line, entity_type, id, action: "update", before, after })
+}
@@ -0,0 +1,57 @@
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::result::Error;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::models::{NewOutboxEntry, OutboxEntry};
+use crate::db::schema::outbox;
+use crate::outbox::{enabled, publish_in_order, Change, OutboxEvent, Publisher};
+
+use crate::dbconn::connection;
+
+/// Write an event for a changed asset to the outbox.
+/// Call it on the connection the change was made on, inside the same transaction.
+/// Does nothing if no publisher is configured.
+pub fn record_event<T: Serialize>(conn: &PgConnection, asset_type: &str, change: Change, asset_id: Uuid, asset: &T) -> Result<(), Error> {
+    if !enabled() {
+        return Ok(());
+    }
+    let data = serde_json::to_value(asset).map_err(|e| Error::SerializationError(Box::new(e)))?;
+    let event = OutboxEvent::new(asset_type, change, asset_id, data);
+    let entry = NewOutboxEntry {
+        id: event.id,
+        event_type: event.event_type.clone(),
+        payload: serde_json::to_value(&event).map_err(|e| Error::SerializationError(Box::new(e)))?
+    };
+    diesel::insert_into(outbox::table).values(entry).execute(conn).map(|_| ())
+}
+
+/// Publish up to `limit` of the committed events with the lowest `seq` and remove the published ones from the outbox.
+/// An event of a transaction that is still running is left for a later round even if its `seq` is lower,
+/// which only reorders events of different assets (see `crate::outbox`).
+/// The events stay locked meanwhile, so a second relay waits instead of publishing them again.
+/// Return the number of published events, or the error that stopped publishing.
+pub fn relay_events(publisher: &mut dyn Publisher, limit: i64) -> Result<usize, String> {
+    let conn = connection().map_err(|e| e.to_string())?;
+    let mut failure = None;
+
+    let published = conn.transaction::<_, Error, _>(|| {
+        let entries : Vec<OutboxEntry> = outbox::table
+            .order(outbox::seq)
+            .limit(limit)
+            .for_update()
+            .load(&conn)?;
+
+        let (published, error) = publish_in_order(publisher, &entries);
+        failure = error;
+
+        let seqs : Vec<i64> = entries[..published].iter().map(|entry| entry.seq).collect();
+        diesel::delete(outbox::table.filter(outbox::seq.eq_any(&seqs))).execute(&conn)?;
+        Ok(published)
+    }).map_err(|e| e.to_string())?;
+
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(published)
+    }
+}
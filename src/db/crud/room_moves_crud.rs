@@ -0,0 +1,88 @@
+use chrono::{DateTime, Duration, Utc};
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::dsl::sql;
+use diesel::expression::SqlLiteral;
+use diesel::result::Error;
+use diesel::sql_types::Bool;
+use uuid::Uuid;
+
+use crate::db::models::{RoomMove, RoomMoveNotification};
+use crate::db::schema::room_move_notifications;
+
+/// Queue notifications for rooms that ended up in another building.
+/// Call it inside the transaction of the move, the notifications are only queued if it commits.
+pub fn queue_room_moves_with(conn: &PgConnection, moves: &[RoomMove]) -> Result<usize, Error> {
+    if moves.is_empty() {
+        return Ok(0);
+    }
+    let now = Utc::now();
+    let notifications : Vec<RoomMoveNotification> = moves.iter()
+        .map(|room_move| RoomMoveNotification {
+            id: Uuid::new_v4(),
+            room_id: room_move.room_id,
+            storey_id: room_move.storey_id,
+            from_building_id: room_move.from_building_id,
+            to_building_id: room_move.to_building_id,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now,
+            status: "pending".to_string()
+        })
+        .collect();
+    diesel::insert_into(room_move_notifications::table).values(&notifications).execute(conn)
+}
+
+/// A move waits while an earlier move of the same room is pending, so the service learns them in the order they happened.
+/// Otherwise a room moved from A to B and on to C could end up in B, if the first move is backing off.
+fn no_earlier_move_pending() -> SqlLiteral<Bool> {
+    sql("NOT EXISTS (SELECT 1 FROM room_move_notifications earlier \
+         WHERE earlier.room_id = room_move_notifications.room_id AND earlier.status = 'pending' \
+         AND (earlier.created_at, earlier.id) < (room_move_notifications.created_at, room_move_notifications.id))")
+}
+
+/// Take up to `limit` notifications that are due, oldest first, at most one per room.
+/// They are pushed back by `lease`, so nobody else picks them up while they are being sent.
+pub fn claim_due_room_moves(conn: &PgConnection, limit: i64, lease: Duration) -> Vec<RoomMoveNotification> {
+    let now = Utc::now();
+
+    conn.transaction::<_, Error, _>(|| {
+        let due : Vec<RoomMoveNotification> = room_move_notifications::table
+            .filter(room_move_notifications::status.eq("pending"))
+            .filter(room_move_notifications::next_attempt_at.le(now))
+            .filter(no_earlier_move_pending())
+            .order(room_move_notifications::created_at)
+            .limit(limit)
+            .for_update()
+            .skip_locked()
+            .load(conn)?;
+
+        let ids : Vec<Uuid> = due.iter().map(|notification| notification.id).collect();
+        diesel::update(room_move_notifications::table.filter(room_move_notifications::id.eq_any(&ids)))
+            .set(room_move_notifications::next_attempt_at.eq(now + lease))
+            .execute(conn)?;
+        Ok(due)
+    }).unwrap_or_default()
+}
+
+/// Forget notifications that arrived.
+pub fn room_moves_notified(conn: &PgConnection, ids: &[Uuid]) -> usize {
+    diesel::delete(room_move_notifications::table.filter(room_move_notifications::id.eq_any(ids))).execute(conn).unwrap_or(0)
+}
+
+/// Record a failed attempt. The notification is retried at `next_attempt_at`, or becomes a dead letter if there is none,
+/// which lets the next move of the room go ahead.
+pub fn room_move_failed(conn: &PgConnection, id: Uuid, attempts: i32, error: &str, next_attempt_at: Option<DateTime<Utc>>) -> bool {
+    let status = if next_attempt_at.is_some() { "pending" } else { "dead" };
+    matches!(
+        diesel::update(room_move_notifications::table.find(id))
+            .set((
+                room_move_notifications::status.eq(status),
+                room_move_notifications::attempts.eq(attempts),
+                room_move_notifications::last_error.eq(Some(error)),
+                room_move_notifications::next_attempt_at.eq(next_attempt_at.unwrap_or_else(Utc::now))
+            ))
+            .execute(conn),
+        Ok(1)
+    )
+}
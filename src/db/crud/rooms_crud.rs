@@ -1,88 +1,264 @@
 use uuid::Uuid;
 
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use chrono::{DateTime, Utc};
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::result::Error;
 
-use crate::db::models::Room;
+use crate::db::models::{Room, RoomAttributes, RoomFilter, RoomMove};
 use crate::db::schema::rooms::dsl::rooms;
+use crate::db::schema::rooms::id as r_id;
 use crate::db::schema::rooms::storey_id;
 use crate::db::schema::rooms::name as r_name;
+use crate::db::schema::rooms::deleted_at as r_deleted_at;
+use crate::db::schema::rooms::{seated_capacity, standing_capacity, room_type, area, wheelchair_accessible, hearing_loop};
+use crate::db::schema::rooms::{latitude, longitude, footprint, plan_polygon};
+use crate::db::schema::storeys;
+
+use crate::db::crud::equipment_crud::rooms_with_equipment;
+use crate::db::crud::audit_crud::{record_change, Audit};
+use crate::db::crud::outbox_crud::record_event;
+use crate::db::crud::room_moves_crud::queue_room_moves_with;
+use crate::cache::{invalidate, invalidate_all};
+use crate::outbox::Change;
 
 use crate::dbconn::connection;
 
 /// Check if a storey has associated rooms.
-/// Return true if a storey has associated rooms, false otherwise.
+/// Return true if a storey has associated rooms that are not in the trash, false otherwise.
 pub fn has_rooms(id: uuid::Uuid) -> bool {
-    let conn = connection().unwrap();
-    diesel::select(diesel::dsl::exists(rooms.filter(storey_id.eq(id))))
-        .get_result(&conn)
+    has_rooms_with(&connection().unwrap(), id)
+}
+
+pub fn has_rooms_with(conn: &PgConnection, id: uuid::Uuid) -> bool {
+    diesel::select(diesel::dsl::exists(rooms.filter(storey_id.eq(id)).filter(r_deleted_at.is_null())))
+        .get_result(conn)
         .unwrap_or(false)
 }
 
-/// Return a vector of all rooms in the database.
-pub fn get_rooms() -> Vec<Room> {
+/// Return a vector of all rooms of a storey.
+/// Rooms in the trash are only included if `include_deleted` is set.
+pub fn rooms_by_storey(id: uuid::Uuid, include_deleted: bool) -> Vec<Room> {
     let conn = connection().unwrap();
-    rooms.load::<Room>(&conn).expect("Error loading storeys")
+    let mut query = rooms.filter(storey_id.eq(id)).into_boxed();
+    if !include_deleted {
+        query = query.filter(r_deleted_at.is_null());
+    }
+    query.load::<Room>(&conn).unwrap_or_default()
+}
+
+/// Return a vector of all rooms matching the filter.
+/// Rooms in the trash are only included if `include_deleted` is set, `as_of` is ignored here.
+pub fn find_rooms(filter: &RoomFilter) -> Vec<Room> {
+    let conn = connection().unwrap();
+    let mut query = rooms.into_boxed();
+
+    if !filter.include_deleted.unwrap_or(false) {
+        query = query.filter(r_deleted_at.is_null());
+    }
+    if let Some(id) = filter.storey_id {
+        query = query.filter(storey_id.eq(id));
+    }
+    if let Some(min) = filter.min_capacity {
+        query = query.filter(seated_capacity.ge(min).or(standing_capacity.ge(min)));
+    }
+    if let Some(ref kind) = filter.room_type {
+        query = query.filter(room_type.eq(kind));
+    }
+    if let Some(flag) = filter.wheelchair_accessible {
+        query = query.filter(wheelchair_accessible.eq(flag));
+    }
+    if let Some(flag) = filter.hearing_loop {
+        query = query.filter(hearing_loop.eq(flag));
+    }
+    let equipment_keys = filter.equipment_keys();
+    if !equipment_keys.is_empty() {
+        query = query.filter(r_id.eq_any(rooms_with_equipment(&equipment_keys)));
+    }
+
+    query.load::<Room>(&conn).unwrap_or_default()
 }
 
-pub fn rooms_by_storey(id: uuid::Uuid) -> Vec<Room> {
+/// Return a vector of all rooms currently in the trash.
+pub fn get_deleted_rooms() -> Vec<Room> {
     let conn = connection().unwrap();
-    rooms.filter(storey_id.eq(id)).load::<Room>(&conn).unwrap_or(Vec::new())
+    rooms.filter(r_deleted_at.is_not_null()).load::<Room>(&conn).unwrap_or_default()
 }
 
 /// Find a room by UUID.
 /// Returns a room struct with the corresponding UUID or None if the UUID is not in the DB.
-pub fn find_room_by_id(id: uuid::Uuid) -> Option<Room> {
-    let conn = connection().unwrap();
-    rooms.find(id).get_result::<Room>(&conn).ok()
+/// Rooms in the trash are only found if `include_deleted` is set.
+pub fn find_room_by_id(id: uuid::Uuid, include_deleted: bool) -> Option<Room> {
+    find_room_by_id_with(&connection().unwrap(), id, include_deleted)
 }
 
-/// Pass a room name and storey ID, maybe a room UUID.
-/// If the UUID already exists, update the room with the new name and storey UUID.
+pub fn find_room_by_id_with(conn: &PgConnection, id: uuid::Uuid, include_deleted: bool) -> Option<Room> {
+    let mut query = rooms.filter(r_id.eq(id)).into_boxed();
+    if !include_deleted {
+        query = query.filter(r_deleted_at.is_null());
+    }
+    query.first::<Room>(conn).ok()
+}
+
+/// Pass a room name and storey ID, maybe a room UUID, and the optional room attributes.
+/// If the UUID already exists, update the room with the new name and storey UUID, unless it is in the trash.
 /// If the UUID does not exist, create a new room with that UUID.
 /// If there is no UUID, generate a new one and insert it with that name and storey ID.
-pub fn create_or_update_room(id: Option<uuid::Uuid>, room_name: String, room_storey_id: uuid::Uuid) -> Option<Room> {
-    let conn = connection().unwrap();
+pub fn create_or_update_room(audit: &Audit, id: Option<uuid::Uuid>, room_name: String, room_storey_id: uuid::Uuid, attributes: RoomAttributes) -> Option<Room> {
+    create_or_update_room_with(&connection().unwrap(), audit, id, room_name, room_storey_id, attributes)
+        .inspect(|room| invalidate("room", room.id))
+}
+
+pub fn create_or_update_room_with(conn: &PgConnection, audit: &Audit, id: Option<uuid::Uuid>, room_name: String, room_storey_id: uuid::Uuid, attributes: RoomAttributes) -> Option<Room> {
+    conn.transaction::<_, Error, _>(|| {
+        let (room, before) = save_room(conn, id, room_name, room_storey_id, attributes)?;
+        let change = if before.is_some() { Change::Updated } else { Change::Created };
+        record_event(conn, "room", change, room.id, &room)?;
+        record_change(conn, audit, change.action(), "room", room.id, before.as_ref(), Some(&room))?;
+        Ok(room)
+    }).ok()
+}
+
+fn save_room(conn: &PgConnection, id: Option<uuid::Uuid>, room_name: String, room_storey_id: uuid::Uuid, attributes: RoomAttributes) -> Result<(Room, Option<Room>), Error> {
     
     match id {
         Some(uuid) => {
 
-            if let Ok(room) = rooms.find(uuid).get_result::<Room>(&conn) {
-                diesel::update(&room)
-                    .set((r_name.eq(room_name), storey_id.eq(room_storey_id)))
-                    .get_result(&conn).ok()
+            if let Ok(room) = rooms.find(uuid).get_result::<Room>(conn) {
+                if room.deleted_at.is_some() {
+                    return Err(Error::RollbackTransaction);
+                }
+                let updated = diesel::update(&room)
+                    .set((
+                        r_name.eq(room_name),
+                        storey_id.eq(room_storey_id),
+                        seated_capacity.eq(attributes.seated_capacity.unwrap_or(room.seated_capacity)),
+                        standing_capacity.eq(attributes.standing_capacity.unwrap_or(room.standing_capacity)),
+                        room_type.eq(attributes.room_type.unwrap_or(room.room_type.clone())),
+                        area.eq(attributes.area.unwrap_or(room.area)),
+                        wheelchair_accessible.eq(attributes.wheelchair_accessible.unwrap_or(room.wheelchair_accessible)),
+                        hearing_loop.eq(attributes.hearing_loop.unwrap_or(room.hearing_loop)),
+                        latitude.eq(attributes.latitude.unwrap_or(room.latitude)),
+                        longitude.eq(attributes.longitude.unwrap_or(room.longitude)),
+                        footprint.eq(attributes.footprint.unwrap_or(room.footprint.clone())),
+                        plan_polygon.eq(attributes.plan_polygon.unwrap_or(room.plan_polygon.clone()))
+                    ))
+                    .get_result(conn)?;
+                Ok((updated, Some(room)))
             } else {
 
-                let new_room = Room {
-                    id: uuid,
-                    name: room_name,
-                    storey_id: room_storey_id
-                };
+                let new_room = new_room(uuid, room_name, room_storey_id, attributes);
 
-                diesel::insert_into(rooms)
+                let created = diesel::insert_into(rooms)
                     .values(new_room)
-                    .get_result(&conn).ok()
+                    .get_result(conn)?;
+                Ok((created, None))
 
             }
         },
         None => {
 
-            let new_room = Room {
-                id: Uuid::new_v4(),
-                name: room_name,
-                storey_id: room_storey_id
-            };
+            let new_room = new_room(Uuid::new_v4(), room_name, room_storey_id, attributes);
 
-            diesel::insert_into(rooms)
+            let created = diesel::insert_into(rooms)
                 .values(new_room)
-                .get_result(&conn).ok()
+                .get_result(conn)?;
+            Ok((created, None))
         
         }
     }
 }
 
-/// Delete the room with the UUID id.
-/// Return true if deletion was successful, false if the UUID was not found.
-pub fn delete_room_by_id(id: uuid::Uuid) -> bool {
+fn new_room(id: uuid::Uuid, room_name: String, room_storey_id: uuid::Uuid, attributes: RoomAttributes) -> Room {
+    Room {
+        id,
+        name: room_name,
+        storey_id: room_storey_id,
+        deleted_at: None,
+        seated_capacity: attributes.seated_capacity.flatten(),
+        standing_capacity: attributes.standing_capacity.flatten(),
+        room_type: attributes.room_type.flatten(),
+        area: attributes.area.flatten(),
+        wheelchair_accessible: attributes.wheelchair_accessible.flatten(),
+        hearing_loop: attributes.hearing_loop.flatten(),
+        latitude: attributes.latitude.flatten(),
+        longitude: attributes.longitude.flatten(),
+        footprint: attributes.footprint.flatten(),
+        plan_polygon: attributes.plan_polygon.flatten()
+    }
+}
+
+/// Move the room with the UUID id to the trash.
+/// Return true if deletion was successful, false if the UUID was not found or is already deleted.
+pub fn delete_room_by_id(audit: &Audit, id: uuid::Uuid) -> bool {
+    let deleted = delete_room_by_id_with(&connection().unwrap(), audit, id);
+    if deleted {
+        invalidate("room", id);
+    }
+    deleted
+}
+
+pub fn delete_room_by_id_with(conn: &PgConnection, audit: &Audit, id: uuid::Uuid) -> bool {
+    conn.transaction::<_, Error, _>(|| {
+        let room : Room = diesel::update(rooms.find(id).filter(r_deleted_at.is_null()))
+            .set(r_deleted_at.eq(Utc::now()))
+            .get_result(conn)?;
+        record_event(conn, "room", Change::Deleted, id, &room)?;
+        record_change(conn, audit, "delete", "room", id, Some(&Room { deleted_at: None, ..room }), None)
+    }).is_ok()
+}
+
+/// Move the rooms with the given UUIDs to another storey in a single transaction.
+/// Return the moved rooms, or None if one of them is gone or in the trash, then none of them is moved.
+/// Rooms that end up in another building are queued for the reservations service in the same transaction.
+pub fn move_rooms_by_id(audit: &Audit, ids: &[uuid::Uuid], target_storey: uuid::Uuid) -> Option<Vec<Room>> {
+    let conn = connection().unwrap();
+    conn.transaction::<_, Error, _>(|| {
+        let before : Vec<Room> = rooms.filter(r_id.eq_any(ids)).filter(r_deleted_at.is_null()).for_update().load(&conn)?;
+        let moved : Vec<Room> = diesel::update(rooms.filter(r_id.eq_any(ids)).filter(r_deleted_at.is_null()))
+            .set(storey_id.eq(target_storey))
+            .get_results(&conn)?;
+        if moved.len() != ids.len() {
+            return Err(Error::RollbackTransaction);
+        }
+        for room in &moved {
+            record_event(&conn, "room", Change::Updated, room.id, room)?;
+            record_change(&conn, audit, "move", "room", room.id, before.iter().find(|old| old.id == room.id), Some(room))?;
+        }
+
+        // rooms that are in another building now are reported to the reservations service
+        let storey_ids : Vec<uuid::Uuid> = before.iter().map(|room| room.storey_id).chain([target_storey]).collect();
+        let buildings : Vec<(uuid::Uuid, uuid::Uuid)> = storeys::table.select((storeys::id, storeys::building_id))
+            .filter(storeys::id.eq_any(&storey_ids))
+            .load(&conn)?;
+        let building_of = |storey: uuid::Uuid| buildings.iter().find(|(id, _)| *id == storey).map(|(_, building)| *building);
+        let moves : Vec<RoomMove> = before.iter()
+            .filter_map(|room| {
+                let (from_building_id, to_building_id) = (building_of(room.storey_id)?, building_of(target_storey)?);
+                (from_building_id != to_building_id)
+                    .then_some(RoomMove { room_id: room.id, storey_id: target_storey, from_building_id, to_building_id })
+            })
+            .collect();
+        queue_room_moves_with(&conn, &moves)?;
+        Ok(moved)
+    }).inspect(|_| invalidate_all(ids.iter().map(|id| ("room", *id)))).ok()
+}
+
+/// Take the room with the UUID id out of the trash.
+/// Return true if the room was restored, false if the UUID was not found in the trash.
+pub fn restore_room_by_id(audit: &Audit, id: uuid::Uuid) -> bool {
     let conn = connection().unwrap();
-    matches!(diesel::delete(rooms.find(id)).execute(&conn), Ok(1))
-}
\ No newline at end of file
+    conn.transaction::<_, Error, _>(|| {
+        let trashed : Room = rooms.find(id).filter(r_deleted_at.is_not_null()).for_update().first(&conn)?;
+        let room : Room = diesel::update(&trashed)
+            .set(r_deleted_at.eq(None::<DateTime<Utc>>))
+            .get_result(&conn)?;
+        record_event(&conn, "room", Change::Created, id, &room)?;
+        record_change(&conn, audit, "restore", "room", id, Some(&trashed), Some(&room))
+    }).inspect(|_| invalidate("room", id)).is_ok()
+}
+
+/// Permanently remove rooms that were moved to the trash before `cutoff`.
+/// Return the UUIDs of the removed rooms.
+pub fn purge_deleted_rooms(conn: &PgConnection, cutoff: DateTime<Utc>) -> Vec<uuid::Uuid> {
+    diesel::delete(rooms.filter(r_deleted_at.lt(cutoff))).returning(r_id).get_results(conn).unwrap_or_default()
+}
@@ -1,91 +1,271 @@
 use uuid::Uuid;
 
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use chrono::{DateTime, Utc};
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::result::Error;
 
 use crate::db::models::*;
 
 use crate::db::schema::storeys::dsl::storeys;
+use crate::db::schema::storeys::id as s_id;
 use crate::db::schema::storeys::building_id;
 use crate::db::schema::storeys::name as s_name;
+use crate::db::schema::storeys::deleted_at as s_deleted_at;
+use crate::db::schema::storeys::{level, elevation, display_order};
+use crate::db::schema::rooms;
+use crate::db::crud::audit_crud::{record_change, Audit};
+use crate::db::crud::outbox_crud::record_event;
+use crate::db::crud::room_moves_crud::queue_room_moves_with;
+use crate::cache::invalidate;
+use crate::outbox::Change;
 
 use crate::dbconn::connection;
 
 /// Check if a building has associated storeys.
-/// Return true if a building has associated storeys, false otherwise.
+/// Return true if a building has associated storeys that are not in the trash, false otherwise.
 pub fn has_storeys(id: uuid::Uuid) -> bool {
-    let conn = connection().unwrap();
-    diesel::select(diesel::dsl::exists(storeys.filter(building_id.eq(id))))
-        .get_result(&conn)
+    has_storeys_with(&connection().unwrap(), id)
+}
+
+pub fn has_storeys_with(conn: &PgConnection, id: uuid::Uuid) -> bool {
+    diesel::select(diesel::dsl::exists(storeys.filter(building_id.eq(id)).filter(s_deleted_at.is_null())))
+        .get_result(conn)
         .unwrap_or(false)
 }
 
-pub fn storeys_by_building(id: uuid::Uuid) -> Vec<Storey> {
+/// Return a vector of all storeys of a building, sorted from the lowest to the highest level.
+/// Storeys without a level come last, ordered by their display order.
+/// Storeys in the trash are only included if `include_deleted` is set.
+pub fn storeys_by_building(id: uuid::Uuid, include_deleted: bool) -> Vec<Storey> {
+    let conn = connection().unwrap();
+    let mut query = storeys.filter(building_id.eq(id)).into_boxed();
+    if !include_deleted {
+        query = query.filter(s_deleted_at.is_null());
+    }
+    // ascending order puts NULLs last in postgres
+    query.order((level.asc(), display_order.asc(), s_name.asc())).load::<Storey>(&conn).unwrap_or_default()
+}
+
+/// Sort storeys the same way `storeys_by_building` does, for storeys that do not come from a DB query.
+pub fn sort_storeys(list: &mut [Storey]) {
+    list.sort_by(|a, b| {
+        (a.level.is_none(), a.level, a.display_order.is_none(), a.display_order, &a.name)
+            .cmp(&(b.level.is_none(), b.level, b.display_order.is_none(), b.display_order, &b.name))
+    });
+}
+
+/// Check if another storey of the building that is not in the trash already has the level.
+/// `except` excludes the storey that is being updated.
+pub fn level_taken(building: uuid::Uuid, storey_level: i32, except: Option<uuid::Uuid>) -> bool {
+    level_taken_with(&connection().unwrap(), building, storey_level, except)
+}
+
+pub fn level_taken_with(conn: &PgConnection, building: uuid::Uuid, storey_level: i32, except: Option<uuid::Uuid>) -> bool {
+    let mut query = storeys
+        .filter(building_id.eq(building))
+        .filter(level.eq(storey_level))
+        .filter(s_deleted_at.is_null())
+        .into_boxed();
+    if let Some(id) = except {
+        query = query.filter(s_id.ne(id));
+    }
+    diesel::select(diesel::dsl::exists(query)).get_result(conn).unwrap_or(false)
+}
+
+/// Check if another storey of the building that is not in the trash already has the name.
+/// `except` excludes the storey that is being moved.
+pub fn storey_name_taken(building: uuid::Uuid, storey_name: &str, except: Option<uuid::Uuid>) -> bool {
     let conn = connection().unwrap();
-    storeys.filter(building_id.eq(id)).load::<Storey>(&conn).unwrap_or(Vec::new())
+    let mut query = storeys
+        .filter(building_id.eq(building))
+        .filter(s_name.eq(storey_name))
+        .filter(s_deleted_at.is_null())
+        .into_boxed();
+    if let Some(id) = except {
+        query = query.filter(s_id.ne(id));
+    }
+    diesel::select(diesel::dsl::exists(query)).get_result(&conn).unwrap_or(false)
 }
 
 /// Return a vector of all storeys in the database.
-pub fn get_storeys() -> Vec<Storey> {
+/// Storeys in the trash are only included if `include_deleted` is set.
+pub fn get_storeys(include_deleted: bool) -> Vec<Storey> {
     let conn = connection().unwrap();
-    storeys.load::<Storey>(&conn).expect("Error loading storeys")
+    let mut query = storeys.into_boxed();
+    if !include_deleted {
+        query = query.filter(s_deleted_at.is_null());
+    }
+    query.order((building_id.asc(), level.asc(), display_order.asc(), s_name.asc())).load::<Storey>(&conn).expect("Error loading storeys")
+}
+
+/// Return a vector of all storeys currently in the trash.
+pub fn get_deleted_storeys() -> Vec<Storey> {
+    let conn = connection().unwrap();
+    storeys.filter(s_deleted_at.is_not_null()).load::<Storey>(&conn).unwrap_or_default()
 }
 
 /// Find a storey by UUID.
 /// Returns a storey struct with the corresponding UUID or None if the UUID is not in the DB.
-pub fn find_storey_by_id(id: uuid::Uuid) -> Option<Storey> {
-    let conn = connection().unwrap();
-    storeys.find(id).get_result::<Storey>(&conn).ok()
+/// Storeys in the trash are only found if `include_deleted` is set.
+pub fn find_storey_by_id(id: uuid::Uuid, include_deleted: bool) -> Option<Storey> {
+    find_storey_by_id_with(&connection().unwrap(), id, include_deleted)
 }
 
-/// Pass a storey name and building ID, maybe a room UUID.
-/// If the UUID already exists, update the storey with the new name and building ID.
+pub fn find_storey_by_id_with(conn: &PgConnection, id: uuid::Uuid, include_deleted: bool) -> Option<Storey> {
+    let mut query = storeys.filter(s_id.eq(id)).into_boxed();
+    if !include_deleted {
+        query = query.filter(s_deleted_at.is_null());
+    }
+    query.first::<Storey>(conn).ok()
+}
+
+/// Pass a storey name and building ID, maybe a storey UUID, and the optional storey attributes.
+/// If the UUID already exists, update the storey with the new name and building ID, unless it is in the trash.
 /// If the UUID does not exist, create a new storey with that UUID.
 /// If there is no UUID, generate a new one and insert it with that name and building ID.
-pub fn create_or_update_storey(id: Option<uuid::Uuid>, storey_name: String, storey_building_id: uuid::Uuid) -> Option<Storey> {
-    let conn = connection().unwrap();
+pub fn create_or_update_storey(audit: &Audit, id: Option<uuid::Uuid>, storey_name: String, storey_building_id: uuid::Uuid, attributes: StoreyAttributes) -> Option<Storey> {
+    create_or_update_storey_with(&connection().unwrap(), audit, id, storey_name, storey_building_id, attributes)
+        .inspect(|storey| invalidate("storey", storey.id))
+}
+
+pub fn create_or_update_storey_with(conn: &PgConnection, audit: &Audit, id: Option<uuid::Uuid>, storey_name: String, storey_building_id: uuid::Uuid, attributes: StoreyAttributes) -> Option<Storey> {
+    conn.transaction::<_, Error, _>(|| {
+        let (storey, before) = save_storey(conn, id, storey_name, storey_building_id, attributes)?;
+        let change = if before.is_some() { Change::Updated } else { Change::Created };
+        record_event(conn, "storey", change, storey.id, &storey)?;
+        record_change(conn, audit, change.action(), "storey", storey.id, before.as_ref(), Some(&storey))?;
+        Ok(storey)
+    }).ok()
+}
+
+fn save_storey(conn: &PgConnection, id: Option<uuid::Uuid>, storey_name: String, storey_building_id: uuid::Uuid, attributes: StoreyAttributes) -> Result<(Storey, Option<Storey>), Error> {
 
     match id {
         Some(uuid) => {
 
-            if let Ok(storey) = storeys.find(uuid).get_result::<Storey>(&conn) {
+            if let Ok(storey) = storeys.find(uuid).get_result::<Storey>(conn) {
+                if storey.deleted_at.is_some() {
+                    return Err(Error::RollbackTransaction);
+                }
             
-                diesel::update(&storey)
-                    .set((s_name.eq(storey_name), building_id.eq(storey_building_id)))
-                    .get_result(&conn).ok()
+                let updated = diesel::update(&storey)
+                    .set((
+                        s_name.eq(storey_name),
+                        building_id.eq(storey_building_id),
+                        level.eq(attributes.level.unwrap_or(storey.level)),
+                        elevation.eq(attributes.elevation.unwrap_or(storey.elevation)),
+                        display_order.eq(attributes.display_order.unwrap_or(storey.display_order))
+                    ))
+                    .get_result(conn)?;
+                Ok((updated, Some(storey)))
 
             } else {
 
-                let new_storey = Storey {
-                    id: uuid,
-                    name: storey_name,
-                    building_id: storey_building_id
-                };
+                let new_storey = new_storey(uuid, storey_name, storey_building_id, attributes);
                 
-                diesel::insert_into(storeys)
+                let created = diesel::insert_into(storeys)
                     .values(new_storey)
-                    .get_result(&conn).ok()
+                    .get_result(conn)?;
+                Ok((created, None))
             
             }
         },
         None => {
             
-            let new_storey = Storey {
-                id: Uuid::new_v4(),
-                name: storey_name,
-                building_id: storey_building_id
-            };
+            let new_storey = new_storey(Uuid::new_v4(), storey_name, storey_building_id, attributes);
             
-            diesel::insert_into(storeys)
+            let created = diesel::insert_into(storeys)
                 .values(new_storey)
-                .get_result(&conn).ok()
+                .get_result(conn)?;
+            Ok((created, None))
         
         }
     }
 }
 
-/// Delete the storey with the UUID id.
-/// Return true if deletion was successful, false if the UUID was not found.
-pub fn delete_storey_by_id(id: uuid::Uuid) -> bool {
+fn new_storey(id: uuid::Uuid, storey_name: String, storey_building_id: uuid::Uuid, attributes: StoreyAttributes) -> Storey {
+    Storey {
+        id,
+        name: storey_name,
+        building_id: storey_building_id,
+        deleted_at: None,
+        level: attributes.level.flatten(),
+        elevation: attributes.elevation.flatten(),
+        display_order: attributes.display_order.flatten()
+    }
+}
+
+/// Move the storey with the UUID id to the trash.
+/// Return true if deletion was successful, false if the UUID was not found or is already deleted.
+pub fn delete_storey_by_id(audit: &Audit, id: uuid::Uuid) -> bool {
+    let deleted = delete_storey_by_id_with(&connection().unwrap(), audit, id);
+    if deleted {
+        invalidate("storey", id);
+    }
+    deleted
+}
+
+pub fn delete_storey_by_id_with(conn: &PgConnection, audit: &Audit, id: uuid::Uuid) -> bool {
+    conn.transaction::<_, Error, _>(|| {
+        let storey : Storey = diesel::update(storeys.find(id).filter(s_deleted_at.is_null()))
+            .set(s_deleted_at.eq(Utc::now()))
+            .get_result(conn)?;
+        record_event(conn, "storey", Change::Deleted, id, &storey)?;
+        record_change(conn, audit, "delete", "storey", id, Some(&Storey { deleted_at: None, ..storey }), None)
+    }).is_ok()
+}
+
+/// Move the storey with the UUID id, and with it all of its rooms, to another building and/or level.
+/// The storey keeps its level if `new_level` is None.
+/// Return the moved storey, or None if it was not found or is in the trash.
+/// If the building changes, its rooms are queued for the reservations service in the same transaction.
+pub fn move_storey_by_id(audit: &Audit, id: uuid::Uuid, target_building: uuid::Uuid, new_level: Option<i32>) -> Option<Storey> {
+    let conn = connection().unwrap();
+    conn.transaction::<_, Error, _>(|| {
+        let storey = storeys.find(id).filter(s_deleted_at.is_null()).first::<Storey>(&conn)?;
+        let moved : Storey = diesel::update(&storey)
+            .set((
+                building_id.eq(target_building),
+                level.eq(new_level.or(storey.level))
+            ))
+            .get_result(&conn)?;
+        record_event(&conn, "storey", Change::Updated, id, &moved)?;
+        record_change(&conn, audit, "move", "storey", id, Some(&storey), Some(&moved))?;
+        if moved.building_id != storey.building_id {
+            let room_ids : Vec<uuid::Uuid> = rooms::table.select(rooms::id)
+                .filter(rooms::storey_id.eq(id))
+                .filter(rooms::deleted_at.is_null())
+                .load(&conn)?;
+            let moves : Vec<RoomMove> = room_ids.into_iter()
+                .map(|room_id| RoomMove { room_id, storey_id: id, from_building_id: storey.building_id, to_building_id: moved.building_id })
+                .collect();
+            queue_room_moves_with(&conn, &moves)?;
+        }
+        Ok(moved)
+    }).inspect(|_| invalidate("storey", id)).ok()
+}
+
+/// Take the storey with the UUID id out of the trash.
+/// Return true if the storey was restored, false if the UUID was not found in the trash.
+pub fn restore_storey_by_id(audit: &Audit, id: uuid::Uuid) -> bool {
     let conn = connection().unwrap();
-    matches!(diesel::delete(storeys.find(id)).execute(&conn), Ok(1))
-}
\ No newline at end of file
+    conn.transaction::<_, Error, _>(|| {
+        let trashed : Storey = storeys.find(id).filter(s_deleted_at.is_not_null()).for_update().first(&conn)?;
+        let storey : Storey = diesel::update(&trashed)
+            .set(s_deleted_at.eq(None::<DateTime<Utc>>))
+            .get_result(&conn)?;
+        record_event(&conn, "storey", Change::Created, id, &storey)?;
+        record_change(&conn, audit, "restore", "storey", id, Some(&trashed), Some(&storey))
+    }).inspect(|_| invalidate("storey", id)).is_ok()
+}
+
+/// Permanently remove storeys that were moved to the trash before `cutoff`.
+/// Storeys that still have rooms (deleted or not) are kept until those are purged.
+/// Return the UUIDs of the removed storeys.
+pub fn purge_deleted_storeys(conn: &PgConnection, cutoff: DateTime<Utc>) -> Vec<uuid::Uuid> {
+    diesel::delete(
+        storeys
+            .filter(s_deleted_at.lt(cutoff))
+            .filter(s_id.ne_all(rooms::table.select(rooms::storey_id)))
+    ).returning(s_id).get_results(conn).unwrap_or_default()
+}
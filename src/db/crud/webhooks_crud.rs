@@ -0,0 +1,142 @@
+use chrono::{DateTime, Duration, Utc};
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::result::Error;
+use uuid::Uuid;
+
+use crate::db::models::{Webhook, WebhookDelivery};
+use crate::db::schema::{webhook_deliveries, webhooks};
+use crate::webhooks::{event_matches, WebhookEvent};
+
+use crate::dbconn::connection;
+
+/// Upper bound for the number of dead letters returned by a single query.
+const MAX_DEAD_LETTERS: i64 = 1000;
+
+/// Store a new webhook subscription.
+pub fn create_webhook(webhook: Webhook) -> Option<Webhook> {
+    let conn = connection().unwrap();
+    diesel::insert_into(webhooks::table).values(webhook).get_result(&conn).ok()
+}
+
+/// Return all webhook subscriptions, oldest first.
+pub fn get_webhooks() -> Vec<Webhook> {
+    let conn = connection().unwrap();
+    webhooks::table.order(webhooks::created_at).load::<Webhook>(&conn).unwrap_or_default()
+}
+
+/// Remove a webhook subscription together with its pending deliveries and dead letters.
+/// Return true if the subscription existed.
+pub fn delete_webhook_by_id(id: Uuid) -> bool {
+    let conn = connection().unwrap();
+    matches!(diesel::delete(webhooks::table.find(id)).execute(&conn), Ok(1))
+}
+
+/// Queue the event for every subscription that wants it.
+/// Call it inside the transaction of the change, the deliveries are only queued if it commits.
+/// Return the number of queued deliveries.
+pub fn queue_deliveries_with(conn: &PgConnection, event: &WebhookEvent) -> Result<usize, Error> {
+    let subscribers : Vec<Webhook> = webhooks::table.load::<Webhook>(conn)?.into_iter()
+        .filter(|webhook| event_matches(&webhook.events, &event.event_type))
+        .collect();
+    if subscribers.is_empty() {
+        return Ok(0);
+    }
+
+    let payload = serde_json::to_value(event).map_err(|e| Error::SerializationError(Box::new(e)))?;
+    let now = Utc::now();
+    let deliveries : Vec<WebhookDelivery> = subscribers.iter()
+        .map(|webhook| WebhookDelivery {
+            id: Uuid::new_v4(),
+            webhook_id: webhook.id,
+            event_type: event.event_type.clone(),
+            payload: payload.clone(),
+            status: "pending".to_string(),
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now
+        })
+        .collect();
+
+    diesel::insert_into(webhook_deliveries::table).values(&deliveries).execute(conn)
+}
+
+/// Take up to `limit` deliveries that are due, together with their subscription.
+/// The deliveries are pushed back by `lease`, so nobody else picks them up while they are being sent.
+pub fn claim_due_deliveries(conn: &PgConnection, limit: i64, lease: Duration) -> Vec<(WebhookDelivery, Webhook)> {
+    let now = Utc::now();
+
+    conn.transaction::<_, Error, _>(|| {
+        let due : Vec<WebhookDelivery> = webhook_deliveries::table
+            .filter(webhook_deliveries::status.eq("pending"))
+            .filter(webhook_deliveries::next_attempt_at.le(now))
+            .order(webhook_deliveries::next_attempt_at)
+            .limit(limit)
+            .for_update()
+            .skip_locked()
+            .load(conn)?;
+        if due.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let ids : Vec<Uuid> = due.iter().map(|delivery| delivery.id).collect();
+        diesel::update(webhook_deliveries::table.filter(webhook_deliveries::id.eq_any(&ids)))
+            .set(webhook_deliveries::next_attempt_at.eq(now + lease))
+            .execute(conn)?;
+
+        let webhook_ids : Vec<Uuid> = due.iter().map(|delivery| delivery.webhook_id).collect();
+        let subscriptions : Vec<Webhook> = webhooks::table.filter(webhooks::id.eq_any(&webhook_ids)).load(conn)?;
+        Ok(due.into_iter()
+            .filter_map(|delivery| {
+                let webhook = subscriptions.iter().find(|webhook| webhook.id == delivery.webhook_id)?;
+                Some((delivery, webhook.clone()))
+            })
+            .collect())
+    }).unwrap_or_default()
+}
+
+/// Forget a delivery that arrived.
+pub fn delivery_succeeded(conn: &PgConnection, id: Uuid) -> bool {
+    matches!(diesel::delete(webhook_deliveries::table.find(id)).execute(conn), Ok(1))
+}
+
+/// Record a failed attempt. The delivery is retried at `next_attempt_at`, or becomes a dead letter if there is none.
+pub fn delivery_failed(conn: &PgConnection, id: Uuid, attempts: i32, error: String, next_attempt_at: Option<DateTime<Utc>>) -> bool {
+    let status = if next_attempt_at.is_some() { "pending" } else { "dead" };
+    matches!(
+        diesel::update(webhook_deliveries::table.find(id))
+            .set((
+                webhook_deliveries::status.eq(status),
+                webhook_deliveries::attempts.eq(attempts),
+                webhook_deliveries::last_error.eq(Some(error)),
+                webhook_deliveries::next_attempt_at.eq(next_attempt_at.unwrap_or_else(Utc::now))
+            ))
+            .execute(conn),
+        Ok(1)
+    )
+}
+
+/// Return the deliveries that ran out of attempts, newest first.
+pub fn get_dead_letters() -> Vec<WebhookDelivery> {
+    let conn = connection().unwrap();
+    webhook_deliveries::table
+        .filter(webhook_deliveries::status.eq("dead"))
+        .order(webhook_deliveries::created_at.desc())
+        .limit(MAX_DEAD_LETTERS)
+        .load::<WebhookDelivery>(&conn)
+        .unwrap_or_default()
+}
+
+/// Queue a dead letter again, with a fresh set of attempts.
+/// Return None if there is no dead letter with this ID.
+pub fn retry_dead_letter(id: Uuid) -> Option<WebhookDelivery> {
+    let conn = connection().unwrap();
+    diesel::update(webhook_deliveries::table.find(id).filter(webhook_deliveries::status.eq("dead")))
+        .set((
+            webhook_deliveries::status.eq("pending"),
+            webhook_deliveries::attempts.eq(0),
+            webhook_deliveries::next_attempt_at.eq(Utc::now())
+        ))
+        .get_result(&conn)
+        .ok()
+}
@@ -1,5 +1,6 @@
-use diesel::PgConnection;
+use diesel::{Connection, PgConnection};
 use diesel::r2d2::ConnectionManager;
+use diesel_migrations::RunMigrationsError;
 
 use lazy_static::lazy_static;
 use r2d2;
@@ -8,27 +9,34 @@ use std::env;
 type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
 pub type DbConnection = r2d2::PooledConnection<ConnectionManager<PgConnection>>;
 
+// embeds the SQL files in the `migrations` folder into the binary,
+// so the docker image does not need to ship them separately
+embed_migrations!();
+
 // lazily initialize the DB connection pool
 // and make it available through the reference POOL
 lazy_static! {
     static ref POOL : Pool = {
+        let manager = ConnectionManager::<PgConnection>::new(database_url());
+        Pool::new(manager).expect("failed to create db pool")
+    };
+}
 
-        let db_user = env::var("POSTGRES_ASSETS_USER")
-            .expect("POSTGRES_ASSETS_USER environment variable not set");
-        let db_pass = env::var("POSTGRES_ASSETS_PASSWORD")
-            .expect("POSTGRES_ASSETS_PASSWORD environment variable not set");
-        let db_name = env::var("POSTGRES_ASSETS_DBNAME")
-            .expect("POSTGRES_ASSETS_DBNAME environment variable not set");
-        let db_host = env::var("POSTGRES_ASSETS_HOST")
-            .expect("POSTGRES_ASSETS_HOST environment variable not set");
-        let db_port = env::var("POSTGRES_ASSETS_PORT")
-            .expect("POSTGRES_ASSETS_PORT environment variable not set");
+/// Connection URL built from the `POSTGRES_ASSETS_*` environment variables.
+pub fn database_url() -> String {
 
-        let db_url = format!("postgres://{}:{}@{}:{}/{}", db_user, db_pass, db_host, db_port, db_name);
+    let db_user = env::var("POSTGRES_ASSETS_USER")
+        .expect("POSTGRES_ASSETS_USER environment variable not set");
+    let db_pass = env::var("POSTGRES_ASSETS_PASSWORD")
+        .expect("POSTGRES_ASSETS_PASSWORD environment variable not set");
+    let db_name = env::var("POSTGRES_ASSETS_DBNAME")
+        .expect("POSTGRES_ASSETS_DBNAME environment variable not set");
+    let db_host = env::var("POSTGRES_ASSETS_HOST")
+        .expect("POSTGRES_ASSETS_HOST environment variable not set");
+    let db_port = env::var("POSTGRES_ASSETS_PORT")
+        .expect("POSTGRES_ASSETS_PORT environment variable not set");
 
-        let manager = ConnectionManager::<PgConnection>::new(db_url);
-        Pool::new(manager).expect("failed to create db pool")
-    };
+    format!("postgres://{}:{}@{}:{}/{}", db_user, db_pass, db_host, db_port, db_name)
 }
 
 /// Initialize the DB connection pool.
@@ -37,7 +45,20 @@ pub fn init() -> Result<DbConnection, r2d2::Error> {
     connection()
 }
 
+/// Apply all pending migrations from the `migrations` folder.
+pub fn run_migrations(conn: &DbConnection) -> Result<(), RunMigrationsError> {
+    embedded_migrations::run(&**conn)
+}
+
 /// Get a database connection from the pool.
 pub fn connection() -> Result<DbConnection, r2d2::Error> {
     POOL.get()
-}
\ No newline at end of file
+}
+
+/// Run `f` in a single transaction on a connection from the pool.
+/// Everything `f` did is rolled back if it returns an error.
+pub fn transaction<T, E, F>(f: F) -> Result<T, E>
+where F: FnOnce(&PgConnection) -> Result<T, E>, E: From<diesel::result::Error> {
+    let conn = connection().unwrap();
+    conn.transaction(|| f(&conn))
+}
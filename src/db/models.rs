@@ -1,60 +1,688 @@
-use diesel::{Queryable, Insertable, Identifiable};
-use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use diesel::{AsChangeset, Queryable, QueryableByName, Insertable, Identifiable};
+use diesel::sql_types::{Bool, Double, Integer, Nullable, Text, Timestamptz};
+use serde::{Serialize, Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use utoipa::{IntoParams, ToSchema};
 use crate::db::schema::*;
 
-#[derive(Serialize, Deserialize, Queryable, Insertable, Identifiable)]
+/// Deserialize an attribute that may be left out, be `null` or have a value.
+/// Use it with `#[serde(default)]`: left out is `None`, `null` is `Some(None)`, which clears the attribute.
+pub fn deserialize_given<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Clone, Serialize, Deserialize, Queryable, Insertable, Identifiable, ToSchema)]
 /// Building type, identified by UUID, has a name and an address.
+/// `deleted_at` is set while the building is in the trash.
+/// The structured address and position are optional and described in `BuildingAttributes`.
 pub struct Building {
     pub id: uuid::Uuid,
     pub name: String,
-    pub address: String
+    pub address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub street: Option<String>,
+    pub house_number: Option<String>,
+    pub postal_code: Option<String>,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub footprint: Option<serde_json::Value>
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 /// Building type, potentially without UUID, that may be passed as part of a POST or PUT request.
+/// The free-text `address` may be left out if the structured address is given.
 pub struct OptionalIDBuilding {
     pub id: Option<uuid::Uuid>,
     pub name: String,
-    pub address: String
+    pub address: Option<String>,
+    #[serde(flatten)]
+    pub attributes: BuildingAttributes
+}
+
+#[derive(Deserialize, Clone, Default, ToSchema)]
+/// Optional structured address of a building, its WGS84 position in degrees
+/// and its footprint as a GeoJSON Polygon or MultiPolygon geometry.
+/// An attribute that is left out keeps its value on an update, `null` clears it.
+pub struct BuildingAttributes {
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub street: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub house_number: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub postal_code: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub city: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub country: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub latitude: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub longitude: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub footprint: Option<Option<serde_json::Value>>
+}
+
+impl BuildingAttributes {
+    /// Fill the attributes that are not given from an existing building, those given as `null` stay cleared.
+    pub fn or(self, building: &Building) -> BuildingAttributes {
+        BuildingAttributes {
+            street: self.street.or(Some(building.street.clone())),
+            house_number: self.house_number.or(Some(building.house_number.clone())),
+            postal_code: self.postal_code.or(Some(building.postal_code.clone())),
+            city: self.city.or(Some(building.city.clone())),
+            country: self.country.or(Some(building.country.clone())),
+            latitude: self.latitude.or(Some(building.latitude)),
+            longitude: self.longitude.or(Some(building.longitude)),
+            footprint: self.footprint.or(Some(building.footprint.clone()))
+        }
+    }
+
+    /// Format the structured address as a single line, e.g. `Hauptstraße 1, 12345 Berlin, Germany`.
+    /// Return None if none of the address fields are set.
+    pub fn format_address(&self) -> Option<String> {
+        let join = |parts: &[&Option<Option<String>>]| {
+            parts.iter().filter_map(|part| part.as_ref()?.as_deref()).filter(|part| !part.trim().is_empty()).collect::<Vec<_>>().join(" ")
+        };
+        let lines : Vec<String> = [
+            join(&[&self.street, &self.house_number]),
+            join(&[&self.postal_code, &self.city]),
+            join(&[&self.country])
+        ].into_iter().filter(|line| !line.is_empty()).collect();
+
+        if lines.is_empty() { None } else { Some(lines.join(", ")) }
+    }
+}
+
+#[derive(Serialize)]
+/// Building found by a distance query, `distance` is in metres.
+pub struct NearbyBuilding {
+    #[serde(flatten)]
+    pub building: Building,
+    pub distance: f64
 }
 
-#[derive(Serialize, Deserialize, Queryable, Insertable, Identifiable)]
+#[derive(Clone, Serialize, Deserialize, Queryable, Insertable, Identifiable, ToSchema)]
 /// Storey type, identified by UUID, has an associated building and a name.
+/// `deleted_at` is set while the storey is in the trash.
+/// The remaining attributes are optional and described in `StoreyAttributes`.
 pub struct Storey {
     pub id: uuid::Uuid,
     pub name: String,
-    pub building_id: uuid::Uuid
+    pub building_id: uuid::Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub level: Option<i32>,
+    pub elevation: Option<f64>,
+    pub display_order: Option<i32>
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 /// Storey type, potentially without UUID, that may be passed as part of a POST or PUT request.
 pub struct OptionalIDStorey {
     pub id: Option<uuid::Uuid>,
     pub name: String,
-    pub building_id: uuid::Uuid
+    pub building_id: uuid::Uuid,
+    #[serde(flatten)]
+    pub attributes: StoreyAttributes
+}
+
+#[derive(Deserialize, Clone, Default, ToSchema)]
+/// Optional attributes of a storey that define the order of the floors in a building.
+/// `level` is the floor number (0 is the ground floor, negative for basements) and unique per building,
+/// `elevation` is the height of the floor in metres, `display_order` sorts storeys without a level.
+/// An attribute that is left out keeps its value on an update, `null` clears it.
+pub struct StoreyAttributes {
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub level: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub elevation: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub display_order: Option<Option<i32>>
 }
 
-#[derive(Serialize, Deserialize, Queryable, Insertable, Identifiable)]
+#[derive(Clone, Serialize, Deserialize, Queryable, Insertable, Identifiable, ToSchema)]
 /// Room type, identified by UUID, has an associated storey and a name.
+/// `deleted_at` is set while the room is in the trash.
+/// The remaining attributes are optional and described in `RoomAttributes`.
 pub struct Room {
     pub id: uuid::Uuid,
     pub name: String,
-    pub storey_id: uuid::Uuid
+    pub storey_id: uuid::Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub seated_capacity: Option<i32>,
+    pub standing_capacity: Option<i32>,
+    pub room_type: Option<String>,
+    pub area: Option<f64>,
+    pub wheelchair_accessible: Option<bool>,
+    pub hearing_loop: Option<bool>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub footprint: Option<serde_json::Value>,
+    pub plan_polygon: Option<serde_json::Value>
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 /// Room type, potentially without UUID, that may be passed as part of a POST or PUT request.
 pub struct OptionalIDRoom {
     pub id: Option<uuid::Uuid>,
     pub name: String,
-    pub storey_id: uuid::Uuid
+    pub storey_id: uuid::Uuid,
+    #[serde(flatten)]
+    pub attributes: RoomAttributes
+}
+
+/// Valid values for the `room_type` of a room.
+pub const ROOM_TYPES: [&str; 8] = ["office", "lecture_hall", "seminar", "meeting", "lab", "workshop", "storage", "other"];
+
+#[derive(Deserialize, Clone, Default, ToSchema)]
+/// Optional attributes of a room used to pick a room for an event.
+/// Capacities are numbers of people, `area` is the floor area in m²,
+/// `room_type` is one of `ROOM_TYPES`. The position and footprint are WGS84 like those of buildings,
+/// `plan_polygon` is the outline of the room on the floor plan of its storey as `[[x, y], ...]`.
+/// An attribute that is left out keeps its value on an update, `null` clears it.
+pub struct RoomAttributes {
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub seated_capacity: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub standing_capacity: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub room_type: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub area: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub wheelchair_accessible: Option<Option<bool>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub hearing_loop: Option<Option<bool>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub latitude: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub longitude: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub footprint: Option<Option<serde_json::Value>>,
+    #[serde(default, deserialize_with = "deserialize_given")]
+    pub plan_polygon: Option<Option<serde_json::Value>>
+}
+
+impl RoomAttributes {
+    /// Fill the attributes that are not given from an existing room, those given as `null` stay cleared.
+    pub fn or(self, room: &Room) -> RoomAttributes {
+        RoomAttributes {
+            seated_capacity: self.seated_capacity.or(Some(room.seated_capacity)),
+            standing_capacity: self.standing_capacity.or(Some(room.standing_capacity)),
+            room_type: self.room_type.or(Some(room.room_type.clone())),
+            area: self.area.or(Some(room.area)),
+            wheelchair_accessible: self.wheelchair_accessible.or(Some(room.wheelchair_accessible)),
+            hearing_loop: self.hearing_loop.or(Some(room.hearing_loop)),
+            latitude: self.latitude.or(Some(room.latitude)),
+            longitude: self.longitude.or(Some(room.longitude)),
+            footprint: self.footprint.or(Some(room.footprint.clone())),
+            plan_polygon: self.plan_polygon.or(Some(room.plan_polygon.clone()))
+        }
+    }
+}
+
+#[derive(Deserialize, Default, IntoParams)]
+/// Filter for listing rooms, all criteria are optional and combined.
+/// `min_capacity` matches rooms with enough seated or standing places,
+/// `equipment` is a comma-separated list of equipment keys that must all be present,
+/// `as_of` returns the rooms as they were at that point in time.
+pub struct RoomFilter {
+    pub storey_id: Option<uuid::Uuid>,
+    pub include_deleted: Option<bool>,
+    pub as_of: Option<DateTime<Utc>>,
+    pub min_capacity: Option<i32>,
+    #[serde(rename = "type")]
+    pub room_type: Option<String>,
+    pub wheelchair_accessible: Option<bool>,
+    pub hearing_loop: Option<bool>,
+    pub equipment: Option<String>
+}
+
+/// Split the comma separated equipment keys of a filter, leaving out empty ones.
+fn equipment_keys(raw: &Option<String>) -> Vec<String> {
+    match raw {
+        Some(keys) => keys.split(',').map(|key| key.trim().to_string()).filter(|key| !key.is_empty()).collect(),
+        None => Vec::new()
+    }
+}
+
+impl RoomFilter {
+    /// Return the equipment keys the rooms must have.
+    pub fn equipment_keys(&self) -> Vec<String> {
+        equipment_keys(&self.equipment)
+    }
+
+    /// Check a room against the filter in memory, for rooms that do not come from a DB query.
+    /// The `include_deleted`, `as_of` and `equipment` criteria are not checked here.
+    pub fn matches(&self, room: &Room) -> bool {
+        self.storey_id.is_none_or(|id| room.storey_id == id)
+            && self.min_capacity.is_none_or(|min| {
+                room.seated_capacity.is_some_and(|cap| cap >= min) || room.standing_capacity.is_some_and(|cap| cap >= min)
+            })
+            && self.room_type.as_ref().is_none_or(|kind| room.room_type.as_ref() == Some(kind))
+            && self.wheelchair_accessible.is_none_or(|flag| room.wheelchair_accessible == Some(flag))
+            && self.hearing_loop.is_none_or(|flag| room.hearing_loop == Some(flag))
+    }
+
+    /// Check if the filter selects nothing but the storey, the room lists the read cache keeps.
+    pub fn only_by_storey(&self) -> bool {
+        self.as_of.is_none() && self.min_capacity.is_none() && self.room_type.is_none()
+            && self.wheelchair_accessible.is_none() && self.hearing_loop.is_none() && self.equipment.is_none()
+    }
 }
 
 #[derive(Deserialize)]
-/// Reservation type, used to check for existing room reservations while deleting rooms.
+#[allow(dead_code)] // mirrors the reservations API, not every field is used here
+/// Reservation type, used to check for existing room reservations and for room availability.
+/// A reservation lasts from `from` until just before `to`, a plain date as `to` includes that whole day.
+/// A time that is missing or cannot be read is `None` and leaves the reservation open at that end.
 pub struct Reservation {
     pub id: uuid::Uuid,
-    pub from: String,
-    pub to: String,
+    #[serde(default, deserialize_with = "crate::reservations::deserialize_start")]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "crate::reservations::deserialize_end")]
+    pub to: Option<DateTime<Utc>>,
     pub room_id: uuid::Uuid
 }
+
+impl Reservation {
+    /// Check if the reservation takes up any time between `from` and `to`.
+    pub fn overlaps(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|start| start < to) && self.to.is_none_or(|end| from < end)
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+/// Query for rooms that are free for the whole time from `from` to `to`.
+pub struct AvailabilityQuery {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub min_capacity: Option<i32>,
+    pub building_id: Option<uuid::Uuid>
+}
+
+#[derive(Serialize, ToSchema)]
+/// Assets affected by a (cascading) delete, also returned as the result of a dry run.
+pub struct DeletionReport {
+    pub buildings: Vec<uuid::Uuid>,
+    pub storeys: Vec<uuid::Uuid>,
+    pub rooms: Vec<uuid::Uuid>
+}
+
+#[derive(Deserialize, ToSchema)]
+/// Target of a storey move, another building and/or a new level.
+/// The storey keeps its level if none is given.
+pub struct MoveStorey {
+    pub building_id: uuid::Uuid,
+    pub level: Option<i32>
+}
+
+#[derive(Deserialize, ToSchema)]
+/// Target of a room move.
+pub struct MoveRoom {
+    pub storey_id: uuid::Uuid
+}
+
+#[derive(Deserialize, ToSchema)]
+/// Target of a bulk move of the rooms of a storey, `room_ids` picks some of them instead of all.
+pub struct MoveRooms {
+    pub storey_id: uuid::Uuid,
+    pub room_ids: Option<Vec<uuid::Uuid>>
+}
+
+#[derive(Deserialize, Clone, ToSchema)]
+/// Rewrites names of cloned assets, names starting with `prefix` get `replacement` instead,
+/// e.g. prefix `2` and replacement `3` turn room `2.014` into `3.014`.
+pub struct NamePattern {
+    pub prefix: String,
+    pub replacement: String
+}
+
+impl NamePattern {
+    pub fn apply(&self, name: &str) -> String {
+        match name.strip_prefix(&self.prefix) {
+            Some(rest) if !self.prefix.is_empty() => format!("{}{}", self.replacement, rest),
+            _ => name.to_string()
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+/// Where to put the copy of a storey. The copy keeps the name (after `rename`) and level of the original
+/// unless `name` or `level` are given.
+pub struct CloneStorey {
+    pub building_id: uuid::Uuid,
+    pub name: Option<String>,
+    pub level: Option<i32>,
+    pub rename: Option<NamePattern>
+}
+
+#[derive(Deserialize, ToSchema)]
+/// Name and address of the copy of a building, it gets the address of the original if none is given.
+pub struct CloneBuilding {
+    pub name: String,
+    pub address: Option<String>,
+    pub rename: Option<NamePattern>
+}
+
+#[derive(Serialize, Default, ToSchema)]
+/// UUIDs of the originals of a clone, mapped to the UUIDs of their copies.
+pub struct CloneMapping {
+    pub buildings: BTreeMap<uuid::Uuid, uuid::Uuid>,
+    pub storeys: BTreeMap<uuid::Uuid, uuid::Uuid>,
+    pub rooms: BTreeMap<uuid::Uuid, uuid::Uuid>,
+    pub room_equipment: BTreeMap<uuid::Uuid, uuid::Uuid>
+}
+
+#[derive(Serialize)]
+/// Room that ended up in another building, as reported to the reservations service.
+/// The room keeps its UUID, so reservations stay attached to it.
+pub struct RoomMove {
+    pub room_id: uuid::Uuid,
+    pub storey_id: uuid::Uuid,
+    pub from_building_id: uuid::Uuid,
+    pub to_building_id: uuid::Uuid
+}
+
+#[derive(Queryable, Insertable)]
+#[table_name = "room_move_notifications"]
+/// Room move the reservations service has not been told about yet, or a dead letter once `status` is `dead`.
+pub struct RoomMoveNotification {
+    pub id: uuid::Uuid,
+    pub room_id: uuid::Uuid,
+    pub storey_id: uuid::Uuid,
+    pub from_building_id: uuid::Uuid,
+    pub to_building_id: uuid::Uuid,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub status: String
+}
+
+impl RoomMoveNotification {
+    pub fn room_move(&self) -> RoomMove {
+        RoomMove { room_id: self.room_id, storey_id: self.storey_id, from_building_id: self.from_building_id, to_building_id: self.to_building_id }
+    }
+}
+
+#[derive(Serialize, Queryable, Insertable, ToSchema)]
+#[table_name = "audit_log"]
+/// Entry in the append-only audit log, records who changed which asset and how.
+/// `before` and `after` hold JSON snapshots of the asset, missing on create and delete respectively.
+pub struct AuditEntry {
+    pub id: uuid::Uuid,
+    pub occurred_at: DateTime<Utc>,
+    pub actor_id: Option<String>,
+    pub actor_name: Option<String>,
+    pub action: String,
+    pub entity_type: String,
+    pub entity_id: uuid::Uuid,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub trace_id: Option<String>
+}
+
+#[derive(Deserialize, IntoParams)]
+/// Filter for querying the audit log, all criteria are optional and combined.
+/// `actor` matches both the actor ID and name.
+pub struct AuditFilter {
+    pub entity_type: Option<String>,
+    pub entity_id: Option<uuid::Uuid>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>
+}
+
+#[derive(Serialize, Queryable, ToSchema)]
+/// Version of a building, storey, or room, valid from `valid_from` until `valid_to`.
+/// `data` holds the asset as it was during that time, `valid_to` is missing for the current version.
+/// The version number is increasing, but shared between all assets.
+pub struct AssetVersion {
+    #[serde(rename = "version")]
+    pub id: i64,
+    pub entity_type: String,
+    pub entity_id: uuid::Uuid,
+    pub valid_from: DateTime<Utc>,
+    pub valid_to: Option<DateTime<Utc>>,
+    pub data: serde_json::Value
+}
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Identifiable, ToSchema)]
+#[table_name = "equipment"]
+/// Equipment type in the catalogue, e.g. a projector.
+/// The `key` is a short unique identifier used to filter rooms by equipment.
+pub struct Equipment {
+    pub id: uuid::Uuid,
+    pub key: String,
+    pub name: String
+}
+
+#[derive(Deserialize, ToSchema)]
+/// Equipment type, potentially without UUID, that may be passed as part of a POST or PUT request.
+pub struct OptionalIDEquipment {
+    pub id: Option<uuid::Uuid>,
+    pub key: String,
+    pub name: String
+}
+
+#[derive(Serialize, Deserialize, Queryable, Insertable, Identifiable, ToSchema)]
+#[table_name = "room_equipment"]
+/// Equipment present in a room, identified by UUID.
+/// Either a number of identical items or a single item with a serial number.
+pub struct RoomEquipment {
+    pub id: uuid::Uuid,
+    pub room_id: uuid::Uuid,
+    pub equipment_id: uuid::Uuid,
+    pub quantity: i32,
+    pub serial_number: Option<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+/// Room equipment, potentially without UUID, that may be passed as part of a POST or PUT request.
+/// The room is taken from the request path, the quantity defaults to 1.
+pub struct OptionalIDRoomEquipment {
+    pub id: Option<uuid::Uuid>,
+    pub equipment_id: uuid::Uuid,
+    #[serde(default = "default_quantity")]
+    pub quantity: i32,
+    pub serial_number: Option<String>
+}
+
+fn default_quantity() -> i32 { 1 }
+
+#[derive(Serialize, Queryable, Insertable, AsChangeset, ToSchema)]
+#[table_name = "floor_plans"]
+#[changeset_options(treat_none_as_null = "true")]
+/// Floor plan of a storey, an SVG or PNG image of `width` x `height` in floor-plan coordinates.
+/// The image is stored either in `data` or in a file at `path` in the floor plan directory.
+pub struct FloorPlan {
+    pub storey_id: uuid::Uuid,
+    pub content_type: String,
+    pub width: f64,
+    pub height: f64,
+    #[serde(skip)]
+    pub data: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub path: Option<String>,
+    pub uploaded_at: DateTime<Utc>
+}
+
+#[derive(Deserialize, IntoParams)]
+/// Filter for exporting the asset hierarchy, the criteria of the building, storey and room lists combined.
+/// `format` is `csv` (default), `jsonl` or `xlsx`.
+pub struct ExportFilter {
+    pub format: Option<String>,
+    pub building_id: Option<uuid::Uuid>,
+    pub storey_id: Option<uuid::Uuid>,
+    pub include_deleted: Option<bool>,
+    pub as_of: Option<DateTime<Utc>>,
+    pub min_capacity: Option<i32>,
+    #[serde(rename = "type")]
+    pub room_type: Option<String>,
+    pub wheelchair_accessible: Option<bool>,
+    pub hearing_loop: Option<bool>,
+    pub equipment: Option<String>
+}
+
+impl ExportFilter {
+    /// Return the equipment keys the rooms must have.
+    pub fn equipment_keys(&self) -> Vec<String> {
+        equipment_keys(&self.equipment)
+    }
+
+    /// Check if any room criteria are set, then only rows with a matching room are exported.
+    pub fn filters_rooms(&self) -> bool {
+        self.min_capacity.is_some() || self.room_type.is_some() || self.wheelchair_accessible.is_some()
+            || self.hearing_loop.is_some() || !self.equipment_keys().is_empty()
+    }
+}
+
+#[derive(Serialize, QueryableByName)]
+/// One row of the flattened asset hierarchy: a room with its storey and building.
+/// Storeys without rooms and buildings without storeys have empty columns for the missing levels.
+/// Geometry (footprints, plan polygons) is left out, it is available as GeoJSON.
+pub struct ExportRow {
+    #[sql_type = "diesel::sql_types::Uuid"]
+    pub building_id: uuid::Uuid,
+    #[sql_type = "Text"]
+    pub building_name: String,
+    #[sql_type = "Text"]
+    pub address: String,
+    #[sql_type = "Nullable<Text>"]
+    pub street: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub house_number: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub postal_code: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub city: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub country: Option<String>,
+    #[sql_type = "Nullable<Double>"]
+    pub building_latitude: Option<f64>,
+    #[sql_type = "Nullable<Double>"]
+    pub building_longitude: Option<f64>,
+    #[sql_type = "Nullable<Timestamptz>"]
+    pub building_deleted_at: Option<DateTime<Utc>>,
+    #[sql_type = "Nullable<diesel::sql_types::Uuid>"]
+    pub storey_id: Option<uuid::Uuid>,
+    #[sql_type = "Nullable<Text>"]
+    pub storey_name: Option<String>,
+    #[sql_type = "Nullable<Integer>"]
+    pub level: Option<i32>,
+    #[sql_type = "Nullable<Double>"]
+    pub elevation: Option<f64>,
+    #[sql_type = "Nullable<Integer>"]
+    pub display_order: Option<i32>,
+    #[sql_type = "Nullable<Timestamptz>"]
+    pub storey_deleted_at: Option<DateTime<Utc>>,
+    #[sql_type = "Nullable<diesel::sql_types::Uuid>"]
+    pub room_id: Option<uuid::Uuid>,
+    #[sql_type = "Nullable<Text>"]
+    pub room_name: Option<String>,
+    #[sql_type = "Nullable<Integer>"]
+    pub seated_capacity: Option<i32>,
+    #[sql_type = "Nullable<Integer>"]
+    pub standing_capacity: Option<i32>,
+    #[sql_type = "Nullable<Text>"]
+    pub room_type: Option<String>,
+    #[sql_type = "Nullable<Double>"]
+    pub area: Option<f64>,
+    #[sql_type = "Nullable<Bool>"]
+    pub wheelchair_accessible: Option<bool>,
+    #[sql_type = "Nullable<Bool>"]
+    pub hearing_loop: Option<bool>,
+    #[sql_type = "Nullable<Double>"]
+    pub room_latitude: Option<f64>,
+    #[sql_type = "Nullable<Double>"]
+    pub room_longitude: Option<f64>,
+    #[sql_type = "Nullable<Timestamptz>"]
+    pub room_deleted_at: Option<DateTime<Utc>>
+}
+
+#[derive(Clone, Serialize, Queryable, Insertable, ToSchema)]
+/// Subscription to asset change events. Deliveries are signed with `secret`, which is only shown once on creation.
+/// `events` are event types like `room.updated`, or patterns like `room.*` and `*`.
+pub struct Webhook {
+    pub id: uuid::Uuid,
+    pub url: String,
+    #[serde(skip)]
+    pub secret: String,
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>
+}
+
+#[derive(Deserialize, ToSchema)]
+/// Webhook subscription as sent by the client, a secret is generated if none is given.
+pub struct NewWebhook {
+    pub url: String,
+    pub secret: Option<String>,
+    pub events: Vec<String>
+}
+
+#[derive(Serialize, Queryable, Insertable, ToSchema)]
+#[table_name = "webhook_deliveries"]
+/// Event waiting to be delivered to a webhook, or a dead letter once `status` is `dead`.
+pub struct WebhookDelivery {
+    pub id: uuid::Uuid,
+    pub webhook_id: uuid::Uuid,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>
+}
+
+#[derive(Queryable)]
+/// Event in the outbox, waiting for the relay to publish it.
+pub struct OutboxEntry {
+    pub seq: i64,
+    pub id: uuid::Uuid,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub created_at: DateTime<Utc>
+}
+
+#[derive(Insertable)]
+#[table_name = "outbox"]
+pub struct NewOutboxEntry {
+    pub id: uuid::Uuid,
+    pub event_type: String,
+    pub payload: serde_json::Value
+}
+
+#[derive(Clone, Serialize, Queryable, ToSchema)]
+/// Change of a building, storey or room as recorded by the `asset_changes` triggers, sent by `GET /assets/events`.
+/// `data` is the asset after the change, or before it if the row was removed.
+/// `tx` is the transaction that made the change, changes are streamed in the order of `position()`.
+pub struct AssetChange {
+    pub id: i64,
+    pub entity_type: String,
+    pub entity_id: uuid::Uuid,
+    pub action: String,
+    #[serde(skip)]
+    pub building_ids: Vec<uuid::Uuid>,
+    pub data: serde_json::Value,
+    pub occurred_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub tx: i64
+}
+
+/// Where a change stands in the stream: the transaction that made it, then its ID.
+pub type StreamPosition = (i64, i64);
+
+impl AssetChange {
+    pub fn position(&self) -> StreamPosition {
+        (self.tx, self.id)
+    }
+}
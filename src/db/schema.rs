@@ -8,6 +8,15 @@ table! {
         id -> diesel::sql_types::Uuid,
         name -> diesel::sql_types::Text,
         address -> diesel::sql_types::Text,
+        deleted_at -> diesel::sql_types::Nullable<diesel::sql_types::Timestamptz>,
+        street -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        house_number -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        postal_code -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        city -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        country -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        latitude -> diesel::sql_types::Nullable<diesel::sql_types::Double>,
+        longitude -> diesel::sql_types::Nullable<diesel::sql_types::Double>,
+        footprint -> diesel::sql_types::Nullable<diesel::sql_types::Jsonb>,
     }
 }
 
@@ -16,6 +25,10 @@ table! {
         id -> diesel::sql_types::Uuid,
         name -> diesel::sql_types::Text,
         building_id -> diesel::sql_types::Uuid,
+        deleted_at -> diesel::sql_types::Nullable<diesel::sql_types::Timestamptz>,
+        level -> diesel::sql_types::Nullable<diesel::sql_types::Integer>,
+        elevation -> diesel::sql_types::Nullable<diesel::sql_types::Double>,
+        display_order -> diesel::sql_types::Nullable<diesel::sql_types::Integer>,
     }
 }
 
@@ -24,5 +37,147 @@ table! {
         id -> diesel::sql_types::Uuid,
         name -> diesel::sql_types::Text,
         storey_id -> diesel::sql_types::Uuid,
+        deleted_at -> diesel::sql_types::Nullable<diesel::sql_types::Timestamptz>,
+        seated_capacity -> diesel::sql_types::Nullable<diesel::sql_types::Integer>,
+        standing_capacity -> diesel::sql_types::Nullable<diesel::sql_types::Integer>,
+        room_type -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        area -> diesel::sql_types::Nullable<diesel::sql_types::Double>,
+        wheelchair_accessible -> diesel::sql_types::Nullable<diesel::sql_types::Bool>,
+        hearing_loop -> diesel::sql_types::Nullable<diesel::sql_types::Bool>,
+        latitude -> diesel::sql_types::Nullable<diesel::sql_types::Double>,
+        longitude -> diesel::sql_types::Nullable<diesel::sql_types::Double>,
+        footprint -> diesel::sql_types::Nullable<diesel::sql_types::Jsonb>,
+        plan_polygon -> diesel::sql_types::Nullable<diesel::sql_types::Jsonb>,
     }
 }
+
+table! {
+    pub audit_log (id) {
+        id -> diesel::sql_types::Uuid,
+        occurred_at -> diesel::sql_types::Timestamptz,
+        actor_id -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        actor_name -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        action -> diesel::sql_types::Text,
+        entity_type -> diesel::sql_types::Text,
+        entity_id -> diesel::sql_types::Uuid,
+        before -> diesel::sql_types::Nullable<diesel::sql_types::Jsonb>,
+        after -> diesel::sql_types::Nullable<diesel::sql_types::Jsonb>,
+        trace_id -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+    }
+}
+
+table! {
+    pub asset_history (id) {
+        id -> diesel::sql_types::BigInt,
+        entity_type -> diesel::sql_types::Text,
+        entity_id -> diesel::sql_types::Uuid,
+        valid_from -> diesel::sql_types::Timestamptz,
+        valid_to -> diesel::sql_types::Nullable<diesel::sql_types::Timestamptz>,
+        data -> diesel::sql_types::Jsonb,
+    }
+}
+
+table! {
+    pub equipment (id) {
+        id -> diesel::sql_types::Uuid,
+        key -> diesel::sql_types::Text,
+        name -> diesel::sql_types::Text,
+    }
+}
+
+table! {
+    pub room_equipment (id) {
+        id -> diesel::sql_types::Uuid,
+        room_id -> diesel::sql_types::Uuid,
+        equipment_id -> diesel::sql_types::Uuid,
+        quantity -> diesel::sql_types::Integer,
+        serial_number -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+    }
+}
+
+table! {
+    pub floor_plans (storey_id) {
+        storey_id -> diesel::sql_types::Uuid,
+        content_type -> diesel::sql_types::Text,
+        width -> diesel::sql_types::Double,
+        height -> diesel::sql_types::Double,
+        data -> diesel::sql_types::Nullable<diesel::sql_types::Bytea>,
+        path -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        uploaded_at -> diesel::sql_types::Timestamptz,
+    }
+}
+
+table! {
+    pub webhooks (id) {
+        id -> diesel::sql_types::Uuid,
+        url -> diesel::sql_types::Text,
+        secret -> diesel::sql_types::Text,
+        events -> diesel::sql_types::Array<diesel::sql_types::Text>,
+        created_at -> diesel::sql_types::Timestamptz,
+    }
+}
+
+table! {
+    pub webhook_deliveries (id) {
+        id -> diesel::sql_types::Uuid,
+        webhook_id -> diesel::sql_types::Uuid,
+        event_type -> diesel::sql_types::Text,
+        payload -> diesel::sql_types::Jsonb,
+        status -> diesel::sql_types::Text,
+        attempts -> diesel::sql_types::Integer,
+        next_attempt_at -> diesel::sql_types::Timestamptz,
+        last_error -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        created_at -> diesel::sql_types::Timestamptz,
+    }
+}
+
+table! {
+    pub outbox (seq) {
+        seq -> diesel::sql_types::BigInt,
+        id -> diesel::sql_types::Uuid,
+        event_type -> diesel::sql_types::Text,
+        payload -> diesel::sql_types::Jsonb,
+        created_at -> diesel::sql_types::Timestamptz,
+    }
+}
+
+table! {
+    pub asset_changes (id) {
+        id -> diesel::sql_types::BigInt,
+        entity_type -> diesel::sql_types::Text,
+        entity_id -> diesel::sql_types::Uuid,
+        action -> diesel::sql_types::Text,
+        building_ids -> diesel::sql_types::Array<diesel::sql_types::Uuid>,
+        data -> diesel::sql_types::Jsonb,
+        occurred_at -> diesel::sql_types::Timestamptz,
+        tx -> diesel::sql_types::BigInt,
+    }
+}
+
+table! {
+    pub room_move_notifications (id) {
+        id -> diesel::sql_types::Uuid,
+        room_id -> diesel::sql_types::Uuid,
+        storey_id -> diesel::sql_types::Uuid,
+        from_building_id -> diesel::sql_types::Uuid,
+        to_building_id -> diesel::sql_types::Uuid,
+        attempts -> diesel::sql_types::Integer,
+        next_attempt_at -> diesel::sql_types::Timestamptz,
+        last_error -> diesel::sql_types::Nullable<diesel::sql_types::Text>,
+        created_at -> diesel::sql_types::Timestamptz,
+        status -> diesel::sql_types::Text,
+    }
+}
+
+table! {
+    pub collection_version (id) {
+        id -> diesel::sql_types::Bool,
+        version -> diesel::sql_types::BigInt,
+        changed_at -> diesel::sql_types::Timestamptz,
+    }
+}
+
+joinable!(room_equipment -> equipment (equipment_id));
+joinable!(webhook_deliveries -> webhooks (webhook_id));
+allow_tables_to_appear_in_same_query!(rooms, room_equipment, equipment);
+allow_tables_to_appear_in_same_query!(webhooks, webhook_deliveries);
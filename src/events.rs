@@ -0,0 +1,78 @@
+// Live stream of asset changes for `GET /assets/events`.
+//
+// The `asset_changes` triggers record every change to buildings, storeys and rooms and announce its ID with NOTIFY.
+// The `jobs::changes` thread listens on its own connection, loads the new changes once they are settled and passes
+// them to every open stream through a broadcast channel. Changes are streamed by the transaction that made them
+// and then by ID, so the IDs of a stream are not always ascending. Streams are Server-Sent Events:
+//
+//   id: 42
+//   event: room.updated
+//   data: {"id":42,"entity_type":"room","entity_id":"...","action":"updated","data":{...},"occurred_at":"..."}
+
+use actix_web::web::Bytes;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::db::models::AssetChange;
+
+/// Changes kept for streams that fall behind, a stream that misses more catches up from the database.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// The channel every open stream receives the changes from.
+pub fn changes() -> &'static broadcast::Sender<Arc<AssetChange>> {
+    static CHANNEL: OnceLock<broadcast::Sender<Arc<AssetChange>>> = OnceLock::new();
+    CHANNEL.get_or_init(|| broadcast::channel(CHANNEL_CAPACITY).0)
+}
+
+/// Check if a change concerns the building, or any if there is none.
+pub fn concerns(change: &AssetChange, building: Option<Uuid>) -> bool {
+    building.is_none_or(|id| change.building_ids.contains(&id))
+}
+
+/// A change as a Server-Sent Event, its ID is what clients send as `Last-Event-ID` to resume.
+pub fn sse_event(change: &AssetChange) -> Bytes {
+    let data = serde_json::to_string(change).unwrap_or_default();
+    Bytes::from(format!("id: {}\nevent: {}.{}\ndata: {}\n\n", change.id, change.entity_type, change.action, data))
+}
+
+/// A comment, which clients ignore, keeps proxies from closing idle streams.
+pub fn sse_keepalive() -> Bytes {
+    Bytes::from_static(b": keepalive\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+
+    #[test]
+    fn test_sse_event() {
+        let building = Uuid::new_v4();
+        let change = AssetChange {
+            id: 42,
+            entity_type: "room".to_string(),
+            entity_id: Uuid::new_v4(),
+            action: "updated".to_string(),
+            building_ids: vec![building],
+            data: json!({ "name": "A.101" }),
+            occurred_at: Utc::now(),
+            tx: 1000
+        };
+
+        let event = String::from_utf8(sse_event(&change).to_vec()).unwrap();
+        let lines : Vec<&str> = event.lines().collect();
+        assert_eq!(lines[0], "id: 42");
+        assert_eq!(lines[1], "event: room.updated");
+        let data : serde_json::Value = serde_json::from_str(lines[2].strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(data["data"]["name"], "A.101");
+        assert!(data.get("building_ids").is_none());
+        assert!(data.get("tx").is_none());
+        assert!(event.ends_with("\n\n"));
+
+        assert!(concerns(&change, Some(building)));
+        assert!(concerns(&change, None));
+        assert!(!concerns(&change, Some(Uuid::new_v4())));
+    }
+}
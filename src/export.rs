@@ -0,0 +1,260 @@
+// Writers for exporting the flattened asset hierarchy as CSV, JSON Lines or XLSX.
+// All of them write row by row into any `Write`, so an export can be streamed to the client.
+
+use serde_json::Value;
+use std::io::{self, Write};
+use zip::write::{SimpleFileOptions, StreamWriter};
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::db::models::ExportRow;
+
+/// Columns of an export, in the order of the fields of `ExportRow`.
+pub const EXPORT_COLUMNS: [&str; 28] = [
+    "building_id", "building_name", "address", "street", "house_number", "postal_code", "city", "country",
+    "building_latitude", "building_longitude", "building_deleted_at",
+    "storey_id", "storey_name", "level", "elevation", "display_order", "storey_deleted_at",
+    "room_id", "room_name", "seated_capacity", "standing_capacity", "room_type", "area",
+    "wheelchair_accessible", "hearing_loop", "room_latitude", "room_longitude", "room_deleted_at"
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    Xlsx
+}
+
+impl ExportFormat {
+    /// Parse the `format` query parameter, CSV if there is none.
+    pub fn parse(format: Option<&str>) -> Option<ExportFormat> {
+        match format.map(|format| format.to_lowercase()).as_deref() {
+            None | Some("csv") => Some(ExportFormat::Csv),
+            Some("jsonl") | Some("ndjson") => Some(ExportFormat::Jsonl),
+            Some("xlsx") => Some(ExportFormat::Xlsx),
+            _ => None
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/jsonl",
+            ExportFormat::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Xlsx => "xlsx"
+        }
+    }
+}
+
+/// Writes export rows in one of the formats.
+pub enum ExportWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Jsonl(W),
+    Xlsx(Box<XlsxWriter<W>>)
+}
+
+impl<W: Write> ExportWriter<W> {
+    /// Start an export, this already writes the header row for CSV and XLSX.
+    pub fn new(format: ExportFormat, inner: W) -> io::Result<ExportWriter<W>> {
+        match format {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(inner);
+                writer.write_record(EXPORT_COLUMNS)?;
+                Ok(ExportWriter::Csv(Box::new(writer)))
+            },
+            ExportFormat::Jsonl => Ok(ExportWriter::Jsonl(inner)),
+            ExportFormat::Xlsx => Ok(ExportWriter::Xlsx(Box::new(XlsxWriter::new(inner)?)))
+        }
+    }
+
+    pub fn write_row(&mut self, row: &ExportRow) -> io::Result<()> {
+        match self {
+            ExportWriter::Csv(writer) => writer.serialize(row).map_err(io::Error::other),
+            ExportWriter::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, row)?;
+                writer.write_all(b"\n")
+            },
+            ExportWriter::Xlsx(writer) => writer.write_row(row)
+        }
+    }
+
+    /// Complete the export, e.g. write the end of the XLSX file, and flush everything.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            ExportWriter::Csv(mut writer) => writer.flush(),
+            ExportWriter::Jsonl(mut writer) => writer.flush(),
+            ExportWriter::Xlsx(writer) => writer.finish()
+        }
+    }
+}
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#;
+
+const ROOT_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+
+const WORKBOOK: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="Assets" sheetId="1" r:id="rId1"/></sheets></workbook>"#;
+
+const WORKBOOK_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#;
+
+const SHEET_START: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>"#;
+
+const SHEET_END: &str = "</sheetData></worksheet>";
+
+/// Minimal XLSX workbook with a single sheet, written as a stream.
+/// The sheet is the last part of the ZIP file, so rows can be appended until the export is finished.
+/// Strings are stored inline instead of in a shared string table, which would have to be written at the end.
+pub struct XlsxWriter<W: Write> {
+    zip: ZipWriter<StreamWriter<W>>,
+    rows: usize
+}
+
+impl<W: Write> XlsxWriter<W> {
+    pub fn new(inner: W) -> io::Result<XlsxWriter<W>> {
+        let mut zip = ZipWriter::new_stream(inner);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, content) in [("[Content_Types].xml", CONTENT_TYPES), ("_rels/.rels", ROOT_RELS), ("xl/workbook.xml", WORKBOOK), ("xl/_rels/workbook.xml.rels", WORKBOOK_RELS)] {
+            zip.start_file(name, options)?;
+            zip.write_all(content.as_bytes())?;
+        }
+        zip.start_file("xl/worksheets/sheet1.xml", options)?;
+        zip.write_all(SHEET_START.as_bytes())?;
+
+        let mut writer = XlsxWriter { zip, rows: 0 };
+        let header : Vec<Value> = EXPORT_COLUMNS.iter().map(|column| Value::from(*column)).collect();
+        writer.write_cells(&header)?;
+        Ok(writer)
+    }
+
+    pub fn write_row(&mut self, row: &ExportRow) -> io::Result<()> {
+        let values = serde_json::to_value(row)?;
+        let cells : Vec<Value> = EXPORT_COLUMNS.iter().map(|column| values.get(*column).cloned().unwrap_or(Value::Null)).collect();
+        self.write_cells(&cells)
+    }
+
+    fn write_cells(&mut self, cells: &[Value]) -> io::Result<()> {
+        self.rows += 1;
+        let mut xml = format!("<row r=\"{}\">", self.rows);
+        for (index, value) in cells.iter().enumerate() {
+            let reference = format!("{}{}", column_name(index), self.rows);
+            match value {
+                Value::Null => continue,
+                Value::Bool(flag) => xml.push_str(&format!("<c r=\"{}\" t=\"b\"><v>{}</v></c>", reference, if *flag { 1 } else { 0 })),
+                Value::Number(number) => xml.push_str(&format!("<c r=\"{}\"><v>{}</v></c>", reference, number)),
+                Value::String(text) => xml.push_str(&format!("<c r=\"{}\" t=\"inlineStr\"><is><t>{}</t></is></c>", reference, escape(text))),
+                other => xml.push_str(&format!("<c r=\"{}\" t=\"inlineStr\"><is><t>{}</t></is></c>", reference, escape(&other.to_string())))
+            }
+        }
+        xml.push_str("</row>");
+        self.zip.write_all(xml.as_bytes())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.zip.write_all(SHEET_END.as_bytes())?;
+        self.zip.finish()?.into_inner().flush()
+    }
+}
+
+/// Spreadsheet column name for a zero-based index, `A` to `Z`, then `AA` and so on.
+fn column_name(index: usize) -> String {
+    let mut name = String::new();
+    let mut index = index + 1;
+    while index > 0 {
+        let rest = (index - 1) % 26;
+        name.insert(0, (b'A' + rest as u8) as char);
+        index = (index - 1) / 26;
+    }
+    name
+}
+
+/// Escape text content for XML, control characters are not allowed in XML 1.0 and are dropped.
+fn escape(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            c => c.to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_row() -> ExportRow {
+        ExportRow {
+            building_id: uuid::Uuid::nil(),
+            building_name: "Main, \"Old\" & New".to_string(),
+            address: "Hauptstraße 1".to_string(),
+            street: None, house_number: None, postal_code: None, city: None, country: None,
+            building_latitude: None, building_longitude: None, building_deleted_at: None,
+            storey_id: None, storey_name: Some("Ground Floor".to_string()), level: Some(0), elevation: None, display_order: None, storey_deleted_at: None,
+            room_id: None, room_name: Some("A.001".to_string()), seated_capacity: Some(30), standing_capacity: None,
+            room_type: Some("seminar".to_string()), area: Some(42.5), wheelchair_accessible: Some(true), hearing_loop: None,
+            room_latitude: None, room_longitude: None, room_deleted_at: None
+        }
+    }
+
+    #[test]
+    fn test_columns_match_rows() {
+        let values = serde_json::to_value(sample_row()).unwrap();
+        let fields = values.as_object().unwrap();
+        assert_eq!(fields.len(), EXPORT_COLUMNS.len());
+        assert!(EXPORT_COLUMNS.iter().all(|column| fields.contains_key(*column)));
+    }
+
+    #[test]
+    fn test_csv_and_jsonl() {
+        let mut out = Vec::new();
+        let mut writer = ExportWriter::new(ExportFormat::Csv, &mut out).unwrap();
+        writer.write_row(&sample_row()).unwrap();
+        writer.finish().unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines : Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], EXPORT_COLUMNS.join(","));
+        assert!(lines[1].starts_with("00000000-0000-0000-0000-000000000000,\"Main, \"\"Old\"\" & New\",Hauptstraße 1,"));
+
+        let mut out = Vec::new();
+        let mut writer = ExportWriter::new(ExportFormat::Jsonl, &mut out).unwrap();
+        writer.write_row(&sample_row()).unwrap();
+        writer.write_row(&sample_row()).unwrap();
+        writer.finish().unwrap();
+        let jsonl = String::from_utf8(out).unwrap();
+        assert_eq!(jsonl.lines().count(), 2);
+        assert_eq!(serde_json::from_str::<Value>(jsonl.lines().next().unwrap()).unwrap()["seated_capacity"], 30);
+    }
+
+    #[test]
+    fn test_xlsx() {
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(27), "AB");
+        assert_eq!(escape("R&D <lab>\u{1}"), "R&amp;D &lt;lab&gt;");
+
+        let mut out = Vec::new();
+        let mut writer = ExportWriter::new(ExportFormat::Xlsx, &mut out).unwrap();
+        writer.write_row(&sample_row()).unwrap();
+        writer.finish().unwrap();
+
+        let mut archive = zip::ZipArchive::new(io::Cursor::new(out)).unwrap();
+        let mut sheet = String::new();
+        io::Read::read_to_string(&mut archive.by_name("xl/worksheets/sheet1.xml").unwrap(), &mut sheet).unwrap();
+        assert!(sheet.contains("<c r=\"B2\" t=\"inlineStr\"><is><t>Main, \"Old\" &amp; New</t></is></c>"));
+        assert!(sheet.contains("<c r=\"T2\"><v>30</v></c>"));
+        assert!(sheet.contains("<c r=\"X2\" t=\"b\"><v>1</v></c>"));
+        assert!(sheet.ends_with("</sheetData></worksheet>"));
+    }
+}
@@ -0,0 +1,218 @@
+// Helpers for storey floor plans and the room outlines drawn on top of them.
+// Floor-plan coordinates start at the top left corner of the plan, x to the right and y downwards,
+// in pixels for PNG plans and in user units for SVG plans.
+
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Media types accepted for floor plans.
+pub const SVG: &str = "image/svg+xml";
+pub const PNG: &str = "image/png";
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+/// Directory for floor plan files, set with `FLOOR_PLAN_DIR`.
+/// Without it, floor plans are stored in the database.
+pub fn plan_dir() -> Option<PathBuf> {
+    env::var("FLOOR_PLAN_DIR").ok().filter(|dir| !dir.trim().is_empty()).map(PathBuf::from)
+}
+
+/// A floor plan file written under a temporary name, it only takes its place with `commit`
+/// once the floor plan is committed, so a plan that is rolled back leaves no file behind that looks like a plan.
+/// Every file gets a name of its own, `<UUID>.svg` or `.png`, so it never takes the place of the file of another plan.
+pub struct StagedPlanFile {
+    /// The name of the file once it is committed, what the floor plan refers to.
+    pub file_name: String,
+    temp_path: PathBuf,
+    path: PathBuf
+}
+
+impl StagedPlanFile {
+    fn new(dir: &Path, extension: &str) -> StagedPlanFile {
+        let file_name = format!("{}.{}", Uuid::new_v4(), extension);
+        StagedPlanFile {
+            temp_path: dir.join(format!(".{}.{}.upload", file_name, Uuid::new_v4())),
+            path: dir.join(&file_name),
+            file_name
+        }
+    }
+
+    /// Write a new floor plan file with the extension in `dir`.
+    pub fn write(dir: &Path, extension: &str, data: &[u8]) -> io::Result<StagedPlanFile> {
+        let staged = StagedPlanFile::new(dir, extension);
+        fs::create_dir_all(dir).and_then(|_| fs::write(&staged.temp_path, data)).inspect_err(|_| staged.remove_temp())?;
+        Ok(staged)
+    }
+
+    /// Copy the floor plan file `from` in `dir`.
+    pub fn copy(dir: &Path, from: &str) -> io::Result<StagedPlanFile> {
+        let extension = Path::new(from).extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+        let staged = StagedPlanFile::new(dir, extension);
+        fs::copy(dir.join(from), &staged.temp_path).inspect_err(|_| staged.remove_temp())?;
+        Ok(staged)
+    }
+
+    /// Give the file its name, after the floor plan was committed.
+    pub fn commit(self) -> io::Result<()> {
+        fs::rename(&self.temp_path, &self.path).inspect_err(|_| self.remove_temp())
+    }
+
+    /// Remove the file, the floor plan was not committed.
+    pub fn discard(self) {
+        self.remove_temp();
+    }
+
+    fn remove_temp(&self) {
+        let _ = fs::remove_file(&self.temp_path);
+    }
+}
+
+/// File extension for a floor plan media type.
+pub fn extension(content_type: &str) -> &'static str {
+    if content_type == PNG { "png" } else { "svg" }
+}
+
+/// Width and height of a floor plan, read from the PNG header or the root element of an SVG.
+/// Return None if the data is not a plan of the given type or has no usable size.
+pub fn dimensions(content_type: &str, data: &[u8]) -> Option<(f64, f64)> {
+    let (width, height) = match content_type {
+        PNG => png_dimensions(data)?,
+        SVG => svg_dimensions(std::str::from_utf8(data).ok()?)?,
+        _ => return None
+    };
+    if width > 0.0 && height > 0.0 && width.is_finite() && height.is_finite() { Some((width, height)) } else { None }
+}
+
+fn png_dimensions(data: &[u8]) -> Option<(f64, f64)> {
+    if data.len() < 24 || data[..8] != PNG_SIGNATURE || &data[12..16] != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(data[16..20].try_into().ok()?);
+    let height = u32::from_be_bytes(data[20..24].try_into().ok()?);
+    Some((width as f64, height as f64))
+}
+
+/// Take the size from the `viewBox` of the `<svg>` element, or from its `width` and `height`.
+fn svg_dimensions(text: &str) -> Option<(f64, f64)> {
+    let start = text.find("<svg")?;
+    let tag = &text[start..start + text[start..].find('>')?];
+
+    if let Some(view_box) = attribute(tag, "viewBox") {
+        let values : Vec<f64> = view_box.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|value| !value.is_empty())
+            .filter_map(|value| value.parse().ok())
+            .collect();
+        if values.len() == 4 {
+            return Some((values[2], values[3]));
+        }
+    }
+
+    let length = |value: &str| value.trim().trim_end_matches("px").parse::<f64>().ok();
+    Some((length(attribute(tag, "width")?)?, length(attribute(tag, "height")?)?))
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    for quote in ['"', '\''] {
+        let pattern = format!(" {}={}", name, quote);
+        if let Some(start) = tag.find(&pattern) {
+            let value = &tag[start + pattern.len()..];
+            return value.find(quote).map(|end| &value[..end]);
+        }
+    }
+    None
+}
+
+/// Read the outline of a room, a JSON array of at least three `[x, y]` points in floor-plan coordinates.
+/// Return None if the polygon is malformed.
+pub fn parse_polygon(polygon: &Value) -> Option<Vec<(f64, f64)>> {
+    let points : Vec<(f64, f64)> = polygon.as_array()?.iter().map(|point| {
+        let point = point.as_array().filter(|point| point.len() == 2)?;
+        let (x, y) = (point[0].as_f64()?, point[1].as_f64()?);
+        if x.is_finite() && y.is_finite() { Some((x, y)) } else { None }
+    }).collect::<Option<_>>()?;
+    if points.len() >= 3 { Some(points) } else { None }
+}
+
+/// A room outline to draw on the overlay.
+pub struct Outline {
+    pub room_id: uuid::Uuid,
+    pub label: String,
+    pub points: Vec<(f64, f64)>
+}
+
+/// Render an SVG with the room outlines and labels in floor-plan coordinates.
+/// If `plan_href` is given, the plan itself is drawn below the outlines.
+/// Every outline carries the room UUID in `data-room-id`, so a UI can react to clicks on it.
+pub fn overlay_svg(width: f64, height: f64, plan_href: Option<&str>, outlines: &[Outline]) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {w} {h}\" width=\"{w}\" height=\"{h}\">\n",
+        w = width, h = height
+    );
+    svg.push_str("<style>.room{fill:#3b82f6;fill-opacity:0.2;stroke:#1d4ed8;stroke-width:2;cursor:pointer}.room:hover{fill-opacity:0.4}.label{font:14px sans-serif;text-anchor:middle;dominant-baseline:middle;pointer-events:none}</style>\n");
+    if let Some(href) = plan_href {
+        svg.push_str(&format!("<image href=\"{}\" x=\"0\" y=\"0\" width=\"{}\" height=\"{}\"/>\n", escape_xml(href), width, height));
+    }
+
+    for outline in outlines {
+        let points : Vec<String> = outline.points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+        let count = outline.points.len() as f64;
+        let (cx, cy) = outline.points.iter().fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x / count, sy + y / count));
+        svg.push_str(&format!(
+            "<g id=\"room-{id}\"><polygon class=\"room\" data-room-id=\"{id}\" points=\"{points}\"><title>{label}</title></polygon><text class=\"label\" x=\"{cx}\" y=\"{cy}\">{label}</text></g>\n",
+            id = outline.room_id, points = points.join(" "), label = escape_xml(&outline.label), cx = cx, cy = cy
+        ));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_dimensions() {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(&[0, 0, 0, 13]);
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&800u32.to_be_bytes());
+        png.extend_from_slice(&600u32.to_be_bytes());
+        assert_eq!(dimensions(PNG, &png), Some((800.0, 600.0)));
+        assert!(dimensions(PNG, b"not a png").is_none());
+
+        let svg = br#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 1200 900"></svg>"#;
+        assert_eq!(dimensions(SVG, svg), Some((1200.0, 900.0)));
+        let svg = br#"<svg width='640px' height='480px'></svg>"#;
+        assert_eq!(dimensions(SVG, svg), Some((640.0, 480.0)));
+        assert!(dimensions(SVG, b"<svg></svg>").is_none());
+    }
+
+    #[test]
+    fn test_polygon() {
+        assert_eq!(parse_polygon(&json!([[0, 0], [10, 0], [10, 5]])), Some(vec![(0.0, 0.0), (10.0, 0.0), (10.0, 5.0)]));
+        assert!(parse_polygon(&json!([[0, 0], [10, 0]])).is_none());
+        assert!(parse_polygon(&json!([[0, 0], [10, 0], [10]])).is_none());
+        assert!(parse_polygon(&json!({ "points": [] })).is_none());
+    }
+
+    #[test]
+    fn test_overlay() {
+        let outline = Outline { room_id: uuid::Uuid::nil(), label: "R&D <lab>".to_string(), points: vec![(0.0, 0.0), (30.0, 0.0), (30.0, 30.0), (0.0, 30.0)] };
+        let svg = overlay_svg(100.0, 50.0, None, &[outline]);
+        assert!(svg.contains("viewBox=\"0 0 100 50\""));
+        assert!(svg.contains("points=\"0,0 30,0 30,30 0,30\""));
+        assert!(svg.contains("x=\"15\" y=\"15\""));
+        assert!(svg.contains("R&amp;D &lt;lab&gt;"));
+        assert!(!svg.contains("<image"));
+    }
+}
@@ -0,0 +1,119 @@
+// Small geographic helpers for asset positions and footprints, all coordinates are WGS84 degrees.
+
+use serde_json::{json, Value};
+
+/// Mean earth radius in metres, as used by the haversine formula.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Check a latitude/longitude pair for valid ranges.
+pub fn valid_position(latitude: f64, longitude: f64) -> bool {
+    (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude)
+}
+
+/// Parse a position given as `lat,lon`, e.g. `49.0069,8.4037`.
+/// Return None if the string is not a valid position.
+pub fn parse_position(input: &str) -> Option<(f64, f64)> {
+    let (lat, lon) = input.split_once(',')?;
+    let (lat, lon) = (lat.trim().parse::<f64>().ok()?, lon.trim().parse::<f64>().ok()?);
+    if valid_position(lat, lon) { Some((lat, lon)) } else { None }
+}
+
+/// Great-circle distance between two positions in metres (haversine formula).
+pub fn distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, lon2) = (to.0.to_radians(), to.1.to_radians());
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// GeoJSON Point geometry for a position, note that GeoJSON puts the longitude first.
+pub fn point(latitude: f64, longitude: f64) -> Value {
+    json!({ "type": "Point", "coordinates": [longitude, latitude] })
+}
+
+/// Read the position from a GeoJSON Point geometry.
+/// Return None if the geometry is not a valid point.
+pub fn point_position(geometry: &Value) -> Option<(f64, f64)> {
+    if geometry.get("type")?.as_str()? != "Point" {
+        return None;
+    }
+    let coordinates = geometry.get("coordinates")?.as_array()?;
+    let (lon, lat) = (coordinates.first()?.as_f64()?, coordinates.get(1)?.as_f64()?);
+    if valid_position(lat, lon) { Some((lat, lon)) } else { None }
+}
+
+/// Check that a footprint is a GeoJSON Polygon or MultiPolygon geometry
+/// whose rings are closed, have at least four positions, and stay within range.
+pub fn valid_footprint(geometry: &Value) -> bool {
+    let coordinates = match geometry.get("coordinates") {
+        Some(coordinates) => coordinates,
+        None => return false
+    };
+    match geometry.get("type").and_then(Value::as_str) {
+        Some("Polygon") => valid_polygon(coordinates),
+        Some("MultiPolygon") => coordinates.as_array().is_some_and(|polygons| !polygons.is_empty() && polygons.iter().all(valid_polygon)),
+        _ => false
+    }
+}
+
+fn valid_polygon(rings: &Value) -> bool {
+    rings.as_array().is_some_and(|rings| !rings.is_empty() && rings.iter().all(valid_ring))
+}
+
+fn valid_ring(ring: &Value) -> bool {
+    let positions : Option<Vec<(f64, f64)>> = match ring.as_array() {
+        Some(ring) => ring.iter().map(|position| {
+            let position = position.as_array()?;
+            let (lon, lat) = (position.first()?.as_f64()?, position.get(1)?.as_f64()?);
+            if valid_position(lat, lon) { Some((lat, lon)) } else { None }
+        }).collect(),
+        None => None
+    };
+    positions.is_some_and(|positions| positions.len() >= 4 && positions.first() == positions.last())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_position() {
+        assert_eq!(parse_position("49.0069,8.4037"), Some((49.0069, 8.4037)));
+        assert_eq!(parse_position(" -33.9 , 151.2 "), Some((-33.9, 151.2)));
+        assert!(parse_position("91,0").is_none());
+        assert!(parse_position("49.0069").is_none());
+        assert!(parse_position("north,east").is_none());
+    }
+
+    #[test]
+    fn test_distance() {
+        // Karlsruhe to Stuttgart is roughly 62 km as the crow flies
+        let karlsruhe = (49.0069, 8.4037);
+        let stuttgart = (48.7758, 9.1829);
+        let km = distance(karlsruhe, stuttgart) / 1000.0;
+        assert!((60.0..64.0).contains(&km), "got {} km", km);
+        assert_eq!(distance(karlsruhe, karlsruhe), 0.0);
+    }
+
+    #[test]
+    fn test_point() {
+        let geometry = point(49.0069, 8.4037);
+        assert_eq!(geometry["coordinates"][0], 8.4037);
+        assert_eq!(point_position(&geometry), Some((49.0069, 8.4037)));
+        assert!(point_position(&json!({ "type": "Point", "coordinates": [8.4] })).is_none());
+        assert!(point_position(&json!({ "type": "Polygon", "coordinates": [8.4, 49.0] })).is_none());
+    }
+
+    #[test]
+    fn test_footprint() {
+        let square = json!({ "type": "Polygon", "coordinates": [[[8.40, 49.00], [8.41, 49.00], [8.41, 49.01], [8.40, 49.00]]] });
+        assert!(valid_footprint(&square));
+        let multi = json!({ "type": "MultiPolygon", "coordinates": [square["coordinates"].clone()] });
+        assert!(valid_footprint(&multi));
+
+        let open = json!({ "type": "Polygon", "coordinates": [[[8.40, 49.00], [8.41, 49.00], [8.41, 49.01], [8.42, 49.02]]] });
+        assert!(!valid_footprint(&open));
+        assert!(!valid_footprint(&json!({ "type": "Polygon", "coordinates": [] })));
+        assert!(!valid_footprint(&point(49.0, 8.4)));
+    }
+}
@@ -0,0 +1,222 @@
+// Parsing of asset hierarchies from CSV, as handed over by facility management.
+// Every row names a building and optionally a storey and a room in it, plus their attributes:
+//
+//   building,address,storey,level,room,capacity,type
+//   Main Building,Hauptstraße 1,Ground Floor,0,A.001,30,seminar
+//
+// Parents are matched by name, so the same building and storey can appear in many rows.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::api::util::{validate_building_attributes, validate_room_attributes};
+use crate::db::crud::audit_crud::Audit;
+use crate::db::crud::import_crud::{import_rows, ImportReport};
+use crate::db::models::{BuildingAttributes, RoomAttributes, StoreyAttributes};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+/// What to do with assets that already exist.
+/// `Upsert` updates them with the values of the row, `CreateOnly` rejects rows that would change them.
+pub enum ImportPolicy {
+    Upsert,
+    CreateOnly
+}
+
+/// One row of an import, with the names of its assets and their attributes.
+/// `line` is the line in the CSV file, counting the header as line 1.
+pub struct ImportRow {
+    pub line: usize,
+    pub building: String,
+    pub address: Option<String>,
+    pub building_attributes: BuildingAttributes,
+    pub storey: Option<String>,
+    pub storey_attributes: StoreyAttributes,
+    pub room: Option<String>,
+    pub room_attributes: RoomAttributes
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+/// Problem with a single row of an import.
+pub struct RowError {
+    pub line: usize,
+    pub message: String
+}
+
+/// Column names that are accepted for convenience, mapped to the name used here.
+const ALIASES: [(&str, &str); 4] = [("capacity", "seated_capacity"), ("type", "room_type"), ("lat", "latitude"), ("lon", "longitude")];
+
+/// Columns that can appear in an import file.
+const COLUMNS: [&str; 20] = [
+    "building", "address", "street", "house_number", "postal_code", "city", "country", "latitude", "longitude",
+    "storey", "level", "elevation", "display_order",
+    "room", "seated_capacity", "standing_capacity", "room_type", "area", "wheelchair_accessible", "hearing_loop"
+];
+
+/// Parse, check and import a CSV file of buildings, storeys and rooms.
+/// Shared by the import endpoint and the `import` command, changes are audited with `audit`.
+/// Returns an error if the file as a whole cannot be read, problems with single rows are part of the report.
+pub fn run_import(audit: &Audit, data: &[u8], policy: ImportPolicy, dry_run: bool) -> Result<ImportReport, String> {
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for row in parse_csv(data)? {
+        let row = row.and_then(|row| {
+            let problem = validate_building_attributes(&row.building_attributes).or(validate_room_attributes(&row.room_attributes));
+            match problem {
+                Some(message) => Err(RowError { line: row.line, message: message.to_string() }),
+                None => Ok(row)
+            }
+        });
+        match row {
+            Ok(row) => rows.push(row),
+            Err(e) => errors.push(e)
+        }
+    }
+
+    // rows that could not be parsed still get checked against the database, so the report is complete
+    let mut report = import_rows(audit, &rows, policy, dry_run || !errors.is_empty()).ok_or("import failed")?;
+    report.rows += errors.len();
+    report.dry_run = dry_run;
+    report.errors.append(&mut errors);
+    report.errors.sort_by_key(|e| e.line);
+    Ok(report)
+}
+
+/// Parse a CSV file with a header row into import rows.
+/// Return an error if the file itself is unusable, e.g. an unknown column or no `building` column,
+/// problems with single rows are returned in place of those rows.
+pub fn parse_csv(data: &[u8]) -> Result<Vec<Result<ImportRow, RowError>>, String> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).flexible(true).from_reader(data);
+
+    let headers : Vec<String> = reader.headers().map_err(|e| format!("invalid header row: {}", e))?
+        .iter()
+        .map(|header| {
+            let header = header.to_lowercase().replace([' ', '-'], "_");
+            ALIASES.iter().find(|(alias, _)| *alias == header).map(|(_, column)| column.to_string()).unwrap_or(header)
+        })
+        .collect();
+    if let Some(unknown) = headers.iter().find(|header| !COLUMNS.contains(&header.as_str())) {
+        return Err(format!("unknown column {}", unknown));
+    }
+    if !headers.iter().any(|header| header == "building") {
+        return Err("missing column building".to_string());
+    }
+
+    let mut rows = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let line = index + 2;
+        let row = match record {
+            Ok(record) => {
+                let values : HashMap<&str, &str> = headers.iter().map(String::as_str)
+                    .zip(record.iter())
+                    .filter(|(_, value)| !value.is_empty())
+                    .collect();
+                parse_row(line, &values).map_err(|message| RowError { line, message })
+            },
+            Err(e) => Err(RowError { line, message: e.to_string() })
+        };
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn parse_row(line: usize, values: &HashMap<&str, &str>) -> Result<ImportRow, String> {
+    let text = |column: &str| values.get(column).map(|value| value.to_string());
+
+    let building = text("building").ok_or("building name missing")?;
+    let storey = text("storey");
+    let room = text("room");
+    if room.is_some() && storey.is_none() {
+        return Err("room without storey".to_string());
+    }
+
+    Ok(ImportRow {
+        line,
+        building,
+        address: text("address"),
+        building_attributes: BuildingAttributes {
+            street: text("street").map(Some),
+            house_number: text("house_number").map(Some),
+            postal_code: text("postal_code").map(Some),
+            city: text("city").map(Some),
+            country: text("country").map(Some),
+            latitude: number(values, "latitude")?.map(Some),
+            longitude: number(values, "longitude")?.map(Some),
+            footprint: None
+        },
+        storey,
+        storey_attributes: StoreyAttributes {
+            level: number(values, "level")?.map(Some),
+            elevation: number(values, "elevation")?.map(Some),
+            display_order: number(values, "display_order")?.map(Some)
+        },
+        room,
+        room_attributes: RoomAttributes {
+            seated_capacity: number(values, "seated_capacity")?.map(Some),
+            standing_capacity: number(values, "standing_capacity")?.map(Some),
+            room_type: text("room_type").map(Some),
+            area: number(values, "area")?.map(Some),
+            wheelchair_accessible: flag(values, "wheelchair_accessible")?.map(Some),
+            hearing_loop: flag(values, "hearing_loop")?.map(Some),
+            ..Default::default()
+        }
+    })
+}
+
+fn number<T: std::str::FromStr>(values: &HashMap<&str, &str>, column: &str) -> Result<Option<T>, String> {
+    match values.get(column) {
+        Some(value) => value.replace(',', ".").parse().map(Some).map_err(|_| format!("{} is not a number: {}", column, value)),
+        None => Ok(None)
+    }
+}
+
+/// Spreadsheets are not very consistent about booleans, accept the usual spellings.
+fn flag(values: &HashMap<&str, &str>, column: &str) -> Result<Option<bool>, String> {
+    match values.get(column).map(|value| value.to_lowercase()) {
+        Some(value) => match value.as_str() {
+            "true" | "yes" | "y" | "1" | "x" => Ok(Some(true)),
+            "false" | "no" | "n" | "0" => Ok(Some(false)),
+            _ => Err(format!("{} is not yes or no: {}", column, value))
+        },
+        None => Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rows() {
+        let csv = "Building,Address,Storey,Level,Room,Capacity,Type,Wheelchair accessible\n\
+                   Main,Hauptstraße 1,Ground Floor,0,A.001,30,seminar,yes\n\
+                   Main,,Ground Floor,0,A.002,\"12,5\",meeting,\n\
+                   Main,,,,B.001,,,\n\
+                   Annex,Nebenstraße 2,,,,,,";
+        let rows = parse_csv(csv.as_bytes()).unwrap();
+        assert_eq!(rows.len(), 4);
+
+        let first = rows[0].as_ref().unwrap();
+        assert_eq!(first.line, 2);
+        assert_eq!(first.building, "Main");
+        assert_eq!(first.storey_attributes.level, Some(Some(0)));
+        assert_eq!(first.room_attributes.seated_capacity, Some(Some(30)));
+        assert_eq!(first.room_attributes.room_type, Some(Some("seminar".to_string())));
+        assert_eq!(first.room_attributes.wheelchair_accessible, Some(Some(true)));
+
+        assert_eq!(rows[1].as_ref().err().unwrap().message, "seated_capacity is not a number: 12,5");
+        assert_eq!(rows[2].as_ref().err().unwrap().message, "room without storey");
+
+        let last = rows[3].as_ref().unwrap();
+        assert!(last.storey.is_none() && last.room.is_none());
+        assert_eq!(last.address.as_deref(), Some("Nebenstraße 2"));
+    }
+
+    #[test]
+    fn test_bad_header() {
+        assert!(parse_csv(b"building,colour\nMain,red").is_err());
+        assert!(parse_csv(b"storey,room\nGround Floor,A.001").is_err());
+    }
+}
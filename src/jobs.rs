@@ -0,0 +1,42 @@
+use log::warn;
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use crate::dbconn::{connection, DbConnection};
+
+pub mod changes;
+pub mod outbox;
+pub mod purge;
+pub mod room_moves;
+pub mod webhooks;
+
+/// Longest wait before a background job asks for a database connection again.
+const MAX_DATABASE_BACKOFF_SECS: u64 = 60;
+
+/// Read a numeric setting of a background job, falling back to the default if it is missing or invalid.
+pub(crate) fn env_or_default(key: &str, default: u64) -> u64 {
+    match env::var(key) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!("invalid value {} for {}, using default {}", value, key, default);
+            default
+        }),
+        Err(_) => default
+    }
+}
+
+/// Get a database connection for a background job, waiting with exponential backoff while there is none,
+/// so the job carries on after the database was unavailable instead of dying with it.
+pub(crate) fn database_connection(job: &str) -> DbConnection {
+    let mut backoff_secs = 1;
+    loop {
+        match connection() {
+            Ok(conn) => return conn,
+            Err(e) => {
+                warn!("{} cannot get a database connection, retrying in {}s: {}", job, backoff_secs, e);
+                thread::sleep(Duration::from_secs(backoff_secs));
+                backoff_secs = (backoff_secs * 2).min(MAX_DATABASE_BACKOFF_SECS);
+            }
+        }
+    }
+}
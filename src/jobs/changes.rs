@@ -0,0 +1,110 @@
+use log::{debug, info, warn};
+use postgres::fallible_iterator::FallibleIterator;
+use postgres::{Client, NoTls};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::cache::invalidate;
+use crate::db::crud::changes_crud::{changes_after, latest_position, unsettled_changes_after};
+use crate::db::models::StreamPosition;
+use crate::dbconn::database_url;
+use crate::events::changes;
+use crate::jobs::database_connection;
+
+/// Changes loaded at once.
+const PAGE: i64 = 1000;
+/// How long to wait for announcements before checking the connection.
+const IDLE_CHECK: Duration = Duration::from_secs(30);
+/// How often to look again while changes are waiting for older transactions to end.
+const SETTLE_CHECK: Duration = Duration::from_millis(200);
+/// Longest wait before connecting again.
+const MAX_BACKOFF_SECS: u64 = 60;
+/// Name of the job in the log.
+const JOB: &str = "change listener";
+
+/// Start a background thread that listens for the changes announced by the `asset_changes` triggers
+/// and passes them on to the open event streams. If the connection is lost, it connects again
+/// and passes on the changes made meanwhile.
+/// An announcement only says that there are new changes: they are passed on once they are settled
+/// (see `changes_crud`), so one that commits late is not skipped, and all streams get them in the same order.
+pub fn spawn_change_listener() {
+
+    info!("listening for asset changes");
+
+    thread::spawn(|| {
+        let mut position = latest_position(&database_connection(JOB));
+        let mut backoff_secs = 1;
+
+        loop {
+            let mut client = match connect() {
+                Ok(client) => client,
+                Err(e) => {
+                    warn!("cannot listen for asset changes, retrying in {}s: {}", backoff_secs, e);
+                    thread::sleep(Duration::from_secs(backoff_secs));
+                    backoff_secs = (backoff_secs * 2).min(MAX_BACKOFF_SECS);
+                    continue;
+                }
+            };
+            backoff_secs = 1;
+
+            if let Err(e) = listen(&mut client, &mut position) {
+                warn!("lost connection listening for asset changes: {}", e);
+            }
+        }
+    });
+
+}
+
+/// Connect and start listening for the announcements of the triggers.
+fn connect() -> Result<Client, postgres::Error> {
+    let mut client = Client::connect(&database_url(), NoTls)?;
+    client.batch_execute("LISTEN asset_changes")?;
+    Ok(client)
+}
+
+/// Pass on changes until the connection fails, starting with those made while nobody was listening.
+fn listen(client: &mut Client, position: &mut StreamPosition) -> Result<(), postgres::Error> {
+    let mut unsettled = pass_on_settled(position);
+    loop {
+        let announced = wait_for_announcements(client, if unsettled { SETTLE_CHECK } else { IDLE_CHECK })?;
+        if announced || unsettled {
+            unsettled = pass_on_settled(position);
+        } else {
+            // nothing for a while, make sure the connection is still there
+            client.is_valid(IDLE_CHECK)?;
+        }
+    }
+}
+
+/// Wait up to `timeout` for announcements and take all that arrived, return whether there were any.
+fn wait_for_announcements(client: &mut Client, timeout: Duration) -> Result<bool, postgres::Error> {
+    let mut notifications = client.notifications();
+    if notifications.timeout_iter(timeout).next()?.is_none() {
+        return Ok(false);
+    }
+    let mut pending = notifications.iter();
+    while pending.next()?.is_some() {}
+    Ok(true)
+}
+
+/// Send the settled changes after `position` to the open streams and move it past them.
+/// Return whether there are changes that have to wait for older transactions.
+fn pass_on_settled(position: &mut StreamPosition) -> bool {
+    let conn = database_connection(JOB);
+    loop {
+        let settled = changes_after(&conn, *position, None, PAGE);
+        let done = (settled.len() as i64) < PAGE;
+        for change in settled {
+            debug!("{} {} {}", change.entity_type, change.entity_id, change.action);
+            *position = change.position();
+            // covers changes made by other instances or directly in the database
+            invalidate(&change.entity_type, change.entity_id);
+            // fails only if no stream is open
+            let _ = changes().send(Arc::new(change));
+        }
+        if done {
+            return unsettled_changes_after(&conn, *position);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use log::{debug, info, warn};
+use std::thread;
+use std::time::Duration;
+
+use crate::db::crud::outbox_crud::relay_events;
+use crate::jobs::env_or_default;
+use crate::outbox::{enabled, publisher_from_env, Publisher};
+
+/// Events published per round, the next round starts right away if there were this many.
+const EVENTS_PER_ROUND: i64 = 100;
+/// Longest wait before connecting to the broker again.
+const MAX_BACKOFF_MS: u64 = 60_000;
+
+/// Start a background thread that publishes the events in the outbox, if a publisher is configured.
+/// The outbox is checked every `OUTBOX_POLL_INTERVAL_MS` (default 1000). If the broker is unavailable,
+/// the relay connects again with exponential backoff and the events wait in the outbox meanwhile.
+pub fn spawn_outbox_relay() {
+
+    if !enabled() {
+        return;
+    }
+    let interval_ms = env_or_default("OUTBOX_POLL_INTERVAL_MS", 1000);
+
+    info!("relaying outbox events every {}ms", interval_ms);
+
+    thread::spawn(move || {
+        let mut publisher : Option<Box<dyn Publisher>> = None;
+        let mut backoff_ms = interval_ms;
+
+        loop {
+            let connected = match publisher.as_mut() {
+                Some(connected) => connected,
+                None => match publisher_from_env() {
+                    Ok(connected) => {
+                        info!("outbox relay connected");
+                        backoff_ms = interval_ms;
+                        publisher.insert(connected)
+                    },
+                    Err(e) => {
+                        warn!("outbox relay cannot connect, retrying in {}ms: {}", backoff_ms, e);
+                        thread::sleep(Duration::from_millis(backoff_ms));
+                        backoff_ms = (backoff_ms * 2).min(MAX_BACKOFF_MS);
+                        continue;
+                    }
+                }
+            };
+
+            match relay_events(connected.as_mut(), EVENTS_PER_ROUND) {
+                Ok(published) => {
+                    if published > 0 {
+                        debug!("published {} outbox events", published);
+                    }
+                    if (published as i64) < EVENTS_PER_ROUND {
+                        thread::sleep(Duration::from_millis(interval_ms));
+                    }
+                },
+                Err(e) => {
+                    // the events that were not published stay in the outbox, start over with a new connection
+                    warn!("publishing outbox events failed: {}", e);
+                    publisher = None;
+                }
+            }
+        }
+    });
+
+}
@@ -0,0 +1,90 @@
+use chrono::{Duration, Utc};
+use diesel::PgConnection;
+
+use log::{info, warn};
+use std::fs;
+use std::thread;
+
+use crate::cache::invalidate_all;
+use crate::db::crud::buildings_crud::purge_deleted_buildings;
+use crate::db::crud::storeys_crud::purge_deleted_storeys;
+use crate::db::crud::rooms_crud::purge_deleted_rooms;
+use crate::db::crud::floor_plans_crud::floor_plan_paths;
+use crate::db::crud::changes_crud::purge_changes;
+use crate::floorplan;
+use crate::jobs::{database_connection, env_or_default};
+
+/// Floor plan files younger than this are left alone, their floor plan may be committed but not have been read yet.
+const ORPHAN_GRACE: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Start a background thread that permanently removes assets from the trash
+/// once they have been deleted for longer than `TRASH_RETENTION_DAYS` (default 30).
+/// The trash is checked every `TRASH_PURGE_INTERVAL_SECS` seconds (default 3600).
+/// Recorded changes for the event stream are kept for `EVENTS_RETENTION_HOURS` (default 24).
+pub fn spawn_trash_purge() {
+
+    let retention_days = env_or_default("TRASH_RETENTION_DAYS", 30);
+    let interval_secs = env_or_default("TRASH_PURGE_INTERVAL_SECS", 3600);
+    let events_retention_hours = env_or_default("EVENTS_RETENTION_HOURS", 24);
+
+    info!("purging trash every {}s, retention period is {} days", interval_secs, retention_days);
+
+    thread::spawn(move || loop {
+        let conn = database_connection("trash purge");
+        let cutoff = Utc::now() - Duration::days(retention_days as i64);
+
+        // children first, otherwise their parents are still referenced and stay in the trash
+        let rooms = purge_deleted_rooms(&conn, cutoff);
+        let storeys = purge_deleted_storeys(&conn, cutoff);
+        let buildings = purge_deleted_buildings(&conn, cutoff);
+        info!("purged {} buildings, {} storeys and {} rooms from trash", buildings.len(), storeys.len(), rooms.len());
+        // lists that include the trash still have them
+        let purged = buildings.iter().map(|id| ("building", *id))
+            .chain(storeys.iter().map(|id| ("storey", *id)))
+            .chain(rooms.iter().map(|id| ("room", *id)));
+        invalidate_all(purged);
+        if !storeys.is_empty() {
+            remove_orphaned_floor_plans(&conn);
+        }
+
+        let changes = purge_changes(&conn, Utc::now() - Duration::hours(events_retention_hours as i64));
+        info!("purged {} recorded changes", changes);
+        drop(conn);
+
+        thread::sleep(std::time::Duration::from_secs(interval_secs));
+    });
+
+}
+
+/// Floor plans in the database go away with their storey, files in the floor plan directory have to be removed here.
+/// Only files named like floor plans (`<UUID>.svg` or `.png`) are touched, and only once they are older
+/// than `ORPHAN_GRACE`: a file is given its name after its floor plan is committed, which can happen
+/// between reading the floor plans and looking at the files. Files that are not committed yet have a temporary name,
+/// those still there after `ORPHAN_GRACE` were left behind by an instance that stopped and are removed as well.
+fn remove_orphaned_floor_plans(conn: &PgConnection) {
+    let dir = match floorplan::plan_dir() {
+        Some(dir) => dir,
+        None => return
+    };
+    let referenced = floor_plan_paths(conn);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_plan = name.rsplit_once('.').is_some_and(|(stem, ext)| {
+            uuid::Uuid::parse_str(stem).is_ok() && (ext == "svg" || ext == "png")
+        });
+        let is_staged = name.starts_with('.') && name.ends_with(".upload");
+        let is_old = entry.metadata().and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age > ORPHAN_GRACE));
+        if ((is_plan && !referenced.contains(&name)) || is_staged) && is_old {
+            match fs::remove_file(entry.path()) {
+                Ok(_) => info!("removed unused floor plan file {}", name),
+                Err(e) => warn!("could not remove floor plan file {}: {}", name, e)
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use actix_web::web;
+use chrono::{Duration, Utc};
+
+use log::{debug, info, warn};
+
+use crate::db::crud::room_moves_crud::{claim_due_room_moves, room_move_failed, room_moves_notified};
+use crate::db::models::{RoomMove, RoomMoveNotification};
+use crate::jobs::{database_connection, env_or_default};
+use crate::reservations::{self, ReservationsError};
+use crate::webhooks::retry_delay;
+
+/// Room moves sent per request, the next round starts right away if there were this many.
+const MOVES_PER_ROUND: i64 = 100;
+/// How long claimed notifications are held back from other instances, longer than a request with all its retries takes.
+const LEASE_SECS: i64 = 300;
+/// Name of the job in the log.
+const JOB: &str = "room move notifier";
+
+/// Why a notification did not arrive.
+struct Failure {
+    error: String,
+    /// The service rejected it, sending it again will not help.
+    permanent: bool
+}
+
+impl From<ReservationsError> for Failure {
+    fn from(e: ReservationsError) -> Self {
+        let permanent = matches!(e, ReservationsError::BadResponse(_));
+        Failure { error: e.to_string(), permanent }
+    }
+}
+
+/// Start a background task that tells the reservations service about rooms that moved to another building.
+/// The moves are queued in the transaction of the move, so none is lost if the service is down.
+/// Moves of a room are sent in the order they happened, a move waits while an earlier one of its room is pending.
+/// Failed notifications are retried with exponential backoff, after `ROOM_MOVES_MAX_ATTEMPTS` (default 8) attempts,
+/// or right away if the service rejects them, they become dead letters.
+/// New moves are looked for every `ROOM_MOVES_POLL_INTERVAL_MS` (default 1000).
+pub fn spawn_room_move_notifier() {
+
+    let max_attempts = env_or_default("ROOM_MOVES_MAX_ATTEMPTS", 8) as i32;
+    let interval_ms = env_or_default("ROOM_MOVES_POLL_INTERVAL_MS", 1000);
+
+    info!("notifying the reservations service about room moves every {}ms, giving up after {} attempts", interval_ms, max_attempts);
+
+    // the reservations client is async, so this runs as a task rather than on a thread of its own;
+    // the database is used on the blocking pool, where waiting for a connection does not hold up requests
+    actix_web::rt::spawn(async move {
+        loop {
+            let due = web::block(|| claim_due_room_moves(&database_connection(JOB), MOVES_PER_ROUND, Duration::seconds(LEASE_SECS)))
+                .await
+                .unwrap_or_default();
+            let claimed = due.len() as i64;
+            if !due.is_empty() {
+                let outcomes = notify(due).await;
+                let recorded = web::block(move || record(outcomes, max_attempts)).await;
+                if let Err(e) = recorded {
+                    warn!("could not record notified room moves, they are sent again after the lease: {}", e);
+                }
+            }
+            if claimed < MOVES_PER_ROUND {
+                actix_web::rt::time::sleep(std::time::Duration::from_millis(interval_ms)).await;
+            }
+        }
+    });
+
+}
+
+/// Send the moves in one request. If the service rejects it, each move is sent on its own,
+/// so only the moves it rejects are given up on.
+async fn notify(due: Vec<RoomMoveNotification>) -> Vec<(RoomMoveNotification, Result<(), Failure>)> {
+    let moves : Vec<RoomMove> = due.iter().map(|notification| notification.room_move()).collect();
+    let error = match reservations::client().notify_room_moves(&moves, None).await {
+        Ok(()) => return due.into_iter().map(|notification| (notification, Ok(()))).collect(),
+        Err(e) => Failure::from(e)
+    };
+    if !error.permanent || due.len() == 1 {
+        return due.into_iter()
+            .map(|notification| (notification, Err(Failure { error: error.error.clone(), permanent: error.permanent })))
+            .collect();
+    }
+
+    let mut outcomes = Vec::with_capacity(due.len());
+    for notification in due {
+        let result = reservations::client().notify_room_moves(&[notification.room_move()], None).await.map_err(Failure::from);
+        outcomes.push((notification, result));
+    }
+    outcomes
+}
+
+/// Forget the notifications that arrived, retry the others or give up on them.
+fn record(outcomes: Vec<(RoomMoveNotification, Result<(), Failure>)>, max_attempts: i32) {
+    let conn = database_connection(JOB);
+    let mut notified = Vec::new();
+    for (notification, result) in outcomes {
+        let failure = match result {
+            Ok(()) => {
+                notified.push(notification.id);
+                continue;
+            },
+            Err(failure) => failure
+        };
+        let attempts = notification.attempts + 1;
+        if failure.permanent || attempts >= max_attempts {
+            warn!("giving up on notifying the move of room {} after {} attempts: {}", notification.room_id, attempts, failure.error);
+            room_move_failed(&conn, notification.id, attempts, &failure.error, None);
+        } else {
+            let delay = retry_delay(attempts);
+            debug!("notifying the move of room {} failed ({}), retrying in {}s", notification.room_id, failure.error, delay.num_seconds());
+            room_move_failed(&conn, notification.id, attempts, &failure.error, Some(Utc::now() + delay));
+        }
+    }
+    if !notified.is_empty() {
+        room_moves_notified(&conn, &notified);
+        debug!("notified reservations service about {} moved rooms", notified.len());
+    }
+}
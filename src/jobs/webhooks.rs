@@ -0,0 +1,94 @@
+use chrono::{Duration, Utc};
+
+use log::{debug, info, warn};
+use std::thread;
+
+use crate::db::crud::webhooks_crud::{claim_due_deliveries, delivery_failed, delivery_succeeded};
+use crate::db::models::{Webhook, WebhookDelivery};
+use crate::jobs::{database_connection, env_or_default};
+use crate::webhooks::{allowed_hosts, check_target, retry_delay, sign, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER};
+
+/// Deliveries sent per round, the next round starts right away if there were this many.
+const DELIVERIES_PER_ROUND: i64 = 50;
+/// Name of the job in the log.
+const JOB: &str = "webhook delivery";
+
+/// Start a background thread that sends queued webhook deliveries.
+/// Failed deliveries are retried with exponential backoff, after `WEBHOOK_MAX_ATTEMPTS` (default 8)
+/// attempts they become dead letters. Each attempt times out after `WEBHOOK_TIMEOUT_MS` (default 5000),
+/// new deliveries are looked for every `WEBHOOK_POLL_INTERVAL_MS` (default 1000).
+/// Redirects are not followed, they could lead to an address webhooks must not be sent to.
+pub fn spawn_webhook_delivery() {
+
+    let max_attempts = env_or_default("WEBHOOK_MAX_ATTEMPTS", 8) as i32;
+    let timeout_ms = env_or_default("WEBHOOK_TIMEOUT_MS", 5000);
+    let interval_ms = env_or_default("WEBHOOK_POLL_INTERVAL_MS", 1000);
+
+    info!("delivering webhooks every {}ms, giving up after {} attempts", interval_ms, max_attempts);
+
+    thread::spawn(move || {
+        let timeout = std::time::Duration::from_millis(timeout_ms);
+        let allowed = allowed_hosts();
+        // a delivery is only picked up again if this thread died while sending its round,
+        // which takes one timeout per delivery at most, and a little for the rest
+        let lease = Duration::milliseconds(timeout_ms as i64) * (DELIVERIES_PER_ROUND as i32 + 1);
+
+        loop {
+            let due = claim_due_deliveries(&database_connection(JOB), DELIVERIES_PER_ROUND, lease);
+            for (delivery, webhook) in &due {
+                deliver(timeout, delivery, webhook, max_attempts, &allowed);
+            }
+            if (due.len() as i64) < DELIVERIES_PER_ROUND {
+                thread::sleep(std::time::Duration::from_millis(interval_ms));
+            }
+        }
+    });
+
+}
+
+/// Send a single delivery and record the outcome.
+fn deliver(timeout: std::time::Duration, delivery: &WebhookDelivery, webhook: &Webhook, max_attempts: i32, allowed_hosts: &[String]) {
+    let error = match send(timeout, delivery, webhook, allowed_hosts) {
+        Ok(()) => {
+            debug!("delivered {} event to webhook {}", delivery.event_type, webhook.id);
+            delivery_succeeded(&database_connection(JOB), delivery.id);
+            return;
+        },
+        Err(error) => error
+    };
+
+    let attempts = delivery.attempts + 1;
+    if attempts >= max_attempts {
+        warn!("giving up on delivery {} to webhook {} after {} attempts: {}", delivery.id, webhook.id, attempts, error);
+        delivery_failed(&database_connection(JOB), delivery.id, attempts, error, None);
+    } else {
+        let delay = retry_delay(attempts);
+        debug!("delivery {} to webhook {} failed ({}), retrying in {}s", delivery.id, webhook.id, error, delay.num_seconds());
+        delivery_failed(&database_connection(JOB), delivery.id, attempts, error, Some(Utc::now() + delay));
+    }
+}
+
+/// Send a delivery, Err is why it did not arrive.
+/// The client connects to the address the target check looked up, not to whatever the host resolves to by then.
+fn send(timeout: std::time::Duration, delivery: &WebhookDelivery, webhook: &Webhook, allowed_hosts: &[String]) -> Result<(), String> {
+    let mut builder = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none());
+    if let Some((host, address)) = check_target(&webhook.url, allowed_hosts)? {
+        builder = builder.resolve(&host, address);
+    }
+    let client = builder.build().map_err(|e| e.to_string())?;
+    let body = delivery.payload.to_string();
+    let resp = client.post(&webhook.url)
+        .header("Content-Type", "application/json")
+        .header(EVENT_HEADER, &delivery.event_type)
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .header(SIGNATURE_HEADER, format!("sha256={}", sign(&webhook.secret, body.as_bytes())))
+        .body(body)
+        .send()
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("status {}", resp.status()));
+    }
+    Ok(())
+}
@@ -1,11 +1,29 @@
+// diesel 1.4 derives and `table!` expand to impls nested in consts, newer compilers complain about that
+#![allow(non_local_definitions)]
+
 // DO NOT CHANGE THE ORDER OF THESE TWO IMPORTS ON PENALTY OF DEATH
 extern crate openssl;
 #[macro_use]
 extern crate diesel;
 // THE DOCKER BUILD BREAKS IF YOU DON'T DO THIS
+#[macro_use]
+extern crate diesel_migrations;
 
 mod db;
 mod api;
+mod jobs;
+mod geo;
+mod floorplan;
+mod import;
+mod cli;
+mod export;
+mod batch;
+mod reservations;
+mod webhooks;
+mod outbox;
+mod events;
+mod cache;
+mod openapi;
 
 use dotenv::dotenv;
 use env_logger::Env;
@@ -15,10 +33,29 @@ use crate::db::dbconn;
 use crate::api::buildings_api::*;
 use crate::api::rooms_api::*;
 use crate::api::storeys_api::*;
+use crate::api::equipment_api::*;
+use crate::api::trash_api::*;
+use crate::api::audit_api::*;
+use crate::api::geojson_api::*;
+use crate::api::floorplans_api::*;
+use crate::api::import_api::*;
+use crate::api::export_api::*;
+use crate::api::batch_api::*;
+use crate::api::moves_api::*;
+use crate::api::clone_api::*;
+use crate::api::webhooks_api::*;
+use crate::api::events_api::*;
+use crate::api::cache_api::*;
+use crate::api::openapi_api::*;
+use crate::jobs::purge::spawn_trash_purge;
+use crate::jobs::webhooks::spawn_webhook_delivery;
+use crate::jobs::outbox::spawn_outbox_relay;
+use crate::jobs::changes::spawn_change_listener;
+use crate::jobs::room_moves::spawn_room_move_notifier;
 
 use actix_web::{middleware::Logger, middleware::NormalizePath, web, middleware::DefaultHeaders, App, HttpServer};
 
-use std::io::{ErrorKind, Error};
+use std::io::Error;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -30,9 +67,37 @@ async fn main() -> std::io::Result<()> {
 
     // r2d2 will attempt to connect until postgres is up, don't let the error messages irritate you 
     info!("attempting to connect to database service...");
-    if dbconn::init().is_err() { return Err(Error::new(ErrorKind::Other, "could not connect to DB service")); }
+    let conn = match dbconn::init() {
+        Ok(conn) => conn,
+        Err(_) => return Err(Error::other("could not connect to DB service"))
+    };
     info!("database connection successful");
 
+    info!("applying database migrations...");
+    if dbconn::run_migrations(&conn).is_err() { return Err(Error::other("could not apply database migrations")); }
+    drop(conn);
+
+    // `biletado-assets import <file.csv>` imports assets from the command line instead of starting the service
+    let args : Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("import") {
+        return cli::import(&args[2..]);
+    }
+
+    // deleted assets stay restorable for a while, this cleans them up afterwards
+    spawn_trash_purge();
+
+    // changes are sent to webhook subscribers in the background, after they have been committed
+    spawn_webhook_delivery();
+
+    // events written to the outbox together with the changes are passed on to the message broker
+    spawn_outbox_relay();
+
+    // changes announced by the database triggers are passed on to the open event streams
+    spawn_change_listener();
+
+    // rooms that moved to another building are reported to the reservations service once the move is committed
+    spawn_room_move_notifier();
+
     // ...and here we go!
     info!("starting API service");
     HttpServer::new(|| {
@@ -47,6 +112,7 @@ async fn main() -> std::io::Result<()> {
                     .service(get_storeys_by_building)
                     .service(get_storey_by_id)
                     .service(get_rooms_by_storey)
+                    .service(get_available_rooms)
                     .service(get_room_by_id)
                     .service(add_building)
                     .service(update_building)
@@ -57,6 +123,44 @@ async fn main() -> std::io::Result<()> {
                     .service(add_room)
                     .service(update_room)
                     .service(delete_room)
+                    .service(restore_building)
+                    .service(restore_storey)
+                    .service(restore_room)
+                    .service(get_equipment_catalogue)
+                    .service(add_equipment)
+                    .service(update_equipment)
+                    .service(delete_equipment)
+                    .service(get_room_equipment)
+                    .service(add_room_equipment)
+                    .service(update_room_equipment)
+                    .service(delete_room_equipment)
+                    .service(get_building_history)
+                    .service(get_storey_history)
+                    .service(get_room_history)
+                    .service(get_trash)
+                    .service(get_audit_log)
+                    .service(import_geojson)
+                    .service(upload_floor_plan)
+                    .service(get_floor_plan)
+                    .service(delete_floor_plan_of_storey)
+                    .service(get_floor_plan_overlay)
+                    .service(import_assets)
+                    .service(get_export)
+                    .service(batch_assets)
+                    .service(move_storey)
+                    .service(move_room)
+                    .service(move_rooms_of_storey)
+                    .service(clone_storey)
+                    .service(clone_building)
+                    .service(get_webhook_subscriptions)
+                    .service(add_webhook)
+                    .service(get_webhook_dead_letters)
+                    .service(retry_webhook_dead_letter)
+                    .service(delete_webhook)
+                    .service(get_events)
+                    .service(get_cache_stats)
+                    .service(get_openapi)
+                    .configure(swagger_ui)
             )
     }).bind(("0.0.0.0", 9000))?.run().await // HAS to be 0.0.0.0 or docker won't let you connect
 }
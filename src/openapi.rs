@@ -0,0 +1,119 @@
+// OpenAPI 3 document of the assets API, generated from the handlers and models with utoipa.
+//
+// It covers every endpoint but the document itself: the buildings, storeys and rooms of the Biletado assets contract
+// and everything around them, from equipment to webhooks. Served at `GET /assets/openapi.json`, with the `swagger-ui` feature also browsable at `/assets/swagger-ui/index.html`.
+// `schemas/openapi.json` is the checked-in copy the client SDKs are generated from, the test below fails
+// when the handlers drift from it; `UPDATE_OPENAPI=1 cargo test openapi` writes the current document there.
+
+use serde::Serialize;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi, ToSchema};
+
+use crate::api::{
+    audit_api, batch_api, buildings_api, cache_api, clone_api, equipment_api, events_api, export_api, floorplans_api,
+    geojson_api, import_api, moves_api, rooms_api, storeys_api, trash_api, webhooks_api
+};
+use crate::api::cache_api::CacheReport;
+use crate::api::trash_api::Trash;
+use crate::api::util::ReservationPolicy;
+use crate::batch::{AssetType, BatchAction, BatchOperation, BatchReport, BatchRequest, OperationResult};
+use crate::cache::CacheStats;
+use crate::db::crud::import_crud::{ImportChange, ImportReport};
+use crate::db::models::*;
+use crate::import::{ImportPolicy, RowError};
+
+#[derive(Serialize, ToSchema)]
+/// Body of error responses.
+pub struct ErrorMessage {
+    pub message: String
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "biletado assets", description = "Buildings, storeys and rooms of the Biletado reservation system, with their equipment, floor plans and history."),
+    servers((url = "/assets")),
+    paths(
+        buildings_api::get_all_buildings, buildings_api::add_building, buildings_api::get_building_by_id,
+        buildings_api::update_building, buildings_api::delete_building, buildings_api::restore_building,
+        buildings_api::get_building_history,
+        storeys_api::get_storeys_by_building, storeys_api::add_storey, storeys_api::get_storey_by_id,
+        storeys_api::update_storey, storeys_api::delete_storey, storeys_api::restore_storey,
+        storeys_api::get_storey_history,
+        rooms_api::get_rooms_by_storey, rooms_api::get_available_rooms, rooms_api::add_room, rooms_api::get_room_by_id,
+        rooms_api::update_room, rooms_api::delete_room, rooms_api::restore_room, rooms_api::get_room_history,
+        clone_api::clone_building, clone_api::clone_storey,
+        moves_api::move_storey, moves_api::move_room, moves_api::move_rooms_of_storey,
+        equipment_api::get_equipment_catalogue, equipment_api::add_equipment, equipment_api::update_equipment,
+        equipment_api::delete_equipment, equipment_api::get_room_equipment, equipment_api::add_room_equipment,
+        equipment_api::update_room_equipment, equipment_api::delete_room_equipment,
+        floorplans_api::get_floor_plan, floorplans_api::upload_floor_plan, floorplans_api::delete_floor_plan_of_storey,
+        floorplans_api::get_floor_plan_overlay,
+        trash_api::get_trash,
+        audit_api::get_audit_log,
+        batch_api::batch_assets,
+        import_api::import_assets, export_api::get_export, geojson_api::import_geojson,
+        events_api::get_events,
+        webhooks_api::get_webhook_subscriptions, webhooks_api::add_webhook, webhooks_api::delete_webhook,
+        webhooks_api::get_webhook_dead_letters, webhooks_api::retry_webhook_dead_letter,
+        cache_api::get_cache_stats
+    ),
+    components(schemas(
+        Building, OptionalIDBuilding, BuildingAttributes, Storey, OptionalIDStorey, StoreyAttributes,
+        Room, OptionalIDRoom, RoomAttributes, AssetVersion, DeletionReport, ReservationPolicy, ErrorMessage,
+        CloneBuilding, CloneStorey, NamePattern, CloneMapping, MoveStorey, MoveRoom, MoveRooms,
+        Equipment, OptionalIDEquipment, RoomEquipment, OptionalIDRoomEquipment, FloorPlan, Trash, AuditEntry,
+        BatchRequest, BatchOperation, BatchAction, AssetType, BatchReport, OperationResult,
+        ImportPolicy, ImportReport, ImportChange, RowError, AssetChange,
+        Webhook, NewWebhook, WebhookDelivery, CacheReport, CacheStats
+    )),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "buildings"),
+        (name = "storeys"),
+        (name = "rooms"),
+        (name = "equipment"),
+        (name = "floor plans"),
+        (name = "trash"),
+        (name = "audit"),
+        (name = "batch"),
+        (name = "import"),
+        (name = "export"),
+        (name = "geojson"),
+        (name = "events"),
+        (name = "webhooks"),
+        (name = "cache")
+    )
+)]
+pub struct ApiDoc;
+
+/// The `bearer` scheme of the endpoints that need a Keycloak token.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("bearer", SecurityScheme::Http(
+            HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    const CHECKED_IN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/schemas/openapi.json");
+
+    #[test]
+    fn test_openapi_matches_checked_in_spec() {
+        let generated = ApiDoc::openapi().to_pretty_json().unwrap();
+        if env::var("UPDATE_OPENAPI").is_ok() {
+            fs::write(CHECKED_IN, format!("{}\n", generated)).unwrap();
+        }
+
+        let checked_in : serde_json::Value = serde_json::from_str(&fs::read_to_string(CHECKED_IN).unwrap()).unwrap();
+        let generated : serde_json::Value = serde_json::from_str(&generated).unwrap();
+        assert!(checked_in == generated, "schemas/openapi.json is out of date, run `UPDATE_OPENAPI=1 cargo test openapi` and review the diff");
+    }
+}
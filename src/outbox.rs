@@ -0,0 +1,236 @@
+// Transactional outbox for asset events.
+//
+// Changes to buildings, storeys and rooms in `db::crud` write an event to the `outbox` table in the same transaction,
+// so an event exists if and only if the change was committed. The `jobs::outbox` relay publishes the events by their
+// `seq` to a message broker and removes them afterwards. An event can be published twice if the relay dies in between,
+// consumers recognize duplicates by the event `id`.
+//
+// `seq` is taken when the event is written, not when its transaction commits, so events of different assets may be
+// published in another order than their changes committed. Events of the same asset keep their order: the change
+// locks the row of the asset before its event is written, so a later change of it waits for the commit of the earlier
+// one and gets the higher `seq`.
+//
+// The broker is picked with `OUTBOX_PUBLISHER`: `stdout`, `file`, `nats` or `amqp`, the last two need the cargo
+// features of the same name. Without a publisher no events are written at all.
+// Events follow the JSON schema in `schemas/asset-event.v1.json`.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::db::models::OutboxEntry;
+
+#[cfg(feature = "amqp")]
+mod amqp;
+#[cfg(feature = "nats")]
+mod nats;
+
+/// Identifier of the event schema, changes that break consumers need a new one.
+pub const SCHEMA: &str = "asset-event.v1";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    Created,
+    Updated,
+    Deleted
+}
+
+impl Change {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Change::Created => "created",
+            Change::Updated => "updated",
+            Change::Deleted => "deleted"
+        }
+    }
+
+    /// The action of the change as it is written to the audit log.
+    pub fn action(&self) -> &'static str {
+        match self {
+            Change::Created => "create",
+            Change::Updated => "update",
+            Change::Deleted => "delete"
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// Event as published to the broker, `data` is the asset after the change.
+pub struct OutboxEvent {
+    pub schema: &'static str,
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub occurred_at: DateTime<Utc>,
+    pub asset_type: String,
+    pub asset_id: Uuid,
+    pub data: Value
+}
+
+impl OutboxEvent {
+    pub fn new(asset_type: &str, change: Change, asset_id: Uuid, data: Value) -> OutboxEvent {
+        OutboxEvent {
+            schema: SCHEMA,
+            id: Uuid::new_v4(),
+            event_type: format!("{}.{}", asset_type, change.as_str()),
+            occurred_at: Utc::now(),
+            asset_type: asset_type.to_string(),
+            asset_id,
+            data
+        }
+    }
+}
+
+/// Something events can be published to. `publish` returns once the broker has taken the event,
+/// the relay only removes an event from the outbox after that.
+/// Brokers that detect duplicates get the event `id` as message ID.
+pub trait Publisher: Send {
+    fn publish(&mut self, id: Uuid, subject: &str, payload: &[u8]) -> Result<(), String>;
+}
+
+/// Writes every event as a line to stdout, for development.
+pub struct StdoutPublisher;
+
+impl Publisher for StdoutPublisher {
+    fn publish(&mut self, _id: Uuid, subject: &str, payload: &[u8]) -> Result<(), String> {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{} {}", subject, String::from_utf8_lossy(payload)).map_err(|e| e.to_string())
+    }
+}
+
+/// Appends every event as a line of JSON to a file.
+pub struct FilePublisher {
+    file: File
+}
+
+impl FilePublisher {
+    pub fn open(path: &str) -> Result<FilePublisher, String> {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| format!("cannot open outbox file {}: {}", path, e))?;
+        Ok(FilePublisher { file })
+    }
+}
+
+impl Publisher for FilePublisher {
+    fn publish(&mut self, _id: Uuid, _subject: &str, payload: &[u8]) -> Result<(), String> {
+        self.file.write_all(payload)
+            .and_then(|_| self.file.write_all(b"\n"))
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn env_or(name: &str, default: &str) -> String {
+    env::var(name).unwrap_or(default.to_string())
+}
+
+/// The publisher configured in `OUTBOX_PUBLISHER`, or None if the outbox is turned off.
+fn publisher_name() -> Option<&'static str> {
+    static NAME: OnceLock<Option<String>> = OnceLock::new();
+    NAME.get_or_init(|| env::var("OUTBOX_PUBLISHER").ok().filter(|name| !name.is_empty() && name != "none"))
+        .as_deref()
+}
+
+/// Check if changes should write events to the outbox.
+pub fn enabled() -> bool {
+    publisher_name().is_some()
+}
+
+/// Connect to the configured broker.
+pub fn publisher_from_env() -> Result<Box<dyn Publisher>, String> {
+    match publisher_name() {
+        Some("stdout") => Ok(Box::new(StdoutPublisher)),
+        Some("file") => Ok(Box::new(FilePublisher::open(&env_or("OUTBOX_FILE", "outbox.jsonl"))?)),
+        #[cfg(feature = "nats")]
+        Some("nats") => Ok(Box::new(nats::NatsPublisher::connect(&env_or("OUTBOX_NATS_URL", "nats://localhost:4222"))?)),
+        #[cfg(feature = "amqp")]
+        Some("amqp") => Ok(Box::new(amqp::AmqpPublisher::connect(
+            &env_or("OUTBOX_AMQP_URL", "amqp://localhost:5672/%2f"),
+            &env_or("OUTBOX_AMQP_EXCHANGE", "biletado")
+        )?)),
+        #[cfg(not(feature = "nats"))]
+        Some("nats") => Err("built without the `nats` feature".to_string()),
+        #[cfg(not(feature = "amqp"))]
+        Some("amqp") => Err("built without the `amqp` feature".to_string()),
+        Some(name) => Err(format!("unknown outbox publisher {}", name)),
+        None => Err("no outbox publisher configured".to_string())
+    }
+}
+
+/// Subject (or routing key) an event is published under, e.g. `biletado.assets.room.updated`.
+pub fn subject(event_type: &str) -> String {
+    format!("{}.{}", env_or("OUTBOX_SUBJECT_PREFIX", "biletado.assets"), event_type)
+}
+
+/// Publish the entries in order and stop at the first one that fails, so no event overtakes one of the same asset.
+/// Return the number of published entries and the error, if there was one.
+pub fn publish_in_order(publisher: &mut dyn Publisher, entries: &[OutboxEntry]) -> (usize, Option<String>) {
+    for (published, entry) in entries.iter().enumerate() {
+        if let Err(e) = publisher.publish(entry.id, &subject(&entry.event_type), entry.payload.to_string().as_bytes()) {
+            return (published, Some(format!("event {} from {}: {}", entry.id, entry.created_at, e)));
+        }
+    }
+    (entries.len(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Keeps published events in memory and fails once `fail_at` events have been published.
+    struct MemoryPublisher {
+        published: Vec<(String, Value)>,
+        fail_at: Option<usize>
+    }
+
+    impl Publisher for MemoryPublisher {
+        fn publish(&mut self, _id: Uuid, subject: &str, payload: &[u8]) -> Result<(), String> {
+            if self.fail_at == Some(self.published.len()) {
+                return Err("broker unavailable".to_string());
+            }
+            self.published.push((subject.to_string(), serde_json::from_slice(payload).map_err(|e| e.to_string())?));
+            Ok(())
+        }
+    }
+
+    fn entry(seq: i64, event: &OutboxEvent) -> OutboxEntry {
+        OutboxEntry { seq, id: event.id, event_type: event.event_type.clone(), payload: json!(event), created_at: event.occurred_at }
+    }
+
+    #[test]
+    fn test_event_schema() {
+        let schema : Value = serde_json::from_str(include_str!("../schemas/asset-event.v1.json")).unwrap();
+        assert_eq!(schema["$id"], SCHEMA);
+
+        let room_id = Uuid::new_v4();
+        let event = json!(OutboxEvent::new("room", Change::Deleted, room_id, json!({ "id": room_id, "name": "A.101" })));
+        for field in schema["required"].as_array().unwrap() {
+            assert!(event.get(field.as_str().unwrap()).is_some(), "missing {}", field);
+        }
+        assert_eq!(event["type"], "room.deleted");
+        assert!(schema["properties"]["type"]["enum"].as_array().unwrap().contains(&event["type"]));
+    }
+
+    #[test]
+    fn test_publish_in_order() {
+        let events : Vec<_> = (0..3).map(|_| OutboxEvent::new("storey", Change::Created, Uuid::new_v4(), json!({}))).collect();
+        let entries : Vec<_> = events.iter().enumerate().map(|(seq, event)| entry(seq as i64, event)).collect();
+
+        let mut publisher = MemoryPublisher { published: Vec::new(), fail_at: Some(2) };
+        let (published, error) = publish_in_order(&mut publisher, &entries);
+        assert_eq!(published, 2);
+        assert!(error.is_some());
+        assert_eq!(publisher.published[0].0, "biletado.assets.storey.created");
+        assert_eq!(publisher.published[1].1["id"], json!(events[1].id));
+
+        publisher.fail_at = None;
+        assert_eq!(publish_in_order(&mut publisher, &entries[published..]), (1, None));
+        assert_eq!(publisher.published[2].1["id"], json!(events[2].id));
+    }
+}
@@ -0,0 +1,54 @@
+use lapin::options::{BasicPublishOptions, ConfirmSelectOptions, ExchangeDeclareOptions};
+use lapin::types::FieldTable;
+use lapin::{BasicProperties, Channel, Connection, ConnectionProperties, ExchangeKind};
+use tokio::runtime::{Builder, Runtime};
+use uuid::Uuid;
+
+use crate::outbox::Publisher;
+
+/// Publishes events to a durable topic exchange, the subject is the routing key.
+/// Every event is persistent, carries its ID as `message_id` and only counts as published once the broker confirmed it.
+pub struct AmqpPublisher {
+    runtime: Runtime,
+    // the channel closes with the connection, so keep it around
+    _connection: Connection,
+    channel: Channel,
+    exchange: String
+}
+
+impl AmqpPublisher {
+    pub fn connect(url: &str, exchange: &str) -> Result<AmqpPublisher, String> {
+        let runtime = Builder::new_current_thread().enable_all().build().map_err(|e| e.to_string())?;
+        let (connection, channel) = runtime.block_on(async {
+            let connection = Connection::connect(url, ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel.confirm_select(ConfirmSelectOptions::default()).await?;
+            channel.exchange_declare(
+                exchange,
+                ExchangeKind::Topic,
+                ExchangeDeclareOptions { durable: true, ..ExchangeDeclareOptions::default() },
+                FieldTable::default()
+            ).await?;
+            Ok::<_, lapin::Error>((connection, channel))
+        }).map_err(|e| format!("cannot connect to AMQP broker at {}: {}", url, e))?;
+        Ok(AmqpPublisher { runtime, _connection: connection, channel, exchange: exchange.to_string() })
+    }
+}
+
+impl Publisher for AmqpPublisher {
+    fn publish(&mut self, id: Uuid, subject: &str, payload: &[u8]) -> Result<(), String> {
+        let properties = BasicProperties::default()
+            .with_message_id(id.to_string().into())
+            .with_content_type("application/json".into())
+            .with_delivery_mode(2);
+        let confirmation = self.runtime.block_on(async {
+            self.channel.basic_publish(&self.exchange, subject, BasicPublishOptions::default(), payload, properties)
+                .await?
+                .await
+        }).map_err(|e| e.to_string())?;
+        if confirmation.is_nack() {
+            return Err("broker rejected the event".to_string());
+        }
+        Ok(())
+    }
+}
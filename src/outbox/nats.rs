@@ -0,0 +1,32 @@
+use async_nats::jetstream::{self, context::Publish};
+use tokio::runtime::{Builder, Runtime};
+use uuid::Uuid;
+
+use crate::outbox::Publisher;
+
+/// Publishes events to NATS JetStream, the subject is the routing key of the event.
+/// A stream has to capture the subjects, the server acknowledges an event once the stream stored it
+/// and drops events whose ID it has already seen within the duplicate window of the stream.
+pub struct NatsPublisher {
+    runtime: Runtime,
+    jetstream: jetstream::Context
+}
+
+impl NatsPublisher {
+    pub fn connect(url: &str) -> Result<NatsPublisher, String> {
+        // the relay runs on its own thread, so it gets its own runtime instead of borrowing actix'
+        let runtime = Builder::new_current_thread().enable_all().build().map_err(|e| e.to_string())?;
+        let client = runtime.block_on(async_nats::connect(url))
+            .map_err(|e| format!("cannot connect to NATS at {}: {}", url, e))?;
+        Ok(NatsPublisher { runtime, jetstream: jetstream::new(client) })
+    }
+}
+
+impl Publisher for NatsPublisher {
+    fn publish(&mut self, id: Uuid, subject: &str, payload: &[u8]) -> Result<(), String> {
+        let message = Publish::build().payload(payload.to_vec().into()).message_id(id.to_string());
+        self.runtime.block_on(async {
+            self.jetstream.send_publish(subject.to_string(), message).await?.await
+        }).map(|_| ()).map_err(|e| e.to_string())
+    }
+}
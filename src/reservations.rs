@@ -0,0 +1,428 @@
+// Client for the reservations service of biletado.
+// Rooms with reservations must not be deleted, and the service is told about rooms that move to another building.
+//
+// The service is reached at `RESERVATIONS_HOST`:`RESERVATIONS_PORT`. Every request has a timeout and is retried
+// with exponential backoff; after repeated failures a circuit breaker stops calling the service for a while,
+// so an outage fails fast instead of tying up every request that needs it.
+//
+// Reservations of a room are asked for with the `room_id` filter of the reservations API. Older versions of the
+// service do not have it, `RESERVATIONS_ROOM_FILTER=false` makes the client fetch all reservations instead.
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use futures_util::{StreamExt, TryStreamExt};
+use futures_util::stream;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::env;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::db::models::Reservation;
+
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+const DEFAULT_RETRIES: u32 = 2;
+const DEFAULT_BREAKER_THRESHOLD: u32 = 5;
+const DEFAULT_BREAKER_COOLDOWN_SECS: u64 = 30;
+
+/// Rooms asked for at the same time when checking several rooms with the `room_id` filter.
+const CONCURRENT_ROOM_QUERIES: usize = 8;
+
+/// Delay before the first retry, doubled for every further one.
+const BACKOFF_BASE: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+/// Why the reservations service could not be asked.
+pub enum ReservationsError {
+    /// `RESERVATIONS_HOST` or `RESERVATIONS_PORT` is not set.
+    NotConfigured,
+    /// The circuit breaker is open after repeated failures.
+    CircuitOpen,
+    /// The request failed or timed out on every attempt.
+    Unavailable(String),
+    /// The service answered, but not with what was expected.
+    BadResponse(String)
+}
+
+impl fmt::Display for ReservationsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReservationsError::NotConfigured => write!(f, "RESERVATIONS_HOST and RESERVATIONS_PORT must be set"),
+            ReservationsError::CircuitOpen => write!(f, "circuit breaker is open"),
+            ReservationsError::Unavailable(e) => write!(f, "service unavailable: {}", e),
+            ReservationsError::BadResponse(e) => write!(f, "unexpected response: {}", e)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    /// The cooldown is over and a single trial request is under way.
+    HalfOpen
+}
+
+/// Circuit breaker that opens after `threshold` consecutive failures and lets a trial request through after `cooldown`.
+struct CircuitBreaker {
+    state: BreakerState,
+    threshold: u32,
+    cooldown: Duration
+}
+
+impl CircuitBreaker {
+    fn new(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker { state: BreakerState::Closed { failures: 0 }, threshold: threshold.max(1), cooldown }
+    }
+
+    /// Check if a request may be sent now.
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if now >= until => {
+                self.state = BreakerState::HalfOpen;
+                true
+            },
+            BreakerState::Open { .. } | BreakerState::HalfOpen => false
+        }
+    }
+
+    fn success(&mut self) {
+        if self.state != (BreakerState::Closed { failures: 0 }) {
+            info!("reservations service is reachable again, closing circuit breaker");
+        }
+        self.state = BreakerState::Closed { failures: 0 };
+    }
+
+    fn failure(&mut self, now: Instant) {
+        self.state = match self.state {
+            BreakerState::Closed { failures } if failures + 1 < self.threshold => BreakerState::Closed { failures: failures + 1 },
+            _ => {
+                error!("reservations service keeps failing, opening circuit breaker for {:?}", self.cooldown);
+                BreakerState::Open { until: now + self.cooldown }
+            }
+        };
+    }
+}
+
+/// Counts the trial request as failed if it never finishes, e.g. because the request it was made for
+/// was dropped when its client went away. Otherwise the breaker would wait for it forever in `HalfOpen`.
+struct TrialGuard<'a> {
+    breaker: Option<&'a Mutex<CircuitBreaker>>
+}
+
+impl TrialGuard<'_> {
+    /// The trial has finished and its outcome is recorded by the caller.
+    fn finish(mut self) {
+        self.breaker = None;
+    }
+}
+
+impl Drop for TrialGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(breaker) = self.breaker {
+            debug!("trial request to the reservations service was abandoned");
+            breaker.lock().unwrap().failure(Instant::now());
+        }
+    }
+}
+
+/// Delay before retry number `attempt` (starting at 1).
+fn backoff(attempt: u32) -> Duration {
+    BACKOFF_BASE * 2u32.saturating_pow(attempt.saturating_sub(1))
+}
+
+/// Read a time of the reservations API, either a full RFC 3339 time or a date.
+/// A date is taken as the start of that day, or as the end of it if `end_of_day` is set.
+fn parse_time(value: &str, end_of_day: bool) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let day = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    let day = if end_of_day { day + TimeDelta::days(1) } else { day };
+    Some(day.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+/// Read a time of a reservation, `None` if it is missing or unreadable.
+/// One odd reservation must not make the whole answer of the service unreadable.
+fn deserialize_time<'de, D: Deserializer<'de>>(deserializer: D, end_of_day: bool) -> Result<Option<DateTime<Utc>>, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    let time = value.as_str().and_then(|value| parse_time(value, end_of_day));
+    if time.is_none() {
+        warn!("unreadable reservation time {}, taking the reservation as open-ended", value);
+    }
+    Ok(time)
+}
+
+/// Deserialize the start of a reservation.
+pub fn deserialize_start<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    deserialize_time(deserializer, false)
+}
+
+/// Deserialize the end of a reservation.
+pub fn deserialize_end<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    deserialize_time(deserializer, true)
+}
+
+/// Check if a reservation has not ended yet.
+/// Reservations whose end cannot be read count as current,
+/// a room should rather not be deleted than lose a reservation.
+pub fn is_current(reservation: &Reservation, now: DateTime<Utc>) -> bool {
+    reservation.to.is_none_or(|to| to > now)
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+/// Client for the reservations service, shared by all requests so connections are reused.
+pub struct ReservationsClient {
+    http: reqwest::Client,
+    base_url: Option<String>,
+    room_filter: bool,
+    retries: u32,
+    breaker: Mutex<CircuitBreaker>
+}
+
+static CLIENT: OnceLock<ReservationsClient> = OnceLock::new();
+
+/// The reservations client, configured from the environment on first use.
+pub fn client() -> &'static ReservationsClient {
+    CLIENT.get_or_init(ReservationsClient::from_env)
+}
+
+impl ReservationsClient {
+    fn from_env() -> ReservationsClient {
+        let base_url = match (env::var("RESERVATIONS_HOST"), env::var("RESERVATIONS_PORT")) {
+            (Ok(host), Ok(port)) => Some(format!("http://{}:{}/api/reservations", host, port)),
+            _ => {
+                error!("RESERVATIONS_HOST or RESERVATIONS_PORT not set, reservations cannot be checked");
+                None
+            }
+        };
+        let timeout = Duration::from_millis(env_or("RESERVATIONS_TIMEOUT_MS", DEFAULT_TIMEOUT_MS));
+        let http = reqwest::Client::builder()
+            .timeout(timeout)
+            .connect_timeout(timeout)
+            .build()
+            .expect("failed to create reservations client");
+
+        ReservationsClient {
+            http,
+            base_url,
+            room_filter: env_or("RESERVATIONS_ROOM_FILTER", true),
+            retries: env_or("RESERVATIONS_RETRIES", DEFAULT_RETRIES),
+            breaker: Mutex::new(CircuitBreaker::new(
+                env_or("RESERVATIONS_BREAKER_THRESHOLD", DEFAULT_BREAKER_THRESHOLD),
+                Duration::from_secs(env_or("RESERVATIONS_BREAKER_COOLDOWN_SECS", DEFAULT_BREAKER_COOLDOWN_SECS))
+            ))
+        }
+    }
+
+    /// Return the subset of the given rooms that have current or future reservations.
+    pub async fn rooms_with_reservations(&self, room_ids: &[Uuid], trace_id: Option<&str>) -> Result<Vec<Uuid>, ReservationsError> {
+        let now = Utc::now();
+        let reservations = self.reservations_of_rooms(room_ids, trace_id).await?;
+        Ok(room_ids.iter()
+            .filter(|id| reservations.iter().any(|res| res.room_id == **id && is_current(res, now)))
+            .copied()
+            .collect())
+    }
+
+    /// Fetch the reservations of the given rooms, room by room or all at once if the room filter is turned off.
+    pub async fn reservations_of_rooms(&self, room_ids: &[Uuid], trace_id: Option<&str>) -> Result<Vec<Reservation>, ReservationsError> {
+        if !self.room_filter {
+            let mut reservations = self.reservations(trace_id).await?;
+            reservations.retain(|res| room_ids.contains(&res.room_id));
+            return Ok(reservations);
+        }
+
+        let per_room : Vec<Vec<Reservation>> = stream::iter(room_ids)
+            .map(|id| self.room_reservations(*id, trace_id))
+            .buffered(CONCURRENT_ROOM_QUERIES)
+            .try_collect()
+            .await?;
+        Ok(per_room.into_iter().flatten().collect())
+    }
+
+    /// Fetch the reservations of a room.
+    pub async fn room_reservations(&self, room_id: Uuid, trace_id: Option<&str>) -> Result<Vec<Reservation>, ReservationsError> {
+        let url = format!("{}/", self.base_url()?);
+        let room = room_id.to_string();
+        let resp = self.send(|http| http.get(&url).query(&[("room_id", &room)]), trace_id).await?;
+        let reservations : Vec<Reservation> = resp.json().await.map_err(|e| ReservationsError::BadResponse(e.to_string()))?;
+        debug!("received {} reservations of room {} from backend", reservations.len(), room_id);
+        // a service without the filter answers with all reservations
+        Ok(reservations.into_iter().filter(|res| res.room_id == room_id).collect())
+    }
+
+    /// Fetch all reservations.
+    pub async fn reservations(&self, trace_id: Option<&str>) -> Result<Vec<Reservation>, ReservationsError> {
+        let url = format!("{}/", self.base_url()?);
+        let resp = self.send(|http| http.get(&url), trace_id).await?;
+        let reservations : Vec<Reservation> = resp.json().await.map_err(|e| ReservationsError::BadResponse(e.to_string()))?;
+        debug!("received {} reservations from backend", reservations.len());
+        Ok(reservations)
+    }
+
+    /// Fetch the reservations that may overlap the time from `from` to `to`, of all rooms in a single request.
+    /// The service is asked with the `from` and `to` filter, a service without it answers with all reservations,
+    /// so the caller still has to check for overlaps.
+    pub async fn reservations_between(&self, from: DateTime<Utc>, to: DateTime<Utc>, trace_id: Option<&str>) -> Result<Vec<Reservation>, ReservationsError> {
+        let url = format!("{}/", self.base_url()?);
+        let (from, to) = (from.to_rfc3339(), to.to_rfc3339());
+        let resp = self.send(|http| http.get(&url).query(&[("from", &from), ("to", &to)]), trace_id).await?;
+        let reservations : Vec<Reservation> = resp.json().await.map_err(|e| ReservationsError::BadResponse(e.to_string()))?;
+        debug!("received {} reservations from {} to {} from backend", reservations.len(), from, to);
+        Ok(reservations)
+    }
+
+    /// Cancel a reservation.
+    pub async fn cancel_reservation(&self, reservation_id: Uuid, trace_id: Option<&str>) -> Result<(), ReservationsError> {
+        let url = format!("{}/{}", self.base_url()?, reservation_id);
+        self.send(|http| http.delete(&url), trace_id).await.map(|_| ())
+    }
+
+    /// Tell the service about rooms that are in another building now.
+    pub async fn notify_room_moves<T: Serialize>(&self, moves: &[T], trace_id: Option<&str>) -> Result<(), ReservationsError> {
+        let url = format!("{}/room-moves", self.base_url()?);
+        self.send(|http| http.post(&url).json(moves), trace_id).await.map(|_| ())
+    }
+
+    fn base_url(&self) -> Result<&str, ReservationsError> {
+        self.base_url.as_deref().ok_or(ReservationsError::NotConfigured)
+    }
+
+    /// Send a request with retries, unless the circuit breaker is open.
+    /// Connection errors, timeouts and server errors are retried, other error responses are not.
+    async fn send<F>(&self, request: F, trace_id: Option<&str>) -> Result<reqwest::Response, ReservationsError>
+    where F: Fn(&reqwest::Client) -> reqwest::RequestBuilder {
+        let trial = {
+            let mut breaker = self.breaker.lock().unwrap();
+            if !breaker.allow(Instant::now()) {
+                return Err(ReservationsError::CircuitOpen);
+            }
+            breaker.state == BreakerState::HalfOpen
+        };
+        let guard = TrialGuard { breaker: trial.then_some(&self.breaker) };
+
+        let jaeger_key = env::var("JAEGER_HEADER").unwrap_or("Uber-Trace-Id".to_string());
+        let mut attempt = 0;
+        let result = loop {
+            let mut builder = request(&self.http);
+            if let Some(trace_id) = trace_id {
+                builder = builder.header(&jaeger_key, trace_id);
+            }
+            let result = match builder.send().await {
+                Ok(resp) if resp.status().is_server_error() => Err(ReservationsError::Unavailable(format!("status {}", resp.status()))),
+                Ok(resp) if !resp.status().is_success() => break Err(ReservationsError::BadResponse(format!("status {}", resp.status()))),
+                Ok(resp) => break Ok(resp),
+                Err(e) => Err(ReservationsError::Unavailable(e.to_string()))
+            };
+            if attempt >= self.retries {
+                break result;
+            }
+            attempt += 1;
+            debug!("reservations request failed, retry {} of {}", attempt, self.retries);
+            tokio::time::sleep(backoff(attempt)).await;
+        };
+
+        guard.finish();
+        let mut breaker = self.breaker.lock().unwrap();
+        match result {
+            Err(ReservationsError::Unavailable(_)) => breaker.failure(Instant::now()),
+            _ => breaker.success()
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(30));
+        breaker.failure(start);
+        breaker.failure(start);
+        assert!(breaker.allow(start));
+
+        breaker.failure(start);
+        assert!(!breaker.allow(start));
+        assert!(!breaker.allow(start + Duration::from_secs(29)));
+
+        // one trial request after the cooldown, nothing else until it is done
+        assert!(breaker.allow(start + Duration::from_secs(30)));
+        assert!(!breaker.allow(start + Duration::from_secs(30)));
+        breaker.failure(start + Duration::from_secs(31));
+        assert!(!breaker.allow(start + Duration::from_secs(32)));
+
+        assert!(breaker.allow(start + Duration::from_secs(61)));
+        breaker.success();
+        assert!(breaker.allow(start + Duration::from_secs(61)));
+        assert_eq!(breaker.state, BreakerState::Closed { failures: 0 });
+    }
+
+    #[test]
+    fn test_abandoned_trial() {
+        let start = Instant::now();
+        let breaker = Mutex::new(CircuitBreaker::new(1, Duration::from_secs(30)));
+        breaker.lock().unwrap().failure(start);
+        assert!(breaker.lock().unwrap().allow(start + Duration::from_secs(30)));
+
+        // the trial is dropped before it finishes, the breaker opens again instead of staying half open
+        drop(TrialGuard { breaker: Some(&breaker) });
+        assert!(matches!(breaker.lock().unwrap().state, BreakerState::Open { .. }));
+
+        // a finished trial records nothing by itself
+        let breaker = Mutex::new(CircuitBreaker::new(1, Duration::from_secs(30)));
+        TrialGuard { breaker: Some(&breaker) }.finish();
+        assert_eq!(breaker.lock().unwrap().state, BreakerState::Closed { failures: 0 });
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let day = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap();
+        assert_eq!(parse_time("2026-03-10", false), Some(day.and_hms_opt(0, 0, 0).unwrap().and_utc()));
+        assert_eq!(parse_time("2026-03-10", true), Some(day.and_hms_opt(0, 0, 0).unwrap().and_utc() + TimeDelta::days(1)));
+        assert_eq!(parse_time("2026-03-10T14:30:00+01:00", true), Some(day.and_hms_opt(13, 30, 0).unwrap().and_utc()));
+        assert_eq!(parse_time("next week", false), None);
+
+        let reservation : Reservation = serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(), "room_id": Uuid::new_v4(), "from": "2026-03-10", "to": "2026-03-10"
+        })).unwrap();
+        let noon = day.and_hms_opt(12, 0, 0).unwrap().and_utc();
+        assert!(reservation.overlaps(noon, noon + TimeDelta::hours(1)));
+        assert!(!reservation.overlaps(noon + TimeDelta::days(1), noon + TimeDelta::days(2)));
+
+        // an unreadable time does not fail the answer, the reservation blocks the room instead
+        let reservations : Vec<Reservation> = serde_json::from_value(serde_json::json!([
+            { "id": Uuid::new_v4(), "room_id": Uuid::new_v4(), "from": "2026-03-10", "to": "next week" },
+            { "id": Uuid::new_v4(), "room_id": Uuid::new_v4(), "from": null, "to": "2026-03-10" }
+        ])).unwrap();
+        assert!(reservations[0].overlaps(noon + TimeDelta::days(7), noon + TimeDelta::days(8)));
+        assert!(reservations[1].overlaps(noon - TimeDelta::days(7), noon - TimeDelta::days(6)));
+    }
+
+    #[test]
+    fn test_is_current() {
+        let now = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap().and_hms_opt(12, 0, 0).unwrap().and_utc();
+        let reservation = |to: &str| -> Reservation { serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(), "room_id": Uuid::new_v4(), "from": "2026-03-01", "to": to
+        })).unwrap() };
+        assert!(is_current(&reservation("2026-03-10"), now));
+        assert!(is_current(&reservation("2026-04-01T12:00:00Z"), now));
+        assert!(!is_current(&reservation("2026-03-09"), now));
+        assert!(is_current(&reservation("next week"), now));
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_millis(100));
+        assert_eq!(backoff(2), Duration::from_millis(200));
+        assert_eq!(backoff(4), Duration::from_millis(800));
+    }
+}
@@ -0,0 +1,222 @@
+// Webhook events for other services that react to asset changes.
+//
+// Every change that is written to the audit log becomes an event of type `<entity type>.<action>`, e.g. `room.updated`
+// or `storey.moved`, named like the events of the outbox and the event stream.
+// Subscriptions pick the events they want with patterns like `room.*`, `*.deleted` or `*`.
+// Events are delivered by the `jobs::webhooks` thread, signed with the secret of the subscription:
+//
+//   X-Biletado-Signature: sha256=<hex HMAC-SHA256 of the request body>
+//
+// Webhooks are only sent to public addresses, so a subscription cannot reach into the network of the service.
+// Hosts in `WEBHOOK_ALLOWED_HOSTS` (comma separated) may be on any address, e.g. other services of the deployment.
+
+use chrono::{DateTime, Duration, Utc};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::Serialize;
+use std::env;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use uuid::Uuid;
+
+use crate::db::models::AuditEntry;
+
+pub const EVENT_HEADER: &str = "X-Biletado-Event";
+pub const DELIVERY_HEADER: &str = "X-Biletado-Delivery";
+pub const SIGNATURE_HEADER: &str = "X-Biletado-Signature";
+
+/// Delay before the first retry of a failed delivery, doubled for every further one.
+const RETRY_BASE_SECS: i64 = 10;
+/// Longest delay between two attempts.
+const RETRY_MAX_SECS: i64 = 3600;
+
+#[derive(Debug, Serialize)]
+/// Payload of a webhook delivery, the audit entry of the change without the actor.
+pub struct WebhookEvent {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub occurred_at: DateTime<Utc>,
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub trace_id: Option<String>
+}
+
+impl WebhookEvent {
+    pub fn from_audit(entry: &AuditEntry) -> WebhookEvent {
+        WebhookEvent {
+            id: entry.id,
+            event_type: format!("{}.{}", entry.entity_type, past_tense(&entry.action)),
+            occurred_at: entry.occurred_at,
+            entity_type: entry.entity_type.clone(),
+            entity_id: entry.entity_id,
+            before: entry.before.clone(),
+            after: entry.after.clone(),
+            trace_id: entry.trace_id.clone()
+        }
+    }
+}
+
+/// The action of an audit entry as it is named in events, e.g. `update` becomes `updated`.
+fn past_tense(action: &str) -> String {
+    if action.ends_with('e') {
+        format!("{}d", action)
+    } else {
+        format!("{}ed", action)
+    }
+}
+
+/// Check an event pattern: `*`, or an entity type and an action separated by a dot, either of which may be `*`.
+pub fn valid_pattern(pattern: &str) -> bool {
+    let part = |part: &str| part == "*" || (!part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c == '_'));
+    pattern == "*" || pattern.split_once('.').is_some_and(|(entity, action)| part(entity) && part(action))
+}
+
+/// Check if an event type is matched by any of the patterns.
+pub fn event_matches(patterns: &[String], event_type: &str) -> bool {
+    let (entity, action) = event_type.split_once('.').unwrap_or((event_type, ""));
+    patterns.iter().any(|pattern| {
+        pattern == "*" || pattern.split_once('.').is_some_and(|(p_entity, p_action)| {
+            (p_entity == "*" || p_entity == entity) && (p_action == "*" || p_action == action)
+        })
+    })
+}
+
+/// Hex encoded HMAC-SHA256 of the body, keyed with the secret of the subscription.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let key = PKey::hmac(secret.as_bytes()).expect("failed to create HMAC key");
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).expect("failed to create HMAC signer");
+    signer.update(body).expect("failed to compute HMAC");
+    hex(&signer.sign_to_vec().expect("failed to compute HMAC"))
+}
+
+/// Random secret for subscriptions that do not bring their own.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    openssl::rand::rand_bytes(&mut bytes).expect("failed to generate webhook secret");
+    hex(&bytes)
+}
+
+/// Delay before the next attempt after `attempts` failed ones.
+pub fn retry_delay(attempts: i32) -> Duration {
+    let factor = 2i64.saturating_pow(attempts.saturating_sub(1).clamp(0, 30) as u32);
+    Duration::seconds(RETRY_BASE_SECS.saturating_mul(factor).min(RETRY_MAX_SECS))
+}
+
+/// Hosts webhooks may be sent to whatever their address, from `WEBHOOK_ALLOWED_HOSTS`.
+pub fn allowed_hosts() -> Vec<String> {
+    env::var("WEBHOOK_ALLOWED_HOSTS").unwrap_or_default()
+        .split(',')
+        .map(|host| host.trim().to_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+/// Check that webhooks may be sent to the URL: http or https, and a host that is allowed
+/// or only has public addresses. Err is why not.
+/// Ok has the host and the checked address to connect to if the host had to be looked up,
+/// the delivery must be sent there as the host may resolve to a different address on the next lookup.
+pub fn check_target(url: &str, allowed_hosts: &[String]) -> Result<Option<(String, SocketAddr)>, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("invalid URL: {}", e))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err("only http and https URLs are allowed".to_string());
+    }
+    let host = url.host_str().ok_or("URL without host")?.to_lowercase();
+    if allowed_hosts.contains(&host) {
+        return Ok(None);
+    }
+
+    if let Ok(address) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        return match is_public(&address) {
+            true => Ok(None),
+            false => Err(format!("{} is not a public address", address))
+        };
+    }
+    let addresses : Vec<SocketAddr> = (host.as_str(), url.port_or_known_default().unwrap_or(80)).to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+        .collect();
+    if let Some(address) = addresses.iter().find(|address| !is_public(&address.ip())) {
+        return Err(format!("{} is not a public address", address.ip()));
+    }
+    match addresses.first() {
+        Some(address) => Ok(Some((host, *address))),
+        None => Err(format!("cannot resolve {}", host))
+    }
+}
+
+/// Check if an address can be reached from the internet,
+/// not e.g. a loopback, private or link-local address (which includes the cloud metadata services).
+fn is_public(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(v4) => {
+            let [first, second, ..] = v4.octets();
+            let shared = first == 100 && (64..128).contains(&second);
+            !(v4.is_private() || v4.is_loopback() || v4.is_link_local() || v4.is_unspecified() || v4.is_broadcast()
+                || v4.is_documentation() || v4.is_multicast() || shared || first == 0)
+        },
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public(&IpAddr::V4(v4)),
+            None => !(v6.is_loopback() || v6.is_unspecified() || v6.is_multicast() || v6.is_unique_local() || v6.is_unicast_link_local())
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_matches() {
+        let patterns = vec!["room.*".to_string(), "*.deleted".to_string()];
+        assert!(event_matches(&patterns, "room.updated"));
+        assert!(event_matches(&patterns, "building.deleted"));
+        assert!(!event_matches(&patterns, "storey.moved"));
+        assert!(event_matches(&["*".to_string()], "storey.moved"));
+        assert!(!event_matches(&[], "storey.moved"));
+
+        assert!(valid_pattern("room_equipment.updated"));
+        assert!(valid_pattern("*.moved"));
+        assert!(!valid_pattern("room"));
+        assert!(!valid_pattern("room."));
+        assert!(!valid_pattern("Room.updated"));
+
+        assert_eq!(past_tense("update"), "updated");
+        assert_eq!(past_tense("move"), "moved");
+    }
+
+    #[test]
+    fn test_check_target() {
+        assert!(check_target("https://203.0.113.1/hook", &[]).is_err());
+        assert_eq!(check_target("https://8.8.8.8/hook", &[]), Ok(None));
+        assert!(check_target("http://127.0.0.1:8080/hook", &[]).is_err());
+        assert!(check_target("http://169.254.169.254/latest/meta-data", &[]).is_err());
+        assert!(check_target("http://10.1.2.3/hook", &[]).is_err());
+        assert!(check_target("http://[::1]/hook", &[]).is_err());
+        assert!(check_target("http://[::ffff:192.168.0.1]/hook", &[]).is_err());
+        assert!(check_target("http://[fd00::1]/hook", &[]).is_err());
+        assert!(check_target("ftp://8.8.8.8/hook", &[]).is_err());
+        assert!(check_target("http://localhost/hook", &["localhost".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        assert_eq!(generate_secret().len(), 64);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), Duration::seconds(10));
+        assert_eq!(retry_delay(3), Duration::seconds(40));
+        assert_eq!(retry_delay(20), Duration::seconds(3600));
+    }
+}